@@ -0,0 +1,522 @@
+//! Assembler for the syntax in `assembly_grammar.txt`, which is also what the
+//! disassembler prints, so listings assemble back to the same bytecode.
+
+use pegatexto_vm::bytecode::{Metadata, OwnedBytecode};
+use pegatexto_vm::bytecode::address::Address;
+use pegatexto_vm::bytecode::builder::{BuildError, Builder};
+use pegatexto_vm::bytecode::charset::Charset;
+use pegatexto_vm::bytecode::class_set::{ClassItem, ClassSet};
+use pegatexto_vm::bytecode::instruction::Instruction;
+use pegatexto_vm::bytecode::opcode::Opcode;
+use pegatexto_vm::bytecode::symbols::SymbolTable;
+use pegatexto_vm::grammar::character_class::CharacterClass;
+use pegatexto_vm::grammar::unicode::UnicodeClass;
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt;
+
+#[derive(Debug, PartialEq)]
+pub enum AssembleErrorKind {
+    UnknownOpcode(String),
+    InvalidOperand,
+    /// Input after a complete instruction, label or rule
+    TrailingInput,
+    UndefinedLabel(String),
+    DuplicateLabel(String),
+    Build(BuildError),
+}
+
+#[derive(Debug, PartialEq)]
+pub struct AssembleError {
+    pub kind: AssembleErrorKind,
+    pub line: usize,
+}
+
+impl fmt::Display for AssembleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.kind {
+            AssembleErrorKind::UnknownOpcode(name) => write!(f, "unknown opcode {:?}", name),
+            AssembleErrorKind::InvalidOperand => write!(f, "invalid or missing operand"),
+            AssembleErrorKind::TrailingInput => write!(f, "unexpected input after instruction"),
+            AssembleErrorKind::UndefinedLabel(name) => write!(f, "undefined label {}", name),
+            AssembleErrorKind::DuplicateLabel(name) => write!(f, "{} is already defined", name),
+            AssembleErrorKind::Build(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+enum Target {
+    Address(Address),
+    Label(String),
+    /// Rule, written `.Name`
+    Rule(String),
+}
+
+enum Operands {
+    None,
+    Byte(u8),
+    Target(Target),
+    Char(char),
+    Class(CharacterClass),
+    Str(String),
+    Range(u8, u8),
+    TestSet(Target, String),
+    Charset(Charset),
+    CharRange(char, char),
+    UnicodeClass(UnicodeClass),
+    ClassSet(Vec<u8>),
+}
+
+impl Operands {
+    fn target(&self) -> Option<&Target> {
+        match self {
+            Operands::Target(target) | Operands::TestSet(target, _) => Some(target),
+            _ => None,
+        }
+    }
+
+    fn instruction(&self, opcode: Opcode, target: Address) -> Instruction<'_> {
+        use Opcode as O;
+        match (opcode, self) {
+            (O::Any, _) => Instruction::Any,
+            (O::Succeed, _) => Instruction::Succeed,
+            (O::Fail, _) => Instruction::Fail,
+            (O::FailIfLessThan, Operands::Byte(n)) => Instruction::FailIfLessThan(*n),
+            (O::ToggleSuccess, _) => Instruction::ToggleSuccess,
+            (O::QuantifierInit, _) => Instruction::QuantifierInit,
+            (O::QuantifierNext, _) => Instruction::QuantifierNext,
+            (O::Jump, _) => Instruction::Jump(target),
+            (O::JumpIfFail, _) => Instruction::JumpIfFail(target),
+            (O::JumpIfSuccess, _) => Instruction::JumpIfSuccess(target),
+            (O::Call, _) => Instruction::Call(target),
+            (O::Return, _) => Instruction::Return,
+            (O::Push, _) => Instruction::Push,
+            (O::Peek, _) => Instruction::Peek,
+            (O::Pop, _) => Instruction::Pop,
+            (O::Byte, Operands::Byte(b)) => Instruction::Byte(*b),
+            (O::Char, Operands::Char(c)) => Instruction::Char(*c),
+            (O::Class, Operands::Class(class)) => Instruction::Class(*class),
+            (O::Literal, Operands::Str(s)) => Instruction::Literal(s),
+            (O::Set, Operands::Str(s)) => Instruction::Set(s),
+            (O::NotSet, Operands::Str(s)) => Instruction::NotSet(s),
+            (O::Range, Operands::Range(min, max)) => Instruction::Range(*min, *max),
+            (O::Capture, Operands::Byte(id)) => Instruction::Capture(*id),
+            (O::Halt, _) => Instruction::Halt(None),
+            (O::TestSet, Operands::TestSet(_, s)) => Instruction::TestSet(target, s),
+            (O::Charset, Operands::Charset(charset)) => Instruction::Charset(*charset),
+            (O::NotCharset, Operands::Charset(charset)) => Instruction::NotCharset(*charset),
+            (O::CharRange, Operands::CharRange(min, max)) => Instruction::CharRange(*min, *max),
+            (O::UnicodeClass, Operands::UnicodeClass(class)) => Instruction::UnicodeClass(*class),
+            (O::ClassSet, Operands::ClassSet(bytes)) => {
+                Instruction::ClassSet(ClassSet::parse(bytes).expect("class set encoded by the assembler").0)
+            },
+            (O::LiteralNoCase, Operands::Str(s)) => Instruction::LiteralNoCase(s),
+            (O::SetNoCase, Operands::Str(s)) => Instruction::SetNoCase(s),
+            (O::Behind, Operands::Byte(n)) => Instruction::Behind(*n),
+            (O::EndOfInput, _) => Instruction::EndOfInput,
+            (O::FailIfMoved, _) => Instruction::FailIfMoved,
+            _ => unreachable!("operands parsed for opcode {}", opcode),
+        }
+    }
+}
+
+enum Item {
+    Rule(String),
+    Label(String),
+    Instruction(Opcode, Operands),
+}
+
+struct Cursor<'a> {
+    text: &'a str,
+}
+
+impl<'a> Cursor<'a> {
+    fn skip_space(&mut self) {
+        self.text = self.text.trim_start();
+    }
+
+    fn at_end(&mut self) -> bool {
+        self.skip_space();
+        self.text.is_empty() || self.text.starts_with(';')
+    }
+
+    fn eat(&mut self, s: &str) -> bool {
+        match self.text.strip_prefix(s) {
+            Some(rest) => {
+                self.text = rest;
+                true
+            },
+            None => false,
+        }
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.text.chars().next()?;
+        self.text = &self.text[c.len_utf8()..];
+        Some(c)
+    }
+
+    fn identifier(&mut self) -> Option<&'a str> {
+        let len = self.text.find(|c: char| !c.is_alphanumeric() && c != '_').unwrap_or(self.text.len());
+        let (identifier, rest) = self.text.split_at(len);
+        self.text = rest;
+        if identifier.is_empty() { None } else { Some(identifier) }
+    }
+
+    fn integer<T: std::str::FromStr>(&mut self) -> Option<T> {
+        self.skip_space();
+        let len = self.text.find(|c: char| !c.is_ascii_digit()).unwrap_or(self.text.len());
+        let (digits, rest) = self.text.split_at(len);
+        self.text = rest;
+        digits.parse().ok()
+    }
+
+    fn target(&mut self) -> Option<Target> {
+        self.skip_space();
+        if self.text.starts_with(|c: char| c.is_ascii_digit()) {
+            self.integer().map(|address| Target::Address(Address::new(address)))
+        }
+        else if self.eat(".") {
+            self.identifier().map(|name| Target::Rule(name.to_string()))
+        }
+        else {
+            self.identifier().map(|name| Target::Label(name.to_string()))
+        }
+    }
+
+    // Escapes as printed by `{:?}`, after the backslash
+    fn escape(&mut self) -> Option<char> {
+        match self.bump()? {
+            'n' => Some('\n'),
+            'r' => Some('\r'),
+            't' => Some('\t'),
+            '0' => Some('\0'),
+            'u' if self.eat("{") => {
+                let len = self.text.find('}')?;
+                let c = u32::from_str_radix(&self.text[..len], 16).ok().and_then(char::from_u32);
+                self.text = &self.text[len + 1..];
+                c
+            },
+            c @ '\\' | c @ '\'' | c @ '"' => Some(c),
+            _ => None,
+        }
+    }
+
+    fn quoted(&mut self, quote: char) -> Option<String> {
+        self.skip_space();
+        if self.bump()? != quote {
+            return None;
+        }
+        let mut s = String::new();
+        loop {
+            match self.bump()? {
+                '\\' => s.push(self.escape()?),
+                c if c == quote => return Some(s),
+                c => s.push(c),
+            }
+        }
+    }
+
+    fn char_literal(&mut self) -> Option<char> {
+        let s = self.quoted('\'')?;
+        let mut chars = s.chars();
+        match (chars.next(), chars.next()) {
+            (Some(c), None) => Some(c),
+            _ => None,
+        }
+    }
+
+    fn class(&mut self) -> Option<CharacterClass> {
+        self.skip_space();
+        if !self.eat("\\") {
+            return None;
+        }
+        let class = u8::try_from(self.bump()?).ok().and_then(|b| CharacterClass::try_from(b).ok())?;
+        Some(class)
+    }
+
+    fn unicode_class(&mut self) -> Option<UnicodeClass> {
+        self.skip_space();
+        if !self.eat("\\p{") {
+            return None;
+        }
+        let len = self.text.find('}')?;
+        let class = UnicodeClass::from_name(&self.text[..len]);
+        self.text = &self.text[len + 1..];
+        class
+    }
+
+    // `[min-max]`, with both chars escaped as printed by `escape_debug`
+    fn char_pair(&mut self) -> Option<(char, char)> {
+        let pair_char = |cursor: &mut Cursor| match cursor.bump()? {
+            '\\' => cursor.escape(),
+            c => Some(c),
+        };
+        self.skip_space();
+        if !self.eat("[") {
+            return None;
+        }
+        let min = pair_char(self)?;
+        if !self.eat("-") {
+            return None;
+        }
+        let max = pair_char(self)?;
+        if self.eat("]") && min <= max { Some((min, max)) } else { None }
+    }
+
+    // Charset as printed by its `Display`
+    fn charset(&mut self) -> Option<Charset> {
+        self.skip_space();
+        if !self.eat("[") {
+            return None;
+        }
+        let mut charset = Charset::new();
+        let byte = |cursor: &mut Cursor| -> Option<u8> {
+            match cursor.bump()? {
+                '\\' if cursor.eat("x") => {
+                    let hex = cursor.text.get(..2)?;
+                    cursor.text = &cursor.text[2..];
+                    u8::from_str_radix(hex, 16).ok()
+                },
+                '\\' => u8::try_from(cursor.bump()?).ok(),
+                c => u8::try_from(c).ok(),
+            }
+        };
+        while !self.eat("]") {
+            let min = byte(self)?;
+            let max = if self.eat("-") { byte(self)? } else { min };
+            if max < min {
+                return None;
+            }
+            charset.insert_range(min, max);
+        }
+        Some(charset)
+    }
+
+    fn class_set(&mut self) -> Option<Vec<u8>> {
+        let charset = self.charset()?;
+        let mut items = Vec::new();
+        while !self.at_end() {
+            let item = if self.text.starts_with('[') {
+                let (min, max) = self.char_pair()?;
+                ClassItem::Range(min, max)
+            }
+            else if self.text.starts_with("\\p{") {
+                ClassItem::UnicodeClass(self.unicode_class()?)
+            }
+            else {
+                ClassItem::Class(self.class()?)
+            };
+            items.push(item);
+        }
+        if items.len() > u8::MAX as usize {
+            return None;
+        }
+        Some(ClassSet::encode(&charset, &items))
+    }
+
+    fn operands(&mut self, opcode: Opcode) -> Option<Operands> {
+        use Opcode as O;
+        let operands = match opcode {
+            O::FailIfLessThan | O::Capture | O::Behind | O::Byte => Operands::Byte(self.integer()?),
+            O::Jump | O::JumpIfFail | O::JumpIfSuccess | O::Call => Operands::Target(self.target()?),
+            O::Char => Operands::Char(self.char_literal()?),
+            O::Class => Operands::Class(self.class()?),
+            O::Literal | O::Set | O::NotSet | O::LiteralNoCase | O::SetNoCase => Operands::Str(self.quoted('"')?),
+            O::Range => {
+                let (min, max) = self.char_pair()?;
+                Operands::Range(u8::try_from(min).ok()?, u8::try_from(max).ok()?)
+            },
+            O::TestSet => Operands::TestSet(self.target()?, self.quoted('"')?),
+            O::Charset | O::NotCharset => Operands::Charset(self.charset()?),
+            O::CharRange => {
+                let (min, max) = self.char_pair()?;
+                Operands::CharRange(min, max)
+            },
+            O::UnicodeClass => Operands::UnicodeClass(self.unicode_class()?),
+            O::ClassSet => Operands::ClassSet(self.class_set()?),
+            _ => Operands::None,
+        };
+        Some(operands)
+    }
+}
+
+// Disassembler listings prefix lines with the address and a bar
+fn strip_listing_prefix(line: &str) -> &str {
+    let rest = line.trim_start().trim_start_matches(|c: char| c.is_ascii_digit()).trim_start();
+    rest.strip_prefix('|').unwrap_or(line)
+}
+
+fn parse_line(line: &str) -> Result<Option<Item>, AssembleErrorKind> {
+    let mut cursor = Cursor { text: strip_listing_prefix(line) };
+    if cursor.at_end() {
+        return Ok(None);
+    }
+    let item = if cursor.eat(".") {
+        Item::Rule(cursor.identifier().ok_or(AssembleErrorKind::InvalidOperand)?.to_string())
+    }
+    else {
+        let name = cursor.identifier().ok_or_else(|| AssembleErrorKind::UnknownOpcode(cursor.text.to_string()))?;
+        if cursor.eat(":") {
+            Item::Label(name.to_string())
+        }
+        else {
+            let opcode = Opcode::from_assembly(name).ok_or_else(|| AssembleErrorKind::UnknownOpcode(name.to_string()))?;
+            Item::Instruction(opcode, cursor.operands(opcode).ok_or(AssembleErrorKind::InvalidOperand)?)
+        }
+    };
+    if cursor.at_end() { Ok(Some(item)) } else { Err(AssembleErrorKind::TrailingInput) }
+}
+
+/// Assembles `source`, with its rules as symbols
+pub fn assemble(source: &str) -> Result<OwnedBytecode, AssembleError> {
+    let mut items = Vec::new();
+    for (index, text) in source.lines().enumerate() {
+        let line = index + 1;
+        if let Some(item) = parse_line(text).map_err(|kind| AssembleError { kind, line })? {
+            items.push((line, item));
+        }
+    }
+
+    let mut labels = HashMap::new();
+    let mut rules = HashMap::new();
+    let mut symbols = SymbolTable::new();
+    let mut address = 0;
+    for (line, item) in items.iter() {
+        let (names, name) = match item {
+            Item::Rule(name) => (&mut rules, name),
+            Item::Label(name) => (&mut labels, name),
+            Item::Instruction(opcode, operands) => {
+                let instruction = operands.instruction(*opcode, Address::zero());
+                address += Builder::with_instructions(&[instruction]).build().len();
+                continue;
+            },
+        };
+        let address = u32::try_from(address)
+            .map_err(|_| AssembleError { kind: AssembleErrorKind::Build(BuildError::AddressOverflow(address)), line: *line })?;
+        if names.insert(name.as_str(), Address::new(address)).is_some() {
+            return Err(AssembleError { kind: AssembleErrorKind::DuplicateLabel(name.to_string()), line: *line });
+        }
+        if let Item::Rule(name) = item {
+            symbols.insert(Address::new(address), name);
+        }
+    }
+
+    let mut builder = Builder::new();
+    for (line, item) in items.iter() {
+        if let Item::Instruction(opcode, operands) = item {
+            let target = match operands.target() {
+                Some(Target::Address(address)) => Some(*address),
+                Some(Target::Label(name)) => labels.get(name.as_str()).copied(),
+                Some(Target::Rule(name)) => rules.get(name.as_str()).copied(),
+                None => Some(Address::zero()),
+            };
+            let target = target.ok_or_else(|| {
+                let name = match operands.target() {
+                    Some(Target::Rule(name)) => format!(".{}", name),
+                    Some(Target::Label(name)) => name.to_string(),
+                    _ => String::new(),
+                };
+                AssembleError { kind: AssembleErrorKind::UndefinedLabel(name), line: *line }
+            })?;
+            builder.push_instruction(&operands.instruction(*opcode, target));
+        }
+    }
+    let line = items.last().map_or(0, |(line, _)| *line);
+    builder.current_address().map_err(|error| AssembleError { kind: AssembleErrorKind::Build(error), line })?;
+    let metadata = Metadata { symbols, ..Metadata::default() };
+    Ok(builder.build_owned().with_metadata(metadata))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pegatexto_vm::bytecode::instruction::InstructionIterator;
+    use pegatexto_vm::matcher::try_match;
+
+    #[test]
+    fn test_assemble() {
+        let source = "
+            ; 'a'+ followed by \"b;\" or a digit
+            .S
+              call .As
+              jmpf end
+              push
+              str \"b;\"  ; comment after an operand
+              jmps found
+              peek
+              cls \\d
+            found:
+              pop
+            end:
+              ret
+            .As
+              chr 'a'
+              qinit
+            loop:
+              chr 'a'
+              qnext
+              flt 0
+              succ
+              ret
+        ";
+        let bytecode = assemble(source).unwrap();
+        assert_eq!(bytecode.symbols().address_of("As"), Some(Address::new(25)));
+        let bytecode = bytecode.as_bytecode();
+        assert_eq!(try_match(&bytecode, "aab;"), Ok(4));
+        assert_eq!(try_match(&bytecode, "a7"), Ok(2));
+        assert!(try_match(&bytecode, "b").is_err());
+    }
+
+    #[test]
+    fn test_listing_round_trip() {
+        let greek = UnicodeClass::from_name("Greek").unwrap();
+        let class_set = ClassSet::encode(&Charset::from_bytes([0x81; 32]), &[
+            ClassItem::Range('α', 'ω'),
+            ClassItem::Range('\t', '\u{7f}'),
+            ClassItem::Class(CharacterClass::NotDigit),
+            ClassItem::UnicodeClass(greek),
+        ]);
+        let mut charset = Charset::new();
+        charset.insert_range(0, 255);
+        let instructions = [
+            Instruction::Any, Instruction::Succeed, Instruction::Fail, Instruction::FailIfLessThan(3),
+            Instruction::ToggleSuccess, Instruction::QuantifierInit, Instruction::QuantifierNext,
+            Instruction::Jump(Address::new(0)), Instruction::JumpIfFail(Address::new(1)),
+            Instruction::JumpIfSuccess(Address::new(2)), Instruction::Call(Address::new(3)),
+            Instruction::Return, Instruction::Push, Instruction::Peek, Instruction::Pop, Instruction::Byte(0xce),
+            Instruction::Char('\''), Instruction::Class(CharacterClass::Whitespace),
+            Instruction::Literal("a \"quoted\"\n\\ ;α\u{7f}"), Instruction::Set("]-^"), Instruction::NotSet("xyz"),
+            Instruction::Range(b'-', b']'), Instruction::Range(b'\n', b'\\'), Instruction::Capture(255), Instruction::Halt(None),
+            Instruction::TestSet(Address::new(4), "ab"), Instruction::Charset(Charset::from_bytes([0x5a; 32])),
+            Instruction::NotCharset(charset), Instruction::CharRange('[', 'ω'), Instruction::CharRange('\n', 'ω'), Instruction::UnicodeClass(greek),
+            Instruction::ClassSet(ClassSet::parse(&class_set).unwrap().0), Instruction::LiteralNoCase("Straße"),
+            Instruction::SetNoCase("kσ"), Instruction::Behind(2), Instruction::EndOfInput, Instruction::FailIfMoved,
+        ];
+        let bytecode = OwnedBytecode::from_instructions(&instructions);
+        let bytecode = bytecode.as_bytecode();
+        let mut listing = String::new();
+        let mut iter = InstructionIterator::new(&bytecode);
+        let mut address = iter.current();
+        while let Some(instruction) = iter.next() {
+            listing.push_str(&format!("{:4} | {}\n", address, instruction));
+            address = iter.current();
+        }
+        assert_eq!(&*assemble(&listing).unwrap().as_bytecode(), &*bytecode, "{}", listing);
+    }
+
+    #[test]
+    fn test_assemble_errors() {
+        let error = |source| assemble(source).err().map(|error| (error.kind, error.line));
+        assert_eq!(error("any\nnop"), Some((AssembleErrorKind::UnknownOpcode("nop".to_string()), 2)));
+        assert_eq!(error("chr 'ab'"), Some((AssembleErrorKind::InvalidOperand, 1)));
+        assert_eq!(error("byte 256"), Some((AssembleErrorKind::InvalidOperand, 1)));
+        assert_eq!(error("rng [α-ω]"), Some((AssembleErrorKind::InvalidOperand, 1)));
+        assert_eq!(error("ret ret"), Some((AssembleErrorKind::TrailingInput, 1)));
+        assert_eq!(error("jmp nowhere"), Some((AssembleErrorKind::UndefinedLabel("nowhere".to_string()), 1)));
+        assert_eq!(error("call .R"), Some((AssembleErrorKind::UndefinedLabel(".R".to_string()), 1)));
+        assert_eq!(error("a:\nany\na:"), Some((AssembleErrorKind::DuplicateLabel("a".to_string()), 3)));
+    }
+}
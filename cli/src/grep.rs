@@ -0,0 +1,314 @@
+use crate::{display_path, load_program, parse_args, read, Error, EXIT_ERROR, EXIT_FAILURE, STDIO};
+use crate::repl::is_definition;
+
+use pegatexto_compiler::Compiler;
+use pegatexto_compiler::syntax::parse_grammar;
+use pegatexto_vm::bytecode::{Bytecode, OwnedBytecode};
+use pegatexto_vm::matcher::{find_at, MatchError};
+use pegatexto_vm::source_map::LineIndex;
+
+use std::fs;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+const PATTERN: &str = "<pattern>";
+/// Inline patterns without definitions become the body of this rule
+const PATTERN_RULE: &str = "Pattern <- ";
+
+// grep's default colors: magenta file names, green numbers, cyan separators
+// and bold red matches
+const FILE_COLOR: &str = "\x1b[35m";
+const NUMBER_COLOR: &str = "\x1b[32m";
+const SEPARATOR_COLOR: &str = "\x1b[36m";
+const MATCH_COLOR: &str = "\x1b[1;31m";
+const RESET: &str = "\x1b[m";
+
+fn compile_pattern(pattern: &str) -> Result<OwnedBytecode, Error> {
+    if is_definition(pattern) {
+        return crate::compile_grammar(PATTERN, pattern.to_string());
+    }
+    let source = format!("{}{}", PATTERN_RULE, pattern);
+    let grammar = parse_grammar(&source).map_err(|mut error| {
+        error.offset = error.offset.saturating_sub(PATTERN_RULE.len());
+        Error::invalid(format!("{}:{}: {}", PATTERN, error.position(&LineIndex::new(pattern)), error))
+    })?;
+    let mut compiler = Compiler::new();
+    compiler.compile_parsed(&grammar).map_err(|error| Error::invalid(format!("{}: {}", PATTERN, error)))?;
+    Ok(compiler.emit_owned())
+}
+
+/// The spans of the non-overlapping matches in `line`, from left to right.
+/// Empty matches are kept, they still select the line
+fn find_matches(bytecode: &Bytecode, line: &str) -> Result<Vec<(usize, usize)>, MatchError> {
+    let mut matches = Vec::new();
+    let mut start = 0;
+    while let Some((first, end)) = find_at(bytecode, line, start)? {
+        matches.push((first, end));
+        start = if end > first {
+            end
+        }
+        else {
+            match line[first..].chars().next() {
+                Some(c) => first + c.len_utf8(),
+                None => break,
+            }
+        };
+    }
+    Ok(matches)
+}
+
+fn column(line: &str, offset: usize) -> usize {
+    line[..offset].chars().count() + 1
+}
+
+struct Searcher<W> {
+    bytecode: OwnedBytecode,
+    only_matching: bool,
+    color: bool,
+    out: W,
+    found: bool,
+    failed: bool,
+}
+
+impl<W: Write> Searcher<W> {
+    /// grep's exit status: errors take precedence over matches
+    fn status(&self) -> i32 {
+        if self.failed { EXIT_ERROR } else if self.found { 0 } else { EXIT_FAILURE }
+    }
+
+    fn report(&mut self, error: Error) {
+        eprintln!("{}", error.message);
+        self.failed = true;
+    }
+
+    /// Searches a file, or a directory's files if `recursive`. Only output
+    /// errors are returned, other errors are reported and searching goes on
+    fn search_path(&mut self, path: &str, recursive: bool) -> io::Result<()> {
+        if recursive && path != STDIO && Path::new(path).is_dir() {
+            return self.search_dir(Path::new(path));
+        }
+        match read(path) {
+            Ok(bytes) => match String::from_utf8(bytes) {
+                Ok(text) => self.search(display_path(path), &text),
+                Err(_) => {
+                    self.report(Error::invalid(format!("{}: invalid UTF-8", display_path(path))));
+                    Ok(())
+                },
+            },
+            Err(error) => {
+                self.report(error);
+                Ok(())
+            },
+        }
+    }
+
+    /// Searches the files below `dir` in name order, skipping symbolic links
+    /// and binary files like grep does
+    fn search_dir(&mut self, dir: &Path) -> io::Result<()> {
+        let entries = fs::read_dir(dir).and_then(|entries| {
+            entries.map(|entry| entry.map(|entry| entry.path())).collect::<io::Result<Vec<_>>>()
+        });
+        let mut entries = match entries {
+            Ok(entries) => entries,
+            Err(error) => {
+                self.report(Error::io(&dir.to_string_lossy(), error));
+                return Ok(());
+            },
+        };
+        entries.sort();
+        for entry in entries {
+            let file_type = match fs::symlink_metadata(&entry) {
+                Ok(metadata) => metadata.file_type(),
+                Err(error) => {
+                    self.report(Error::io(&entry.to_string_lossy(), error));
+                    continue;
+                },
+            };
+            if file_type.is_dir() {
+                self.search_dir(&entry)?;
+            }
+            else if file_type.is_file() {
+                match fs::read(&entry) {
+                    Ok(bytes) => if let Ok(text) = String::from_utf8(bytes) {
+                        self.search(&entry.to_string_lossy(), &text)?;
+                    },
+                    Err(error) => self.report(Error::io(&entry.to_string_lossy(), error)),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Prints the lines of `text` with a match, or only the matches
+    fn search(&mut self, name: &str, text: &str) -> io::Result<()> {
+        for (number, line) in text.lines().enumerate() {
+            let matches = match find_matches(&self.bytecode.as_bytecode(), line) {
+                Ok(matches) => matches,
+                Err(error) => {
+                    self.report(Error { status: EXIT_ERROR, message: format!("{}:{}: {}", name, number + 1, error) });
+                    return Ok(());
+                },
+            };
+            let first = match matches.first() {
+                Some(&(first, _)) => first,
+                None => continue,
+            };
+            self.found = true;
+            let spans = matches.into_iter().filter(|(start, end)| end > start);
+            if self.only_matching {
+                for (start, end) in spans {
+                    self.write_prefix(name, number + 1, column(line, start))?;
+                    self.write_match(&line[start..end])?;
+                    writeln!(self.out)?;
+                }
+            }
+            else {
+                self.write_prefix(name, number + 1, column(line, first))?;
+                let mut last = 0;
+                for (start, end) in spans {
+                    write!(self.out, "{}", &line[last..start])?;
+                    self.write_match(&line[start..end])?;
+                    last = end;
+                }
+                writeln!(self.out, "{}", &line[last..])?;
+            }
+        }
+        Ok(())
+    }
+
+    fn write_prefix(&mut self, name: &str, line: usize, column: usize) -> io::Result<()> {
+        if self.color {
+            let separator = format!("{}:{}", SEPARATOR_COLOR, RESET);
+            write!(self.out, "{}{}{}{}", FILE_COLOR, name, RESET, separator)?;
+            write!(self.out, "{}{}{}{}", NUMBER_COLOR, line, RESET, separator)?;
+            write!(self.out, "{}{}{}{}", NUMBER_COLOR, column, RESET, separator)
+        }
+        else {
+            write!(self.out, "{}:{}:{}:", name, line, column)
+        }
+    }
+
+    fn write_match(&mut self, text: &str) -> io::Result<()> {
+        if self.color {
+            write!(self.out, "{}{}{}", MATCH_COLOR, text, RESET)
+        }
+        else {
+            write!(self.out, "{}", text)
+        }
+    }
+}
+
+pub fn grep(args: &[String]) -> Result<i32, Error> {
+    let args = parse_args(args, &["-o", "-r", "--color"], &["-f"])?;
+    let (program, paths) = match args.option("-f") {
+        Some(program) => (load_program(program), &args.positional[..]),
+        None => {
+            let (pattern, paths) = args.positional.split_first().ok_or_else(|| Error::usage("missing pattern"))?;
+            (compile_pattern(pattern), paths)
+        },
+    };
+    // grep exits with 2 on invalid patterns too
+    let bytecode = program.map_err(|error| Error { status: EXIT_ERROR, ..error })?;
+    let recursive = args.has_flag("-r");
+    let default_path = if recursive { "." } else { STDIO };
+
+    let stdout = io::stdout();
+    let mut searcher = Searcher {
+        bytecode,
+        only_matching: args.has_flag("-o"),
+        color: args.has_flag("--color"),
+        out: BufWriter::new(stdout.lock()),
+        found: false,
+        failed: false,
+    };
+    let result = if paths.is_empty() {
+        searcher.search_path(default_path, recursive)
+    }
+    else {
+        paths.iter().try_for_each(|path| searcher.search_path(path, recursive))
+    };
+    match result.and_then(|_| searcher.out.flush()) {
+        // like grep, stop quietly when the reader goes away
+        Err(error) if error.kind() == io::ErrorKind::BrokenPipe => Ok(searcher.status()),
+        Err(error) => Err(Error { status: EXIT_ERROR, message: format!("<stdout>: {}", error) }),
+        Ok(()) => Ok(searcher.status()),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::process;
+
+    fn searcher(pattern: &str, only_matching: bool, color: bool) -> Searcher<Vec<u8>> {
+        let bytecode = compile_pattern(pattern).unwrap();
+        Searcher { bytecode, only_matching, color, out: Vec::new(), found: false, failed: false }
+    }
+
+    fn search(searcher: &mut Searcher<Vec<u8>>, text: &str) -> String {
+        searcher.search("f", text).unwrap();
+        String::from_utf8(std::mem::take(&mut searcher.out)).unwrap()
+    }
+
+    #[test]
+    fn test_find_matches() {
+        let digits = compile_pattern("\\d+").unwrap();
+        assert_eq!(find_matches(&digits.as_bytecode(), "a12 b3"), Ok(vec![(1, 3), (5, 6)]));
+        let optional = compile_pattern("'a'?").unwrap();
+        assert_eq!(find_matches(&optional.as_bytecode(), "bαa"), Ok(vec![(0, 0), (1, 1), (3, 4), (4, 4)]));
+        let grammar = compile_pattern("S <- Word ' ' Word\nWord <- [a-z]+").unwrap();
+        assert_eq!(find_matches(&grammar.as_bytecode(), "1 ab cd"), Ok(vec![(2, 7)]));
+        // arrows inside expressions don't make them grammars
+        let arrow = compile_pattern("'<-'").unwrap();
+        assert_eq!(find_matches(&arrow.as_bytecode(), "a <- b"), Ok(vec![(2, 4)]));
+        let set = compile_pattern("[<-z]+").unwrap();
+        assert_eq!(find_matches(&set.as_bytecode(), "<=> ab"), Ok(vec![(0, 3), (4, 6)]));
+    }
+
+    #[test]
+    fn test_search() {
+        let mut lines = searcher("\\d+", false, false);
+        assert_eq!(search(&mut lines, "no\nαβ 12 and 3\n42\n"), "f:2:4:αβ 12 and 3\nf:3:1:42\n");
+        assert_eq!(lines.status(), 0);
+
+        let mut only = searcher("\\d+", true, false);
+        assert_eq!(search(&mut only, "αβ 12 and 3\nno"), "f:1:4:12\nf:1:11:3\n");
+        let mut color = searcher("'b'", false, true);
+        assert_eq!(search(&mut color, "abc"), "\x1b[35mf\x1b[m\x1b[36m:\x1b[m\x1b[32m1\x1b[m\x1b[36m:\x1b[m\
+            \x1b[32m2\x1b[m\x1b[36m:\x1b[ma\x1b[1;31mb\x1b[mc\n");
+
+        // lines are matched separately, so the end of input is the end of line
+        let mut last = searcher("[a-z]+ !.", true, false);
+        assert_eq!(search(&mut last, "ab cd\nef"), "f:1:4:cd\nf:2:1:ef\n");
+
+        let mut none = searcher("'x'", false, false);
+        assert_eq!(search(&mut none, "abc"), "");
+        assert_eq!(none.status(), EXIT_FAILURE);
+    }
+
+    #[test]
+    fn test_grep() {
+        let dir = env::temp_dir().join(format!("pegatexto-grep-{}", process::id()));
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        let path = |name: &str| dir.join(name).to_string_lossy().into_owned();
+        fs::write(path("a.txt"), "one\ntwo 2\n").unwrap();
+        fs::write(path("sub/b.txt"), "three\n").unwrap();
+        fs::write(path("sub/binary"), b"\xff 1").unwrap();
+        let strings = |args: &[&str]| args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+
+        assert_eq!(grep(&strings(&["\\d", &path("a.txt")])), Ok(0));
+        assert_eq!(grep(&strings(&["'x'", &path("a.txt")])), Ok(EXIT_FAILURE));
+        assert_eq!(grep(&strings(&["-r", "\\d", &path("sub")])), Ok(EXIT_FAILURE));
+        assert_eq!(grep(&strings(&["-r", "'three'", &dir.to_string_lossy()])), Ok(0));
+        // a directory without -r, and an explicit binary file are errors
+        assert_eq!(grep(&strings(&["'one'", &path("a.txt"), &path("sub")])), Ok(EXIT_ERROR));
+        assert_eq!(grep(&strings(&["\\d", &path("sub/binary")])), Ok(EXIT_ERROR));
+
+        assert_eq!(compile_pattern("'a' [").err().map(|error| error.message).as_deref(), Some("<pattern>:1:5: unterminated set"));
+        assert_eq!(grep(&strings(&["'a' [", &path("a.txt")])).unwrap_err().status, EXIT_ERROR);
+        assert_eq!(grep(&strings(&[])).unwrap_err().status, EXIT_ERROR);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,470 @@
+mod grep;
+mod repl;
+mod suite;
+
+use pegatexto_assembler::assemble;
+use pegatexto_compiler::Compiler;
+use pegatexto_compiler::format::format_source;
+use pegatexto_compiler::syntax::parse_grammar;
+use pegatexto_disassembler::dump_bytecode_with_symbols;
+use pegatexto_vm::bytecode::{Metadata, OwnedBytecode};
+use pegatexto_vm::bytecode::file::MAGIC;
+use pegatexto_vm::matcher::{try_match_full_traced_then, try_match_traced_then, MatchError};
+use pegatexto_vm::profiler::Profiler;
+use pegatexto_vm::source_map::LineIndex;
+use pegatexto_vm::tracer::{Debugger, NullTracer, PrintTracer, Tracer};
+
+use std::env;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::process;
+
+const USAGE: &str = "\
+usage: pegatexto <command> [options] <args>
+
+commands:
+  compile <grammar> [-o <output>]   compile a grammar into a bytecode file
+  asm <assembly> [-o <output>]      assemble a bytecode file
+  disasm <program>                  print the bytecode listing of a program
+  check <program>...                check programs compile and verify
+  match [--full] [--trace | --profile | --debug] <program> [<input>]
+                                    match the input, printing its captures
+  test <suite>...                   run grammar test suites
+  grep [-o] [-r] [--color] (<pattern> | -f <program>) [<file>...]
+                                    print the lines of files with a match
+  repl [<grammar>]                  match inputs while editing a grammar
+  fmt [--check | --write] <grammar>...
+                                    print grammars in canonical form, list
+                                    the unformatted ones or rewrite them
+
+Programs are grammars or bytecode files. The output defaults to the input
+path with the `.pgb` extension, and `-` reads stdin or writes stdout.
+
+`match --debug` steps through the match with commands read from stdin, so
+the input must be a file. Type `h` at its prompt for the commands.
+
+grep searches each line separately for matches starting anywhere in it.
+Patterns are expressions, or grammars if they contain definitions. `-o`
+prints only the matches, `-r` searches directories recursively and
+`--color` highlights the matches.
+
+exit status: 0 on success, 1 on no match, failed tests or invalid
+programs, 2 on usage and I/O errors. grep exits with 2 on any error";
+
+/// Exit status for negative results, like no match or an invalid grammar
+const EXIT_FAILURE: i32 = 1;
+/// Exit status for usage and I/O errors
+const EXIT_ERROR: i32 = 2;
+
+const STDIO: &str = "-";
+
+#[derive(Debug, PartialEq)]
+struct Error {
+    status: i32,
+    message: String,
+}
+
+impl Error {
+    fn usage(message: &str) -> Error {
+        Error { status: EXIT_ERROR, message: format!("{}\n\n{}", message, USAGE) }
+    }
+
+    fn io(path: &str, error: io::Error) -> Error {
+        Error { status: EXIT_ERROR, message: format!("{}: {}", display_path(path), error) }
+    }
+
+    fn invalid(message: String) -> Error {
+        Error { status: EXIT_FAILURE, message }
+    }
+}
+
+fn display_path(path: &str) -> &str {
+    if path == STDIO { "<stdin>" } else { path }
+}
+
+#[derive(Debug, Default, PartialEq)]
+struct Args {
+    positional: Vec<String>,
+    flags: Vec<String>,
+    options: Vec<(String, String)>,
+}
+
+impl Args {
+    fn has_flag(&self, flag: &str) -> bool {
+        self.flags.iter().any(|f| f == flag)
+    }
+
+    /// The value of the last occurrence of `option`
+    fn option(&self, option: &str) -> Option<&str> {
+        self.options.iter().rev().find(|(name, _)| name == option).map(|(_, value)| value.as_str())
+    }
+}
+
+/// Splits `args` into positional arguments, the given flags and the given
+/// options, which take a value
+fn parse_args(args: &[String], flags: &[&str], options: &[&str]) -> Result<Args, Error> {
+    let mut parsed = Args::default();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if options.contains(&arg.as_str()) {
+            let value = args.next().ok_or_else(|| Error::usage(&format!("missing value after {}", arg)))?;
+            parsed.options.push((arg.clone(), value.clone()));
+        }
+        else if flags.contains(&arg.as_str()) {
+            parsed.flags.push(arg.clone());
+        }
+        else if arg.starts_with('-') && arg != STDIO {
+            return Err(Error::usage(&format!("unknown option {}", arg)));
+        }
+        else {
+            parsed.positional.push(arg.clone());
+        }
+    }
+    Ok(parsed)
+}
+
+fn read(path: &str) -> Result<Vec<u8>, Error> {
+    let result = if path == STDIO {
+        let mut bytes = Vec::new();
+        io::stdin().read_to_end(&mut bytes).map(|_| bytes)
+    }
+    else {
+        fs::read(path)
+    };
+    result.map_err(|error| Error::io(path, error))
+}
+
+fn read_text(path: &str) -> Result<String, Error> {
+    String::from_utf8(read(path)?).map_err(|_| Error::invalid(format!("{}: invalid UTF-8", display_path(path))))
+}
+
+fn write(path: &str, bytes: &[u8]) -> Result<(), Error> {
+    let result = if path == STDIO { io::stdout().write_all(bytes) } else { fs::write(path, bytes) };
+    result.map_err(|error| Error::io(path, error))
+}
+
+fn output_path(args: &Args, input: &str) -> String {
+    match args.option("-o") {
+        Some(output) => output.to_string(),
+        None if input == STDIO => STDIO.to_string(),
+        None => Path::new(input).with_extension("pgb").to_string_lossy().into_owned(),
+    }
+}
+
+fn compile_grammar(path: &str, source: String) -> Result<OwnedBytecode, Error> {
+    let grammar = parse_grammar(&source).map_err(|error| {
+        Error::invalid(format!("{}:{}: {}", display_path(path), error.position(&LineIndex::new(&source)), error))
+    })?;
+    let mut compiler = Compiler::new();
+    compiler.compile_parsed(&grammar).map_err(|error| Error::invalid(format!("{}: {}", display_path(path), error)))?;
+    let bytecode = compiler.emit_owned();
+    let metadata = Metadata { debug_info: Some(source.clone()), ..bytecode.metadata().clone() };
+    Ok(bytecode.with_metadata(metadata))
+}
+
+/// Loads a bytecode file, verifying it, or compiles a grammar
+fn load_program(path: &str) -> Result<OwnedBytecode, Error> {
+    let bytes = read(path)?;
+    if !bytes.starts_with(&MAGIC) {
+        let source = String::from_utf8(bytes).map_err(|_| Error::invalid(format!("{}: invalid UTF-8", display_path(path))))?;
+        return compile_grammar(path, source);
+    }
+    let bytecode = OwnedBytecode::read_from(&mut &bytes[..])
+        .map_err(|error| Error::invalid(format!("{}: {}", display_path(path), error)))?;
+    bytecode.verify().map_err(|error| Error::invalid(format!("{}: {}", display_path(path), error)))?;
+    Ok(bytecode)
+}
+
+fn write_program(path: &str, bytecode: &OwnedBytecode) -> Result<(), Error> {
+    let mut bytes = Vec::new();
+    bytecode.write_to(&mut bytes).map_err(|error| Error::io(path, error))?;
+    write(path, &bytes)
+}
+
+fn single_positional(args: &Args, what: &str) -> Result<String, Error> {
+    match args.positional.as_slice() {
+        [arg] => Ok(arg.clone()),
+        [] => Err(Error::usage(&format!("missing {}", what))),
+        _ => Err(Error::usage("too many arguments")),
+    }
+}
+
+fn compile(args: &[String]) -> Result<i32, Error> {
+    let args = parse_args(args, &[], &["-o"])?;
+    let input = single_positional(&args, "grammar")?;
+    let bytecode = compile_grammar(&input, read_text(&input)?)?;
+    write_program(&output_path(&args, &input), &bytecode)?;
+    Ok(0)
+}
+
+fn asm(args: &[String]) -> Result<i32, Error> {
+    let args = parse_args(args, &[], &["-o"])?;
+    let input = single_positional(&args, "assembly")?;
+    let bytecode = assemble(&read_text(&input)?).map_err(|error| {
+        Error::invalid(format!("{}:{}: {}", display_path(&input), error.line, error))
+    })?;
+    write_program(&output_path(&args, &input), &bytecode)?;
+    Ok(0)
+}
+
+fn disasm(args: &[String]) -> Result<i32, Error> {
+    let args = parse_args(args, &[], &[])?;
+    let bytecode = load_program(&single_positional(&args, "program")?)?;
+    dump_bytecode_with_symbols(&bytecode.as_bytecode(), bytecode.symbols());
+    Ok(0)
+}
+
+fn check(args: &[String]) -> Result<i32, Error> {
+    let args = parse_args(args, &[], &[])?;
+    if args.positional.is_empty() {
+        return Err(Error::usage("missing program"));
+    }
+    let mut status = 0;
+    for path in args.positional.iter() {
+        match load_program(path) {
+            Ok(_) => println!("{}: ok", display_path(path)),
+            Err(error) => {
+                eprintln!("{}", error.message);
+                status = status.max(error.status);
+            },
+        }
+    }
+    Ok(status)
+}
+
+fn match_traced<R: Tracer>(bytecode: &OwnedBytecode, text: &str, full: bool, tracer: &mut R) -> Result<(usize, Option<suite::CaptureTree>), MatchError> {
+    let capture_tree = suite::capture_tree(&bytecode.metadata().capture_names);
+    if full {
+        try_match_full_traced_then(&bytecode.as_bytecode(), text, tracer, capture_tree)
+    }
+    else {
+        try_match_traced_then(&bytecode.as_bytecode(), text, tracer, capture_tree)
+    }
+}
+
+fn match_input(args: &[String]) -> Result<i32, Error> {
+    let args = parse_args(args, &["--full", "--trace", "--profile", "--debug"], &[])?;
+    let (program, input) = match args.positional.as_slice() {
+        [program] => (program, STDIO),
+        [program, input] => (program, input.as_str()),
+        [] => return Err(Error::usage("missing program")),
+        _ => return Err(Error::usage("too many arguments")),
+    };
+    if ["--trace", "--profile", "--debug"].iter().filter(|flag| args.has_flag(flag)).count() > 1 {
+        return Err(Error::usage("--trace, --profile and --debug can't be used together"));
+    }
+    if args.has_flag("--debug") && input == STDIO {
+        return Err(Error::usage("--debug reads commands from stdin, so the input must be a file"));
+    }
+    let bytecode = load_program(program)?;
+    let text = read_text(input)?;
+
+    let full = args.has_flag("--full");
+    let mut profiler = Profiler::new(bytecode.symbols().clone()).with_tail_calls(&bytecode.metadata().tail_calls);
+    let result = if args.has_flag("--trace") {
+        match_traced(&bytecode, &text, full, &mut PrintTracer::new(io::stdout(), bytecode.symbols().clone()))
+    }
+    else if args.has_flag("--profile") {
+        match_traced(&bytecode, &text, full, &mut profiler)
+    }
+    else if args.has_flag("--debug") {
+        let stdin = io::stdin();
+        match_traced(&bytecode, &text, full, &mut Debugger::new(stdin.lock(), io::stdout(), bytecode.symbols().clone()))
+    }
+    else {
+        match_traced(&bytecode, &text, full, &mut NullTracer)
+    };
+    if args.has_flag("--profile") {
+        profiler.write_report(&mut io::stdout()).map_err(|error| Error::io(STDIO, error))?;
+    }
+    match result {
+        Ok((end, tree)) => {
+            println!("match {}", end);
+            if let Some(tree) = tree {
+                println!("{}", tree);
+            }
+            Ok(0)
+        },
+        Err(error) => {
+            match error.position(&LineIndex::new(&text)) {
+                Some(position) => eprintln!("{}:{}: {}", display_path(input), position, error),
+                None => eprintln!("{}: {}", display_path(input), error),
+            }
+            Ok(EXIT_FAILURE)
+        },
+    }
+}
+
+/// Runs a test suite, returning whether all its cases passed
+fn run_suite(path: &str) -> Result<bool, Error> {
+    let suite = suite::parse_suite(&read_text(path)?)
+        .map_err(|error| Error::invalid(format!("{}:{}: {}", display_path(path), error.line, error)))?;
+    let grammar_path = Path::new(path).parent().unwrap_or_else(|| Path::new("")).join(&suite.grammar);
+    let grammar_path = grammar_path.to_string_lossy();
+    let bytecode = compile_grammar(&grammar_path, read_text(&grammar_path)?)?;
+    let mut failed = 0;
+    for case in suite.cases.iter() {
+        let actual = suite::run_case(&bytecode.as_bytecode(), &bytecode.metadata().capture_names, case);
+        if !actual.satisfies(&case.expected) {
+            failed += 1;
+            println!("{}:{}: {:?}", path, case.line, case.input);
+            print!("{}", suite::diff(&case.expected.to_string(), &actual.to_string()));
+        }
+    }
+    println!("{}: {} passed, {} failed", path, suite.cases.len() - failed, failed);
+    Ok(failed == 0)
+}
+
+fn test(args: &[String]) -> Result<i32, Error> {
+    let args = parse_args(args, &[], &[])?;
+    if args.positional.is_empty() {
+        return Err(Error::usage("missing test suite"));
+    }
+    let mut status = 0;
+    for path in args.positional.iter() {
+        match run_suite(path) {
+            Ok(true) => (),
+            Ok(false) => status = status.max(EXIT_FAILURE),
+            Err(error) => {
+                eprintln!("{}", error.message);
+                status = status.max(error.status);
+            },
+        }
+    }
+    Ok(status)
+}
+
+fn fmt(args: &[String]) -> Result<i32, Error> {
+    let args = parse_args(args, &["--check", "--write"], &[])?;
+    if args.positional.is_empty() {
+        return Err(Error::usage("missing grammar"));
+    }
+    if args.has_flag("--check") && args.has_flag("--write") {
+        return Err(Error::usage("--check and --write can't be used together"));
+    }
+    let mut status = 0;
+    for path in args.positional.iter() {
+        let source = read_text(path)?;
+        let formatted = format_source(&source).map_err(|error| {
+            Error::invalid(format!("{}:{}: {}", display_path(path), error.position(&LineIndex::new(&source)), error))
+        })?;
+        if args.has_flag("--check") {
+            if formatted != source {
+                println!("{}", display_path(path));
+                status = EXIT_FAILURE;
+            }
+        }
+        else if args.has_flag("--write") && path != STDIO {
+            if formatted != source {
+                write(path, formatted.as_bytes())?;
+            }
+        }
+        else {
+            write(STDIO, formatted.as_bytes())?;
+        }
+    }
+    Ok(status)
+}
+
+fn run(args: &[String]) -> Result<i32, Error> {
+    let (command, args) = args.split_first().ok_or_else(|| Error::usage("missing command"))?;
+    match command.as_str() {
+        "compile" => compile(args),
+        "asm" => asm(args),
+        "disasm" => disasm(args),
+        "check" => check(args),
+        "match" => match_input(args),
+        "test" => test(args),
+        "grep" => grep::grep(args),
+        "repl" => repl::repl(args),
+        "fmt" => fmt(args),
+        "help" | "-h" | "--help" => {
+            println!("{}", USAGE);
+            Ok(0)
+        },
+        _ => Err(Error::usage(&format!("unknown command {}", command))),
+    }
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let status = run(&args).unwrap_or_else(|error| {
+        eprintln!("{}", error.message);
+        error.status
+    });
+    process::exit(status);
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn test_parse_args() {
+        let args = parse_args(&strings(&["a.peg", "--full", "-o", "out", "-"]), &["--full"], &["-o"]).unwrap();
+        assert_eq!(args, Args {
+            positional: strings(&["a.peg", "-"]),
+            flags: strings(&["--full"]),
+            options: vec![("-o".to_string(), "out".to_string())],
+        });
+        assert_eq!(args.option("-o"), Some("out"));
+        assert!(args.has_flag("--full"));
+        assert_eq!(parse_args(&strings(&["-o"]), &[], &["-o"]).unwrap_err().status, EXIT_ERROR);
+        assert_eq!(parse_args(&strings(&["-o", "out"]), &[], &[]).unwrap_err().status, EXIT_ERROR);
+        assert_eq!(output_path(&Args::default(), "dir/a.peg"), "dir/a.pgb");
+        assert_eq!(output_path(&Args::default(), STDIO), STDIO);
+    }
+
+    #[test]
+    fn test_commands() {
+        let dir = env::temp_dir().join(format!("pegatexto-cli-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = |name: &str| dir.join(name).to_string_lossy().into_owned();
+        fs::write(path("g.peg"), "S <- {'a'+} !.").unwrap();
+        fs::write(path("bad.peg"), "S <- T").unwrap();
+        fs::write(path("g.asm"), ".S\n  chr 'a'\n  ret\n").unwrap();
+        fs::write(path("input"), "aaa").unwrap();
+
+        assert_eq!(run(&strings(&["compile", &path("g.peg")])), Ok(0));
+        let compiled = load_program(&path("g.pgb")).unwrap();
+        assert_eq!(compiled.metadata().capture_names, strings(&["S"]));
+        assert_eq!(compiled.metadata().debug_info.as_deref(), Some("S <- {'a'+} !."));
+        assert_eq!(run(&strings(&["asm", &path("g.asm"), "-o", &path("asm.pgb")])), Ok(0));
+        assert_eq!(load_program(&path("asm.pgb")).unwrap().symbols().address_of("S").map(usize::from), Some(0));
+        assert_eq!(run(&strings(&["check", &path("g.pgb"), &path("asm.pgb")])), Ok(0));
+        assert_eq!(run(&strings(&["check", &path("bad.peg")])), Ok(EXIT_FAILURE));
+        assert_eq!(run(&strings(&["match", &path("g.pgb"), &path("input")])), Ok(0));
+        assert_eq!(run(&strings(&["match", "--full", &path("asm.pgb"), &path("input")])), Ok(EXIT_FAILURE));
+        assert_eq!(run(&strings(&["match", &path("bad.peg"), &path("input")])).unwrap_err().status, EXIT_FAILURE);
+        assert_eq!(run(&strings(&["match", &path("missing.peg")])).unwrap_err().status, EXIT_ERROR);
+        assert_eq!(run(&strings(&["match", "--debug", &path("g.pgb")])).unwrap_err().status, EXIT_ERROR);
+        assert_eq!(run(&strings(&["match", "--debug", "--trace", &path("g.pgb"), &path("input")])).unwrap_err().status, EXIT_ERROR);
+        assert_eq!(run(&strings(&["frobnicate"])).unwrap_err().status, EXIT_ERROR);
+
+        fs::write(path("fmt.peg"), "S<-('a' 'b')  # s\n").unwrap();
+        assert_eq!(run(&strings(&["fmt", "--check", &path("fmt.peg")])), Ok(EXIT_FAILURE));
+        assert_eq!(run(&strings(&["fmt", "--write", &path("fmt.peg")])), Ok(0));
+        assert_eq!(fs::read_to_string(path("fmt.peg")).unwrap(), "S <- 'a' 'b' # s\n");
+        assert_eq!(run(&strings(&["fmt", "--check", &path("fmt.peg")])), Ok(0));
+        assert_eq!(run(&strings(&["fmt", &path("bad.peg"), &path("g.peg")])), Ok(0));
+        assert_eq!(run(&strings(&["fmt", "--check", &path("input")])).unwrap_err().status, EXIT_FAILURE);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_example_suites() {
+        let grammars = Path::new(env!("CARGO_MANIFEST_DIR")).join("../grammars");
+        for entry in fs::read_dir(grammars).unwrap() {
+            let path = entry.unwrap().path();
+            if path.extension().is_some_and(|extension| extension == "test") {
+                assert_eq!(run_suite(&path.to_string_lossy()), Ok(true), "{}", path.display());
+            }
+        }
+    }
+}
@@ -0,0 +1,264 @@
+use crate::{parse_args, read_text, suite, Error, EXIT_ERROR};
+
+use pegatexto_compiler::Compiler;
+use pegatexto_compiler::syntax::{parse_definitions, parse_grammar};
+use pegatexto_vm::bytecode::OwnedBytecode;
+use pegatexto_vm::matcher::try_match_captures_then;
+use pegatexto_vm::source_map::LineIndex;
+
+use std::io::{self, BufRead, IsTerminal, Write};
+
+const PROMPT: &str = "> ";
+
+const HELP: &str = "\
+Lines with definitions like `Name <- expr` add or replace those rules and
+recompile the grammar, other lines are matched from its first rule.
+
+commands:
+  :match <text>    match text that looks like a command or definition
+  :rules           print the definitions
+  :delete <name>   delete a definition, `@Name` for classes
+  :load <grammar>  replace the definitions with a grammar file's
+  :help            print this help
+  :quit            exit, like end of input";
+
+/// Whether the line starts like a definition, so it isn't matched even if
+/// it has syntax errors
+pub(crate) fn is_definition(line: &str) -> bool {
+    let rest = line.trim_start();
+    let rest = rest.strip_prefix('@').unwrap_or(rest);
+    let name_len = rest.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_')).unwrap_or(rest.len());
+    name_len > 0 && rest[name_len..].trim_start().starts_with("<-")
+}
+
+/// Prints `line` with a caret under its `char_column`
+fn write_caret<W: Write>(out: &mut W, line: &str, char_column: usize, message: &str) -> io::Result<()> {
+    writeln!(out, "  {}", line)?;
+    writeln!(out, "  {}^ {}", " ".repeat(char_column.saturating_sub(1)), message)
+}
+
+struct Session {
+    /// Definition sources in definition order, keyed by name with `@` for classes
+    definitions: Vec<(String, String)>,
+    program: Result<OwnedBytecode, String>,
+}
+
+impl Session {
+    fn new() -> Session {
+        Session { definitions: Vec::new(), program: Err("no grammar, define some rules".to_string()) }
+    }
+
+    fn compile(&mut self) {
+        let source: Vec<&str> = self.definitions.iter().map(|(_, source)| source.as_str()).collect();
+        let source = source.join("\n");
+        self.program = parse_grammar(&source).map_err(|error| error.to_string()).and_then(|grammar| {
+            let mut compiler = Compiler::new();
+            compiler.compile_parsed(&grammar).map_err(|error| error.to_string())?;
+            Ok(compiler.emit_owned())
+        });
+    }
+
+    /// Adds or replaces the definitions of `source`, returning their names
+    fn define(&mut self, source: &str) -> Result<Vec<String>, (usize, String)> {
+        let definitions = parse_definitions(source).map_err(|error| (error.offset, error.to_string()))?;
+        let mut names = Vec::new();
+        for definition in definitions {
+            let key = if definition.is_class { format!("@{}", definition.name) } else { definition.name.to_string() };
+            let text = source[definition.span].trim_end().to_string();
+            match self.definitions.iter_mut().find(|(name, _)| *name == key) {
+                Some(existing) => existing.1 = text,
+                None => self.definitions.push((key.clone(), text)),
+            }
+            names.push(key);
+        }
+        self.compile();
+        Ok(names)
+    }
+
+    fn load(&mut self, path: &str) -> Result<usize, Error> {
+        let source = read_text(path)?;
+        // reports duplicates, which `define` would silently replace
+        parse_grammar(&source).map_err(|error| {
+            Error::invalid(format!("{}:{}: {}", path, error.position(&LineIndex::new(&source)), error))
+        })?;
+        self.definitions.clear();
+        let count = self.define(&source).map_err(|(_, message)| Error::invalid(message))?.len();
+        Ok(count)
+    }
+
+    fn write_status<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        match &self.program {
+            Ok(_) => Ok(()),
+            Err(error) => writeln!(out, "error: {}", error),
+        }
+    }
+
+    fn match_input<W: Write>(&self, text: &str, out: &mut W) -> io::Result<()> {
+        let bytecode = match &self.program {
+            Ok(bytecode) => bytecode,
+            Err(error) => return writeln!(out, "error: {}", error),
+        };
+        let capture_tree = suite::capture_tree(&bytecode.metadata().capture_names);
+        match try_match_captures_then(&bytecode.as_bytecode(), text, capture_tree) {
+            Ok((end, tree)) => {
+                writeln!(out, "match {} of {}", end, text.len())?;
+                if let Some(tree) = tree {
+                    writeln!(out, "{}", tree)?;
+                }
+                Ok(())
+            },
+            Err(error) => match error.position(&LineIndex::new(text)) {
+                Some(position) => write_caret(out, text, position.char_column, &error.to_string()),
+                None => writeln!(out, "error: {}", error),
+            },
+        }
+    }
+
+    /// Evaluates an input line, returning false to quit
+    fn eval<W: Write>(&mut self, line: &str, out: &mut W) -> io::Result<bool> {
+        let line = line.trim_end_matches(['\n', '\r']);
+        let command = match line.strip_prefix(':') {
+            Some(command) => command,
+            None if line.is_empty() => return Ok(true),
+            None if is_definition(line) => {
+                match self.define(line) {
+                    Ok(names) => writeln!(out, "defined {}", names.join(", "))?,
+                    Err((offset, message)) => {
+                        write_caret(out, line, LineIndex::new(line).position(offset).char_column, &message)?;
+                        return Ok(true);
+                    },
+                }
+                self.write_status(out)?;
+                return Ok(true);
+            },
+            None => {
+                self.match_input(line, out)?;
+                return Ok(true);
+            },
+        };
+        let (name, argument) = command.split_once(' ').unwrap_or((command, ""));
+        match name {
+            "match" => self.match_input(argument, out)?,
+            "rules" => {
+                for (_, source) in self.definitions.iter() {
+                    writeln!(out, "{}", source)?;
+                }
+            },
+            "delete" => {
+                let argument = argument.trim();
+                match self.definitions.iter().position(|(name, _)| name == argument) {
+                    Some(index) => {
+                        self.definitions.remove(index);
+                        self.compile();
+                        writeln!(out, "deleted {}", argument)?;
+                        self.write_status(out)?;
+                    },
+                    None => writeln!(out, "error: {} is not defined", argument)?,
+                }
+            },
+            "load" => match self.load(argument.trim()) {
+                Ok(count) => {
+                    writeln!(out, "loaded {} definitions", count)?;
+                    self.write_status(out)?;
+                },
+                Err(error) => writeln!(out, "error: {}", error.message)?,
+            },
+            "help" => writeln!(out, "{}", HELP)?,
+            "quit" | "q" => return Ok(false),
+            _ => writeln!(out, "error: unknown command :{}, see :help", name)?,
+        }
+        Ok(true)
+    }
+}
+
+pub fn repl(args: &[String]) -> Result<i32, Error> {
+    let args = parse_args(args, &[], &[])?;
+    let mut session = Session::new();
+    let loaded = match args.positional.as_slice() {
+        [] => false,
+        [grammar] => session.load(grammar).map(|_| true)?,
+        _ => return Err(Error::usage("too many arguments")),
+    };
+    let stdout_error = |error: io::Error| Error { status: EXIT_ERROR, message: format!("<stdout>: {}", error) };
+    let stdin = io::stdin();
+    let interactive = stdin.is_terminal();
+    let mut out = io::stdout().lock();
+    if interactive {
+        writeln!(out, "type :help for help").map_err(stdout_error)?;
+    }
+    if loaded {
+        session.write_status(&mut out).map_err(stdout_error)?;
+    }
+
+    let mut line = String::new();
+    loop {
+        if interactive {
+            write!(out, "{}", PROMPT).and_then(|_| out.flush()).map_err(stdout_error)?;
+        }
+        line.clear();
+        if stdin.lock().read_line(&mut line).map_err(|error| Error::io(crate::STDIO, error))? == 0 {
+            break;
+        }
+        if !session.eval(&line, &mut out).map_err(stdout_error)? {
+            break;
+        }
+    }
+    Ok(0)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval(session: &mut Session, line: &str) -> String {
+        let mut out = Vec::new();
+        assert!(session.eval(line, &mut out).unwrap());
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_is_definition() {
+        assert!(is_definition("S <- 'a'"));
+        assert!(is_definition("  @Digit<-[0-9]"));
+        assert!(is_definition("S <- 'a"));
+        assert!(!is_definition("a b <- c"));
+        assert!(!is_definition("<- a"));
+        assert!(!is_definition("1+2"));
+    }
+
+    #[test]
+    fn test_session() {
+        let mut session = Session::new();
+        assert_eq!(eval(&mut session, "ab\n"), "error: no grammar, define some rules\n");
+        assert_eq!(eval(&mut session, "S <- {A+} 'b'\n"), "defined S\nerror: undefined rule A\n");
+        assert_eq!(eval(&mut session, "A <- 'a'\n"), "defined A\n");
+        assert_eq!(eval(&mut session, "aab!\n"), "match 3 of 4\n(S \"aa\")\n");
+        assert_eq!(eval(&mut session, "aac\n"), "  aac\n    ^ no match\n");
+
+        // redefining keeps the rule order, so S is still the start rule
+        assert_eq!(eval(&mut session, "A <- [ax]\n"), "defined A\n");
+        assert_eq!(eval(&mut session, "xab\n"), "match 3 of 3\n(S \"xa\")\n");
+        assert_eq!(eval(&mut session, ":rules\n"), "S <- {A+} 'b'\nA <- [ax]\n");
+        assert_eq!(eval(&mut session, "A <- [a\n"), "  A <- [a\n       ^ unterminated set\n");
+        assert_eq!(eval(&mut session, ":match A <- b\n"), "  A <- b\n  ^ no match\n");
+
+        assert_eq!(eval(&mut session, ":delete A\n"), "deleted A\nerror: undefined rule A\n");
+        assert_eq!(eval(&mut session, ":delete A\n"), "error: A is not defined\n");
+        assert_eq!(eval(&mut session, "@A <- [a] A <- @A\n"), "defined @A, A\n");
+        assert_eq!(eval(&mut session, ":match ab\n"), "match 2 of 2\n(S \"a\")\n");
+        assert_eq!(eval(&mut session, ":frobnicate\n"), "error: unknown command :frobnicate, see :help\n");
+        assert!(!session.eval(":quit\n", &mut Vec::new()).unwrap());
+    }
+
+    #[test]
+    fn test_load() {
+        let grammars = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("../grammars");
+        let mut session = Session::new();
+        let output = eval(&mut session, &format!(":load {}\n", grammars.join("arith.peg").display()));
+        assert!(output.starts_with("loaded "), "{}", output);
+        assert_eq!(eval(&mut session, "1\n"), "match 1 of 1\n(Term \"1\"\n  (Number \"1\"))\n");
+        assert!(eval(&mut session, ":load missing.peg\n").starts_with("error: missing.peg: "));
+        assert!(session.definitions.len() > 1);
+    }
+}
@@ -0,0 +1,437 @@
+//! Grammar test suites: the path of the grammar under test, relative to the
+//! suite file, then one case per line with an input and its expected outcome.
+//!
+//! ```text
+//! grammar arith.peg        # comments run to the end of the line
+//! "1+2"    match 3         # matches 3 bytes
+//! "1+2"    full            # matches the whole input
+//! "1+"     fail 2          # fails, with farthest failure at byte 2
+//! "x"      fail
+//! "1+2"    captures (Sum "1+2" (Num "1")
+//!                              (Num "2"))
+//! ```
+//!
+//! Capture trees are the first top level capture, with its name, text and
+//! nested captures, or `()` for none. They may span lines.
+
+use pegatexto_vm::bytecode::Bytecode;
+use pegatexto_vm::matcher::{try_match, try_match_captures_then, try_match_full, Capture, MatchError};
+
+use std::fmt;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct CaptureTree {
+    pub name: String,
+    pub text: String,
+    pub children: Vec<CaptureTree>,
+}
+
+impl CaptureTree {
+    fn write_indented(&self, f: &mut fmt::Formatter<'_>, indent: usize) -> fmt::Result {
+        write!(f, "{:indent$}({} {:?}", "", self.name, self.text, indent = indent)?;
+        for child in self.children.iter() {
+            writeln!(f)?;
+            child.write_indented(f, indent + 2)?;
+        }
+        write!(f, ")")
+    }
+}
+
+impl fmt::Display for CaptureTree {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.write_indented(f, 0)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Outcome {
+    Match(usize),
+    Full,
+    /// Failure with the farthest failure offset, any offset if `None`
+    Fail(Option<usize>),
+    Captures(Option<CaptureTree>),
+    /// Matcher error other than a failed match, never expected
+    Error(String),
+}
+
+impl Outcome {
+    /// Whether this actual outcome satisfies the `expected` one
+    pub fn satisfies(&self, expected: &Outcome) -> bool {
+        match (self, expected) {
+            (Outcome::Fail(_), Outcome::Fail(None)) => true,
+            _ => self == expected,
+        }
+    }
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Outcome::Match(len) => write!(f, "match {}", len),
+            Outcome::Full => write!(f, "full"),
+            Outcome::Fail(Some(offset)) => write!(f, "fail {}", offset),
+            Outcome::Fail(None) => write!(f, "fail"),
+            Outcome::Captures(Some(tree)) => {
+                writeln!(f, "captures")?;
+                tree.write_indented(f, 2)
+            },
+            Outcome::Captures(None) => write!(f, "captures ()"),
+            Outcome::Error(message) => write!(f, "error: {}", message),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Case {
+    pub line: usize,
+    pub input: String,
+    pub expected: Outcome,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Suite {
+    pub grammar: String,
+    pub cases: Vec<Case>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum SuiteErrorKind {
+    ExpectedGrammar,
+    ExpectedInput,
+    ExpectedOutcome,
+    InvalidString,
+    InvalidCaptureTree,
+    UnexpectedToken,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct SuiteError {
+    pub kind: SuiteErrorKind,
+    pub line: usize,
+}
+
+impl fmt::Display for SuiteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.kind {
+            SuiteErrorKind::ExpectedGrammar => write!(f, "expected `grammar <path>`"),
+            SuiteErrorKind::ExpectedInput => write!(f, "expected a quoted input"),
+            SuiteErrorKind::ExpectedOutcome => write!(f, "expected `match <len>`, `full`, `fail [offset]` or `captures <tree>`"),
+            SuiteErrorKind::InvalidString => write!(f, "invalid string"),
+            SuiteErrorKind::InvalidCaptureTree => write!(f, "expected `(name \"text\" children...)` or `()`"),
+            SuiteErrorKind::UnexpectedToken => write!(f, "unexpected token"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Word(String),
+    Str(String),
+    Open,
+    Close,
+    Newline,
+}
+
+fn tokenize(source: &str) -> Result<Vec<(Token, usize)>, SuiteError> {
+    let mut tokens = Vec::new();
+    let mut line = 1;
+    let mut chars = source.chars().peekable();
+    let invalid_string = |line| SuiteError { kind: SuiteErrorKind::InvalidString, line };
+    while let Some(c) = chars.next() {
+        match c {
+            '\n' => {
+                tokens.push((Token::Newline, line));
+                line += 1;
+            },
+            '#' => {
+                while chars.peek().is_some_and(|c| *c != '\n') {
+                    chars.next();
+                }
+            },
+            '(' => tokens.push((Token::Open, line)),
+            ')' => tokens.push((Token::Close, line)),
+            '"' => {
+                let mut s = String::new();
+                loop {
+                    match chars.next().ok_or_else(|| invalid_string(line))? {
+                        '"' => break,
+                        '\n' => return Err(invalid_string(line)),
+                        '\\' => s.push(match chars.next().ok_or_else(|| invalid_string(line))? {
+                            'n' => '\n',
+                            'r' => '\r',
+                            't' => '\t',
+                            '0' => '\0',
+                            'u' if chars.next() == Some('{') => {
+                                let hex: String = chars.by_ref().take_while(|c| *c != '}').collect();
+                                u32::from_str_radix(&hex, 16).ok().and_then(char::from_u32).ok_or_else(|| invalid_string(line))?
+                            },
+                            c @ '\\' | c @ '"' => c,
+                            _ => return Err(invalid_string(line)),
+                        }),
+                        c => s.push(c),
+                    }
+                }
+                tokens.push((Token::Str(s), line));
+            },
+            c if c.is_whitespace() => (),
+            c => {
+                let mut word = c.to_string();
+                while let Some(c) = chars.peek().filter(|c| !c.is_whitespace() && !"()\"#".contains(**c)) {
+                    word.push(*c);
+                    chars.next();
+                }
+                tokens.push((Token::Word(word), line));
+            },
+        }
+    }
+    tokens.push((Token::Newline, line));
+    Ok(tokens)
+}
+
+struct Tokens {
+    tokens: Vec<(Token, usize)>,
+    pos: usize,
+}
+
+impl Tokens {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|(token, _)| token)
+    }
+
+    fn line(&self) -> usize {
+        self.tokens.get(self.pos).or_else(|| self.tokens.last()).map_or(1, |(_, line)| *line)
+    }
+
+    fn error(&self, kind: SuiteErrorKind) -> SuiteError {
+        SuiteError { kind, line: self.line() }
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.peek().cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn skip_newlines(&mut self) {
+        while self.peek() == Some(&Token::Newline) {
+            self.pos += 1;
+        }
+    }
+
+    // Newlines are insignificant inside capture trees
+    fn capture_tree(&mut self) -> Result<Option<CaptureTree>, SuiteError> {
+        if self.next() != Some(Token::Open) {
+            return Err(self.error(SuiteErrorKind::InvalidCaptureTree));
+        }
+        self.skip_newlines();
+        let name = match self.next() {
+            Some(Token::Close) => return Ok(None),
+            Some(Token::Word(name)) => name,
+            _ => return Err(self.error(SuiteErrorKind::InvalidCaptureTree)),
+        };
+        self.skip_newlines();
+        let text = match self.next() {
+            Some(Token::Str(text)) => text,
+            _ => return Err(self.error(SuiteErrorKind::InvalidCaptureTree)),
+        };
+        let mut children = Vec::new();
+        loop {
+            self.skip_newlines();
+            match self.peek() {
+                Some(Token::Close) => break,
+                _ => children.push(self.capture_tree()?.ok_or_else(|| self.error(SuiteErrorKind::InvalidCaptureTree))?),
+            }
+        }
+        self.pos += 1;
+        Ok(Some(CaptureTree { name, text, children }))
+    }
+
+    fn outcome(&mut self) -> Result<Outcome, SuiteError> {
+        let word = match self.next() {
+            Some(Token::Word(word)) => word,
+            _ => return Err(self.error(SuiteErrorKind::ExpectedOutcome)),
+        };
+        let number = |tokens: &mut Tokens| match tokens.peek() {
+            Some(Token::Word(word)) => {
+                let n = word.parse().map_err(|_| tokens.error(SuiteErrorKind::ExpectedOutcome))?;
+                tokens.pos += 1;
+                Ok(Some(n))
+            },
+            _ => Ok(None),
+        };
+        match word.as_str() {
+            "match" => number(self)?.map(Outcome::Match).ok_or_else(|| self.error(SuiteErrorKind::ExpectedOutcome)),
+            "full" => Ok(Outcome::Full),
+            "fail" => Ok(Outcome::Fail(number(self)?)),
+            "captures" => Ok(Outcome::Captures(self.capture_tree()?)),
+            _ => Err(self.error(SuiteErrorKind::ExpectedOutcome)),
+        }
+    }
+}
+
+pub fn parse_suite(source: &str) -> Result<Suite, SuiteError> {
+    let mut tokens = Tokens { tokens: tokenize(source)?, pos: 0 };
+    tokens.skip_newlines();
+    let grammar = match (tokens.next(), tokens.next()) {
+        (Some(Token::Word(keyword)), Some(Token::Word(path))) | (Some(Token::Word(keyword)), Some(Token::Str(path)))
+            if keyword == "grammar" => path,
+        _ => return Err(tokens.error(SuiteErrorKind::ExpectedGrammar)),
+    };
+    let mut cases = Vec::new();
+    loop {
+        if tokens.next() != Some(Token::Newline) {
+            tokens.pos -= 1;
+            return Err(tokens.error(SuiteErrorKind::UnexpectedToken));
+        }
+        tokens.skip_newlines();
+        let line = tokens.line();
+        let input = match tokens.next() {
+            None => break,
+            Some(Token::Str(input)) => input,
+            _ => return Err(SuiteError { kind: SuiteErrorKind::ExpectedInput, line }),
+        };
+        let expected = tokens.outcome()?;
+        cases.push(Case { line, input, expected });
+    }
+    Ok(Suite { grammar, cases })
+}
+
+/// Action building the capture tree, naming captures by their id if there
+/// is no name for it
+pub fn capture_tree<'n>(capture_names: &'n [String]) -> impl Fn(&Capture, &[CaptureTree]) -> CaptureTree + 'n {
+    move |capture, children| CaptureTree {
+        name: capture_names.get(capture.id as usize).cloned().unwrap_or_else(|| capture.id.to_string()),
+        text: capture.text.to_string(),
+        children: children.to_vec(),
+    }
+}
+
+/// Runs a case, returning its actual outcome, of the same kind as expected
+pub fn run_case(bytecode: &Bytecode, capture_names: &[String], case: &Case) -> Outcome {
+    let failure = |error: MatchError| match error {
+        MatchError::NoMatch(offset) => Outcome::Fail(Some(offset)),
+        MatchError::TrailingInput(offset) => Outcome::Match(offset),
+        error => Outcome::Error(error.to_string()),
+    };
+    match case.expected {
+        Outcome::Full => try_match_full(bytecode, &case.input).map(|_| Outcome::Full).unwrap_or_else(failure),
+        Outcome::Captures(_) => {
+            let result = try_match_captures_then(bytecode, &case.input, capture_tree(capture_names));
+            result.map(|(_, tree)| Outcome::Captures(tree)).unwrap_or_else(failure)
+        },
+        _ => try_match(bytecode, &case.input).map(Outcome::Match).unwrap_or_else(failure),
+    }
+}
+
+/// Line diff of `expected` and `actual`, with `-` and `+` marking lines
+/// only in one of them
+pub fn diff(expected: &str, actual: &str) -> String {
+    let expected: Vec<&str> = expected.lines().collect();
+    let actual: Vec<&str> = actual.lines().collect();
+    // lcs[i][j]: longest common subsequence of expected[i..] and actual[j..]
+    let mut lcs = vec![vec![0; actual.len() + 1]; expected.len() + 1];
+    for i in (0..expected.len()).rev() {
+        for j in (0..actual.len()).rev() {
+            lcs[i][j] = if expected[i] == actual[j] { lcs[i + 1][j + 1] + 1 } else { lcs[i + 1][j].max(lcs[i][j + 1]) };
+        }
+    }
+    let mut out = String::new();
+    let (mut i, mut j) = (0, 0);
+    while i < expected.len() || j < actual.len() {
+        if i < expected.len() && j < actual.len() && expected[i] == actual[j] {
+            out.push_str(&format!("  {}\n", expected[i]));
+            i += 1;
+            j += 1;
+        }
+        else if j == actual.len() || (i < expected.len() && lcs[i + 1][j] >= lcs[i][j + 1]) {
+            out.push_str(&format!("- {}\n", expected[i]));
+            i += 1;
+        }
+        else {
+            out.push_str(&format!("+ {}\n", actual[j]));
+            j += 1;
+        }
+    }
+    out
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pegatexto_compiler::Compiler;
+    use pegatexto_compiler::syntax::parse_grammar;
+
+    const SUITE: &str = r#"
+        # arithmetic
+        grammar "arith.peg"
+        "1+2"     match 3
+        "1+2\n"   full
+        "+1"      fail 0
+        "x"       fail
+        "1+23"    captures (Sum "1+23"
+                              (Num "1") (Num "23"))
+    "#;
+
+    fn tree(name: &str, text: &str, children: Vec<CaptureTree>) -> CaptureTree {
+        CaptureTree { name: name.to_string(), text: text.to_string(), children }
+    }
+
+    #[test]
+    fn test_parse_suite() {
+        let suite = parse_suite(SUITE).unwrap();
+        assert_eq!(suite.grammar, "arith.peg");
+        let cases: Vec<_> = suite.cases.iter().map(|case| (case.line, case.input.as_str(), &case.expected)).collect();
+        let sum = tree("Sum", "1+23", vec![tree("Num", "1", vec![]), tree("Num", "23", vec![])]);
+        assert_eq!(cases, vec![
+            (4, "1+2", &Outcome::Match(3)),
+            (5, "1+2\n", &Outcome::Full),
+            (6, "+1", &Outcome::Fail(Some(0))),
+            (7, "x", &Outcome::Fail(None)),
+            (8, "1+23", &Outcome::Captures(Some(sum))),
+        ]);
+        let none = parse_suite("grammar g\n\"\" captures ()").unwrap();
+        assert_eq!(none.cases[0].expected, Outcome::Captures(None));
+
+        let error = |source| parse_suite(source).err().map(|error| (error.kind, error.line));
+        assert_eq!(error("\n\"a\" full"), Some((SuiteErrorKind::ExpectedGrammar, 2)));
+        assert_eq!(error("grammar g\nfull"), Some((SuiteErrorKind::ExpectedInput, 2)));
+        assert_eq!(error("grammar g\n\"a\" match x"), Some((SuiteErrorKind::ExpectedOutcome, 2)));
+        assert_eq!(error("grammar g\n\"a\" full full"), Some((SuiteErrorKind::UnexpectedToken, 2)));
+        assert_eq!(error("grammar g\n\"a\\q\" full"), Some((SuiteErrorKind::InvalidString, 2)));
+        assert_eq!(error("grammar g\n\"a\" captures (x y)"), Some((SuiteErrorKind::InvalidCaptureTree, 2)));
+    }
+
+    #[test]
+    fn test_run_case() {
+        let grammar = parse_grammar("Sum <- {Num ('+' Num)*} '\\n'?  Num <- {[0-9]+}").unwrap();
+        let mut compiler = Compiler::new();
+        compiler.compile_parsed(&grammar).unwrap();
+        let names = compiler.capture_names().to_vec();
+        let bytecode = compiler.emit();
+        for case in parse_suite(SUITE).unwrap().cases.iter() {
+            let actual = run_case(&bytecode, &names, case);
+            assert!(actual.satisfies(&case.expected), "line {}: {}", case.line, actual);
+        }
+
+        let case = |input: &str, expected| Case { line: 1, input: input.to_string(), expected };
+        assert_eq!(run_case(&bytecode, &names, &case("1+2x", Outcome::Full)), Outcome::Match(3));
+        assert_eq!(run_case(&bytecode, &names, &case("1+2x", Outcome::Match(4))), Outcome::Match(3));
+        assert_eq!(run_case(&bytecode, &names, &case("+", Outcome::Captures(None))), Outcome::Fail(Some(0)));
+    }
+
+    #[test]
+    fn test_diff() {
+        let expected = Outcome::Captures(Some(tree("Sum", "1+2", vec![tree("Num", "1", vec![]), tree("Num", "2", vec![])])));
+        let actual = Outcome::Captures(Some(tree("Sum", "1+2", vec![tree("Num", "1+2", vec![])])));
+        assert_eq!(diff(&expected.to_string(), &actual.to_string()), [
+            "  captures",
+            "    (Sum \"1+2\"",
+            "-     (Num \"1\")",
+            "-     (Num \"2\"))",
+            "+     (Num \"1+2\"))",
+            "",
+        ].join("\n"));
+        assert_eq!(diff("fail 2", "match 1"), "- fail 2\n+ match 1\n");
+    }
+}
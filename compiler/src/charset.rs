@@ -0,0 +1,111 @@
+use pegatexto_vm::bytecode::charset::Charset;
+use pegatexto_vm::grammar::character_class::CharacterClass;
+use pegatexto_vm::grammar::expression::Expression;
+use pegatexto_vm::grammar::unicode::case_variants;
+
+/// Test of a single char against a charset, matching the chars out of the
+/// charset instead when negated
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CharsetTest {
+    pub charset: Charset,
+    pub negated: bool,
+}
+
+impl CharsetTest {
+    fn positive(charset: Charset) -> CharsetTest {
+        CharsetTest { charset, negated: false }
+    }
+
+    pub fn union(&self, other: &CharsetTest) -> CharsetTest {
+        match (self.negated, other.negated) {
+            (false, false) => CharsetTest::positive(self.charset.union(&other.charset)),
+            (true, false) => CharsetTest { charset: self.charset.difference(&other.charset), negated: true },
+            (false, true) => CharsetTest { charset: other.charset.difference(&self.charset), negated: true },
+            (true, true) => CharsetTest { charset: self.charset.intersection(&other.charset), negated: true },
+        }
+    }
+}
+
+fn string_charset(s: &str) -> Option<Charset> {
+    let mut charset = Charset::new();
+    for c in s.chars() {
+        if !charset.insert_char(c) {
+            return None;
+        }
+    }
+    Some(charset)
+}
+
+/// Charset test that matches exactly what `expr` matches, if `expr` always
+/// consumes a single char from U+0000 to U+00FF.
+pub fn charset_test(expr: &Expression) -> Option<CharsetTest> {
+    match expr {
+        Expression::Char(c) => {
+            let mut charset = Charset::new();
+            if charset.insert_char(*c) { Some(CharsetTest::positive(charset)) } else { None }
+        },
+        Expression::Set(s) => string_charset(s).map(CharsetTest::positive),
+        Expression::CharNoCase(c) => string_charset(&case_variants(*c).into_iter().collect::<String>()).map(CharsetTest::positive),
+        Expression::SetNoCase(s) => string_charset(&s.chars().flat_map(case_variants).collect::<String>()).map(CharsetTest::positive),
+        Expression::InverseSet(s) => string_charset(s).map(|charset| CharsetTest { charset, negated: true }),
+        // ranges match bytes, so only ASCII ranges match whole chars
+        Expression::Range(min, max) if min.is_ascii() && max.is_ascii() => {
+            let mut charset = Charset::new();
+            charset.insert_range(*min as u8, *max as u8);
+            Some(CharsetTest::positive(charset))
+        },
+        Expression::Class(class) => {
+            use CharacterClass::*;
+            // ASCII only classes, whose negation matches every other char
+            let positive = if class.is_negated() { class.negated() } else { *class };
+            match positive {
+                Digit | Graphic | Punctuation | Hexadigit => {
+                    let mut charset = Charset::new();
+                    (0..128u8).filter(|b| positive.is_member(char::from(*b))).for_each(|b| charset.insert(b));
+                    Some(CharsetTest { charset, negated: class.is_negated() })
+                },
+                _ => None,
+            }
+        },
+        Expression::Choice(es) if !es.is_empty() => {
+            let mut tests = es.iter().map(charset_test);
+            let first = tests.next()??;
+            tests.try_fold(first, |union, test| Some(union.union(&test?)))
+        },
+        _ => None,
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use Expression::*;
+
+    fn test(chars: &str, negated: bool) -> Option<CharsetTest> {
+        Some(CharsetTest { charset: string_charset(chars).unwrap(), negated })
+    }
+
+    #[test]
+    fn test_charset_test() {
+        assert_eq!(charset_test(&Set("abc".to_string())), test("abc", false));
+        assert_eq!(charset_test(&InverseSet("abc".to_string())), test("abc", true));
+        assert_eq!(charset_test(&Range('a', 'e')), test("abcde", false));
+        assert_eq!(charset_test(&Class(CharacterClass::Digit)), test("0123456789", false));
+        assert_eq!(charset_test(&(Char('x') / Range('0', '2') / Set("-+".to_string()))), test("x012-+", false));
+        assert_eq!(charset_test(&(InverseSet("abc".to_string()) / Set("ab".to_string()))), test("c", true));
+        assert_eq!(charset_test(&(InverseSet("abc".to_string()) / InverseSet("bcd".to_string()))), test("bc", true));
+        assert_eq!(charset_test(&Char('é')), test("é", false));
+        assert_eq!(charset_test(&Set("aα".to_string())), None);
+        assert_eq!(charset_test(&Class(CharacterClass::NotDigit)), test("0123456789", true));
+        assert_eq!(charset_test(&(Class(CharacterClass::NotDigit) / Char('1'))), test("023456789", true));
+        assert_eq!(charset_test(&CharNoCase('a')), test("Aa", false));
+        assert_eq!(charset_test(&SetNoCase("é1".to_string())), test("Éé1", false));
+        // 'K' is also a case variant of KELVIN SIGN
+        assert_eq!(charset_test(&SetNoCase("xk".to_string())), None);
+        assert_eq!(charset_test(&Class(CharacterClass::Alphabetic)), None);
+        assert_eq!(charset_test(&Class(CharacterClass::NotAlphabetic)), None);
+        assert_eq!(charset_test(&(Char('x') / Literal("yz".to_string()))), None);
+        assert_eq!(charset_test(&Any), None);
+    }
+}
@@ -0,0 +1,152 @@
+use pegatexto_vm::bytecode::charset::Charset;
+use pegatexto_vm::bytecode::class_set::{ClassItem, ClassSet};
+use pegatexto_vm::grammar::character_class::CharacterClass;
+use pegatexto_vm::grammar::expression::Expression;
+use pegatexto_vm::grammar::unicode::case_variants;
+
+use std::collections::HashMap;
+
+const MAX_ITEMS: usize = 255;
+
+/// A named class lowered to a single test: a charset for chars up to
+/// U+00FF and class items for the chars past it.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ClassDefinition {
+    pub charset: Charset,
+    pub items: Vec<ClassItem>,
+}
+
+// Chars past the charset range that may be part of `item`
+fn beyond_charset(item: ClassItem) -> Option<ClassItem> {
+    use CharacterClass::*;
+    match item {
+        ClassItem::Range(_, max) if (max as u32) < 0x100 => None,
+        ClassItem::Range(min, max) => Some(ClassItem::Range(min.max('\u{100}'), max)),
+        ClassItem::Class(Digit) | ClassItem::Class(Graphic) | ClassItem::Class(Punctuation) | ClassItem::Class(Hexadigit) => None,
+        ClassItem::Class(NotDigit) | ClassItem::Class(NotGraphic) | ClassItem::Class(NotPunctuation) | ClassItem::Class(NotHexadigit) => {
+            Some(ClassItem::Range('\u{100}', char::MAX))
+        },
+        _ => Some(item),
+    }
+}
+
+impl ClassDefinition {
+    fn from_item(item: ClassItem) -> ClassDefinition {
+        let mut definition = ClassDefinition::default();
+        for b in 0..=255u8 {
+            if item.is_member(char::from(b)) {
+                definition.charset.insert(b);
+            }
+        }
+        definition.items.extend(beyond_charset(item));
+        definition
+    }
+
+    fn union(mut self, other: ClassDefinition) -> ClassDefinition {
+        self.charset = self.charset.union(&other.charset);
+        for item in other.items {
+            if !self.items.contains(&item) {
+                self.items.push(item);
+            }
+        }
+        self
+    }
+
+    /// Lowers `expr` to a class definition, if it always matches a single
+    /// char: chars, sets, ranges, classes, other named classes and choices
+    /// of them.
+    pub fn lower(expr: &Expression, classes: &HashMap<String, ClassDefinition>) -> Option<ClassDefinition> {
+        let definition = match expr {
+            Expression::Char(c) => ClassDefinition::from_item(ClassItem::Range(*c, *c)),
+            Expression::Set(s) => s.chars()
+                .map(|c| ClassDefinition::from_item(ClassItem::Range(c, c)))
+                .fold(ClassDefinition::default(), ClassDefinition::union),
+            Expression::CharNoCase(c) => case_variants(*c).into_iter()
+                .map(|c| ClassDefinition::from_item(ClassItem::Range(c, c)))
+                .fold(ClassDefinition::default(), ClassDefinition::union),
+            Expression::SetNoCase(s) => s.chars().flat_map(case_variants)
+                .map(|c| ClassDefinition::from_item(ClassItem::Range(c, c)))
+                .fold(ClassDefinition::default(), ClassDefinition::union),
+            Expression::InverseSet(s) => {
+                let mut charset = Charset::new();
+                for c in s.chars() {
+                    if !charset.insert_char(c) {
+                        return None;
+                    }
+                }
+                ClassDefinition {
+                    charset: Charset::from_bytes([255; 32]).difference(&charset),
+                    items: vec![ClassItem::Range('\u{100}', char::MAX)],
+                }
+            },
+            Expression::Range(min, max) if min <= max => ClassDefinition::from_item(ClassItem::Range(*min, *max)),
+            Expression::Class(class) => ClassDefinition::from_item(ClassItem::Class(*class)),
+            Expression::UnicodeClass(class) => ClassDefinition::from_item(ClassItem::UnicodeClass(*class)),
+            Expression::NamedClass(name) => classes.get(name)?.clone(),
+            Expression::Choice(es) => {
+                let mut definition = ClassDefinition::default();
+                for e in es.iter() {
+                    definition = definition.union(ClassDefinition::lower(e, classes)?);
+                }
+                definition
+            },
+            _ => return None,
+        };
+        if definition.items.len() > MAX_ITEMS { None } else { Some(definition) }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        ClassSet::encode(&self.charset, &self.items)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pegatexto_vm::grammar::unicode::UnicodeClass;
+    use std::convert::TryFrom;
+    use Expression::*;
+
+    fn contains(definition: &ClassDefinition, c: char) -> bool {
+        match u8::try_from(c) {
+            Ok(b) => definition.charset.contains(b),
+            Err(_) => definition.items.iter().any(|item| item.is_member(c)),
+        }
+    }
+
+    #[test]
+    fn test_lower() {
+        let mut classes = HashMap::new();
+        let ident_start = Class(CharacterClass::Alphabetic) / Char('_') / Char('$');
+        let ident_start = ClassDefinition::lower(&ident_start, &classes).unwrap();
+        assert_eq!(ident_start.items, vec![ClassItem::Class(CharacterClass::Alphabetic)]);
+        for c in ['a', 'Z', 'é', 'ж', '_', '$'] {
+            assert!(contains(&ident_start, c), "{:?}", c);
+        }
+        for c in ['1', '-', '\u{2192}'] {
+            assert!(!contains(&ident_start, c), "{:?}", c);
+        }
+        classes.insert("IdentStart".to_string(), ident_start);
+
+        let hex = Class(CharacterClass::Hexadigit) / Range('α', 'γ') / Set("'".to_string());
+        let hex = ClassDefinition::lower(&hex, &classes).unwrap();
+        assert_eq!(hex.items, vec![ClassItem::Range('α', 'γ')]);
+
+        let kelvin = ClassDefinition::lower(&SetNoCase("k".to_string()), &classes).unwrap();
+        assert!(contains(&kelvin, 'K') && contains(&kelvin, '\u{212A}') && !contains(&kelvin, 'x'));
+
+        let not_digit = Class(CharacterClass::NotDigit) / NamedClass("IdentStart".to_string());
+        let not_digit = ClassDefinition::lower(&not_digit, &classes).unwrap();
+        assert!(contains(&not_digit, 'x') && contains(&not_digit, '\u{10FFFF}') && !contains(&not_digit, '7'));
+
+        let greek = UnicodeClass::from_name("Greek").unwrap();
+        let not_abc = InverseSet("abc".to_string()) / UnicodeClass(greek);
+        let not_abc = ClassDefinition::lower(&not_abc, &classes).unwrap();
+        assert!(contains(&not_abc, 'd') && contains(&not_abc, 'λ') && !contains(&not_abc, 'a'));
+
+        assert_eq!(ClassDefinition::lower(&NamedClass("Undefined".to_string()), &classes), None);
+        assert_eq!(ClassDefinition::lower(&(Char('a') / Literal("bc".to_string())), &classes), None);
+        assert_eq!(ClassDefinition::lower(&InverseSet("α".to_string()), &classes), None);
+    }
+}
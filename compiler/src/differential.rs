@@ -0,0 +1,200 @@
+use crate::{CompileError, CompileOptions, Compiler};
+
+use pegatexto_vm::bytecode::verifier::verify;
+use pegatexto_vm::grammar::character_class::CharacterClass;
+use pegatexto_vm::grammar::expression::Expression;
+use pegatexto_vm::interpreter::Interpreter;
+use pegatexto_vm::matcher::{try_match_captures_then, Capture};
+
+use std::fmt;
+
+const RULE_NAMES: [&str; 4] = ["A", "B", "C", "D"];
+const CAPTURE_NAMES: [&str; 2] = ["x", "y"];
+const ALPHABET: [char; 7] = ['a', 'b', 'A', 'é', 'α', '1', ' '];
+const CLASS_NAME: &str = "V";
+
+/// Small xorshift generator, so runs are reproducible from their seed
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Rng {
+        Rng(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    pub fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+
+    pub fn pick<T: Copy>(&mut self, items: &[T]) -> T {
+        items[self.below(items.len())]
+    }
+}
+
+/// Class available to generated grammars as `Expression::NamedClass("V")`
+pub fn class_definition() -> (&'static str, Expression) {
+    (CLASS_NAME, Expression::Set("aα".to_string()) / Expression::CharNoCase('b') / Expression::Class(CharacterClass::Digit))
+}
+
+fn random_string(rng: &mut Rng, len: usize) -> String {
+    (0..len).map(|_| rng.pick(&ALPHABET)).collect()
+}
+
+// Terminals consume a char or more whenever they match
+fn random_terminal(rng: &mut Rng) -> Expression {
+    match rng.below(12) {
+        0 | 1 => Expression::Char(rng.pick(&ALPHABET)),
+        2 => Expression::CharNoCase(rng.pick(&ALPHABET)),
+        3 => Expression::Literal(random_string(rng, 2)),
+        4 => Expression::LiteralNoCase(random_string(rng, 2)),
+        5 => Expression::Set(random_string(rng, 2)),
+        6 => Expression::SetNoCase(random_string(rng, 2)),
+        7 => Expression::InverseSet(random_string(rng, 2)),
+        8 => Expression::Range(rng.pick(&ALPHABET), rng.pick(&ALPHABET)),
+        9 => Expression::Class(rng.pick(&[
+            CharacterClass::Alphabetic, CharacterClass::Digit, CharacterClass::NotDigit, CharacterClass::Lowercase, CharacterClass::Uppercase,
+        ])),
+        10 => Expression::NamedClass(CLASS_NAME.to_string()),
+        _ => Expression::Any,
+    }
+}
+
+fn random_bounded_length(rng: &mut Rng) -> Expression {
+    match rng.below(5) {
+        0 => random_terminal(rng),
+        1 => random_terminal(rng) + random_terminal(rng),
+        2 => random_terminal(rng) / (random_terminal(rng) + random_terminal(rng)),
+        3 => random_terminal(rng) + (random_terminal(rng)^rng.pick(&[-2, -1])),
+        _ => (random_terminal(rng) / (random_terminal(rng) + random_terminal(rng))) + random_terminal(rng),
+    }
+}
+
+fn random_expression(rng: &mut Rng, depth: usize, rule: usize) -> Expression {
+    if depth == 0 {
+        return random_terminal(rng);
+    }
+    let sub = |rng: &mut Rng| random_expression(rng, depth - 1, rule);
+    match rng.below(14) {
+        0 => sub(rng) + sub(rng),
+        1 => sub(rng) + sub(rng) + sub(rng),
+        2 => sub(rng) / sub(rng),
+        3 => sub(rng) / sub(rng) / sub(rng),
+        4 => (random_terminal(rng) + sub(rng))^rng.pick(&[-3, -2, -1, 0, 1, 2]),
+        // bodies that may match empty end the loop when they do
+        5 => sub(rng)^rng.pick(&[-3, -2, -1, 0, 1, 2]),
+        6 => -sub(rng),
+        7 => !sub(rng),
+        8 => Expression::Behind(Box::new(random_bounded_length(rng))),
+        9 => Expression::NotBehind(Box::new(random_bounded_length(rng))),
+        10 => sub(rng) >> rng.pick(&CAPTURE_NAMES),
+        // rules only call the ones after them, so there is no left recursion
+        11 if rule + 1 < RULE_NAMES.len() => {
+            Expression::NonTerminal(RULE_NAMES[rule + 1 + rng.below(RULE_NAMES.len() - rule - 1)].to_string())
+        },
+        12 => sub(rng) + Expression::EndOfInput,
+        _ => random_terminal(rng),
+    }
+}
+
+pub fn random_grammar(rng: &mut Rng) -> Vec<(&'static str, Expression)> {
+    (0..RULE_NAMES.len()).map(|rule| {
+        let expr = random_expression(rng, 3, rule);
+        // captures around rules nest whenever rules call each other
+        let expr = if rng.below(2) == 0 { expr >> rng.pick(&CAPTURE_NAMES) } else { expr };
+        (RULE_NAMES[rule], expr)
+    }).collect()
+}
+
+pub fn random_input(rng: &mut Rng) -> String {
+    let len = rng.below(8);
+    random_string(rng, len)
+}
+
+/// Match length and rendered captures, or `None` when there's no match
+type Outcome = Option<(usize, Option<String>)>;
+
+fn render(name: &str, capture: &Capture, args: &[String]) -> String {
+    format!("{}{:?}[{}]", name, capture.text, args.join(" "))
+}
+
+#[derive(Debug)]
+pub enum Mismatch {
+    Compile(CompileOptions, CompileError),
+    Verify(CompileOptions, String),
+    Match {
+        options: CompileOptions,
+        input: String,
+        expected: Outcome,
+        actual: Outcome,
+    },
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Mismatch::Compile(options, error) => write!(f, "compile error with {:?}: {:?}", options, error),
+            Mismatch::Verify(options, error) => write!(f, "invalid bytecode with {:?}: {}", options, error),
+            Mismatch::Match { options, input, expected, actual } => {
+                write!(f, "with {:?}, matching {:?} gave {:?} instead of {:?}", options, input, actual, expected)
+            },
+        }
+    }
+}
+
+/// Matches every input with the interpreter and with bytecode compiled
+/// plain and optimized, reporting the first difference.
+pub fn check(grammar: &[(&str, Expression)], inputs: &[String]) -> Result<(), Mismatch> {
+    let class = class_definition();
+    let mut interpreter = Interpreter::new(grammar);
+    interpreter.define_class(class.0, &class.1);
+    let configurations = [
+        CompileOptions { optimize: false, ..CompileOptions::default() },
+        CompileOptions { optimize: true, inline_threshold: 0 },
+        CompileOptions::default(),
+    ];
+    for options in configurations.iter() {
+        let mut compiler = Compiler::with_options(*options);
+        compiler.define_class(class.0, &class.1).map_err(|error| Mismatch::Compile(*options, error))?;
+        compiler.compile_grammar(grammar).map_err(|error| Mismatch::Compile(*options, error))?;
+        let bytecode = compiler.emit();
+        verify(&bytecode).map_err(|error| Mismatch::Verify(*options, error.to_string()))?;
+        for input in inputs.iter() {
+            let names = interpreter.capture_names();
+            let expected = interpreter.try_match_captures_then(input, |capture, args| {
+                render(names[capture.id as usize], capture, args)
+            }).ok();
+            let names = compiler.capture_names();
+            let actual = try_match_captures_then(&bytecode, input, |capture, args| {
+                render(&names[capture.id as usize], capture, args)
+            }).ok();
+            if actual != expected {
+                return Err(Mismatch::Match { options: *options, input: input.clone(), expected, actual });
+            }
+        }
+    }
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_differential() {
+        for seed in 0..400 {
+            let mut rng = Rng::new(seed);
+            let grammar = random_grammar(&mut rng);
+            let inputs: Vec<String> = (0..24).map(|_| random_input(&mut rng)).collect();
+            if let Err(mismatch) = check(&grammar, &inputs) {
+                panic!("seed {}: {}", seed, mismatch);
+            }
+        }
+    }
+}
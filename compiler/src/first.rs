@@ -0,0 +1,133 @@
+use pegatexto_vm::grammar::character_class::CharacterClass;
+use pegatexto_vm::grammar::expression::Expression;
+use pegatexto_vm::grammar::unicode::case_variants;
+
+use std::cell::RefCell;
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+/// Computes head sets: the chars an expression needs to see next to have
+/// any chance of matching. Expressions that may succeed without consuming
+/// input, or that accept chars not easily enumerated, have no head set.
+pub struct FirstSets<'g> {
+    rules: HashMap<&'g str, &'g Expression>,
+    cache: RefCell<HashMap<&'g str, Option<BTreeSet<char>>>>,
+}
+
+fn class_chars(class: CharacterClass) -> Option<BTreeSet<char>> {
+    use CharacterClass::*;
+    match class {
+        Digit | Graphic | Punctuation | Hexadigit => {
+            Some((0..128u8).map(char::from).filter(|c| class.is_member(*c)).collect())
+        },
+        _ => None,
+    }
+}
+
+impl<'g> FirstSets<'g> {
+    pub fn new(grammar: &'g [(&'g str, Expression)]) -> FirstSets<'g> {
+        FirstSets {
+            rules: grammar.iter().map(|(name, expr)| (*name, expr)).collect(),
+            cache: RefCell::new(HashMap::new()),
+        }
+    }
+
+    pub fn rule(&self, name: &str) -> Option<&'g Expression> {
+        self.rules.get(name).copied()
+    }
+
+    pub fn head(&self, expr: &Expression) -> Option<BTreeSet<char>> {
+        self.head_visiting(expr, &mut HashSet::new())
+    }
+
+    fn head_visiting(&self, expr: &Expression, visiting: &mut HashSet<String>) -> Option<BTreeSet<char>> {
+        match expr {
+            Expression::Char(c) => Some(std::iter::once(*c).collect()),
+            Expression::Literal(s) => s.chars().next().map(|c| std::iter::once(c).collect()),
+            Expression::CharNoCase(c) => Some(case_variants(*c).into_iter().collect()),
+            Expression::LiteralNoCase(s) => s.chars().next().map(|c| case_variants(c).into_iter().collect()),
+            Expression::SetNoCase(s) => Some(s.chars().flat_map(case_variants).collect()),
+            Expression::Class(class) => class_chars(*class),
+            Expression::Set(s) => Some(s.chars().collect()),
+            Expression::Range(min, max) if (*max as u32).saturating_sub(*min as u32) < 128 => Some((*min..=*max).collect()),
+            Expression::NonTerminal(name) => {
+                if let Some(head) = self.cache.borrow().get(name.as_str()) {
+                    return head.clone();
+                }
+                let (name, rule) = self.rules.get_key_value(name.as_str())?;
+                // left recursion, which never matches anyway
+                if !visiting.insert(name.to_string()) {
+                    return None;
+                }
+                let head = self.head_visiting(rule, visiting);
+                visiting.remove(*name);
+                self.cache.borrow_mut().insert(name, head.clone());
+                head
+            },
+            Expression::Quantifier(e, n) if *n >= 1 => self.head_visiting(e, visiting),
+            Expression::And(e) | Expression::Capture(e, _) => {
+                self.head_visiting(e, visiting)
+            },
+            Expression::Sequence(es) => {
+                for e in es.iter() {
+                    match (self.head_visiting(e, visiting), e) {
+                        (Some(head), _) => return Some(head),
+                        // predicates don't consume, so the next expression decides
+                        (None, Expression::Not(_)) | (None, Expression::Behind(_)) | (None, Expression::NotBehind(_)) => continue,
+                        (None, _) => return None,
+                    }
+                }
+                None
+            },
+            Expression::Choice(es) => {
+                let mut heads = BTreeSet::new();
+                for e in es.iter() {
+                    heads.extend(self.head_visiting(e, visiting)?);
+                }
+                Some(heads)
+            },
+            _ => None,
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use Expression::*;
+
+    fn nt(name: &str) -> Expression {
+        NonTerminal(name.to_string())
+    }
+
+    fn chars(s: &str) -> Option<BTreeSet<char>> {
+        Some(s.chars().collect())
+    }
+
+    #[test]
+    fn test_head() {
+        let grammar = vec![
+            ("Keyword", Literal("if".to_string()) / Literal("else".to_string()) / Literal("while".to_string())),
+            ("Number", (Char('-')^(-1)) + (Class(CharacterClass::Digit)^1)),
+            ("Digits", Class(CharacterClass::Digit)^1),
+            ("Word", !nt("Keyword") + (Class(CharacterClass::Alphabetic)^1)),
+            ("Call", !nt("Keyword") + nt("Digits") + Char('(')),
+            ("Left", nt("Left") + Char('x')),
+        ];
+        let first = FirstSets::new(&grammar);
+        assert_eq!(first.head(&nt("Keyword")), chars("iew"));
+        assert_eq!(first.head(&nt("Number")), None);
+        assert_eq!(first.head(&nt("Digits")), chars("0123456789"));
+        assert_eq!(first.head(&nt("Word")), None);
+        assert_eq!(first.head(&nt("Call")), chars("0123456789"));
+        assert_eq!(first.head(&nt("Left")), None);
+        assert_eq!(first.head(&nt("Undefined")), None);
+        assert_eq!(first.head(&(-Char('a') + Any)), chars("a"));
+        assert_eq!(first.head(&(Range('a', 'c') >> "abc")), chars("abc"));
+        assert_eq!(first.head(&Range('α', 'γ')), chars("αβγ"));
+        assert_eq!(first.head(&Range('\u{0}', '\u{10ffff}')), None);
+        assert_eq!(first.head(&Literal(String::new())), None);
+        assert_eq!(first.head(&(LiteralNoCase("if".to_string()) / SetNoCase("x-".to_string()))), chars("-IXix"));
+        assert_eq!(first.head(&(Set("ab".to_string()) / Any)), None);
+    }
+}
@@ -0,0 +1,189 @@
+//! Canonical grammar text: one definition per line, printed with the least
+//! parentheses by `Expression`'s `Display`.
+
+use crate::syntax::{parse_with_comments, Grammar, SyntaxError};
+
+use pegatexto_vm::grammar::expression::Expression;
+
+use std::fmt::Write;
+use std::ops::Range;
+
+fn write_definition(out: &mut String, name: &str, is_class: bool, expr: &Expression) {
+    let _ = write!(out, "{}{} <- {}", if is_class { "@" } else { "" }, name, expr.display_in_rule(name));
+}
+
+/// Formats a grammar built in code, classes first
+pub fn format_grammar(grammar: &Grammar) -> String {
+    let mut out = String::new();
+    for (name, expr) in grammar.classes.iter() {
+        write_definition(&mut out, name, true, expr);
+        out.push('\n');
+    }
+    for (name, expr) in grammar.rules.iter() {
+        write_definition(&mut out, name, false, expr);
+        out.push('\n');
+    }
+    out
+}
+
+/// Formats grammar source, keeping the definition order, the comments and
+/// single blank lines between groups. Comments inside a definition, which
+/// now takes one line, move above it.
+pub fn format_source(source: &str) -> Result<String, SyntaxError> {
+    let (definitions, comments) = parse_with_comments(source)?;
+    let mut out = String::new();
+    // end of the last item written, for blank lines
+    let mut last_end = None;
+    let mut write_gap = |out: &mut String, start: usize, end: usize| {
+        if last_end.is_some_and(|last_end| source[last_end..start].matches('\n').count() > 1) {
+            out.push('\n');
+        }
+        last_end = Some(end);
+    };
+    let mut comments = comments.into_iter().peekable();
+    for (i, definition) in definitions.iter().enumerate() {
+        while let Some(comment) = comments.next_if(|comment| comment.start < definition.span.start) {
+            write_gap(&mut out, comment.start, comment.end);
+            out.push_str(source[comment].trim_end());
+            out.push('\n');
+        }
+        let mut inner = Vec::new();
+        while let Some(comment) = comments.next_if(|comment| comment.start < definition.span.end) {
+            inner.push(source[comment].trim_end());
+        }
+        write_gap(&mut out, definition.span.start, definition.span.end);
+        for comment in inner {
+            out.push_str(comment);
+            out.push('\n');
+        }
+        write_definition(&mut out, definition.name, definition.is_class, &definition.expr);
+        let end = definition.span.end;
+        let next_start = definitions.get(i + 1).map_or(source.len(), |next| next.span.start);
+        let trailing = |comment: &Range<usize>| comment.start < next_start && !source[end..comment.start].contains('\n');
+        if let Some(comment) = comments.next_if(trailing) {
+            out.push(' ');
+            out.push_str(source[comment.clone()].trim_end());
+            write_gap(&mut out, comment.end, comment.end);
+        }
+        out.push('\n');
+    }
+    for comment in comments {
+        write_gap(&mut out, comment.start, comment.end);
+        out.push_str(source[comment].trim_end());
+        out.push('\n');
+    }
+    Ok(out)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::syntax::parse_grammar;
+    use crate::Compiler;
+
+    use Expression::*;
+
+    fn compiled(source: &str) -> Vec<u8> {
+        let mut compiler = Compiler::new();
+        compiler.compile_parsed(&parse_grammar(source).unwrap()).unwrap();
+        compiler.emit().to_vec()
+    }
+
+    #[test]
+    fn test_format_source() {
+        let source = "\
+# header
+
+Exp<-Sp ( Term ) ( ('+' / \"-\") Term)*  # sums
+  # of terms
+Term <- {Factor
+    # products
+    ( [*/] Factor )*}
+
+
+@Digit <- [0-9]   Number <- {n: @Digit+} # trailing
+# end
+";
+        let formatted = "\
+# header
+
+Exp <- Sp Term (('+' / '-') Term)* # sums
+# of terms
+# products
+Term <- {Factor ([*/] Factor)*}
+
+@Digit <- [0-9]
+Number <- {n: @Digit+} # trailing
+# end
+";
+        assert_eq!(format_source(source).unwrap(), formatted);
+        assert_eq!(format_source(formatted).unwrap(), formatted);
+        let sets = "Ident <- [a-zA-Z_] [a-zA-Z0-9_]*\nOther <- [^0-9a-f\\s] [-+\\p{Greek}] [^a-z]\n";
+        assert_eq!(format_source(sets).unwrap(), sets);
+        assert_eq!(format_source("S <- [_a-z] / [0-9]").unwrap(), "S <- [_a-z] / [0-9]\n");
+        assert_eq!(format_source("").unwrap(), "");
+        assert!(format_source("S <- (").is_err());
+    }
+
+    #[test]
+    fn test_format_grammar() {
+        let grammar = Grammar {
+            classes: vec![("Hex", Range('0', '9') / Range('a', 'f'))],
+            rules: vec![
+                ("S", (NamedClass("Hex".to_string()) ^ 1) >> "S"),
+                ("T", !(Char('a') + Any) + EndOfInput),
+            ],
+        };
+        assert_eq!(format_grammar(&grammar), "@Hex <- [0-9a-f]\nS <- {@Hex+}\nT <- !('a' .) !.\n");
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let sources = [
+            include_str!("../../grammars/arith.peg"),
+            "S <- 'a'i \"bc\"i [xy]i [^a-z\\d]* [^\\]\\t] \\p{Greek}^2 \\W^-3 <&'a' <!'\\u{7f}' &. !'\\'\\\\' T\nT <- ''",
+            "S <- {x: 'a' {'b'}} / {'c' / 'd'} ((.)?)? !.",
+        ];
+        for source in sources.iter() {
+            let formatted = format_source(source).unwrap();
+            assert_eq!(format_source(&formatted).unwrap(), formatted, "{}", source);
+            assert_eq!(compiled(&formatted), compiled(source), "{}", formatted);
+        }
+    }
+
+    #[test]
+    fn test_random_round_trip() {
+        use crate::differential::{class_definition, random_grammar, random_input, Rng};
+        use crate::syntax::SyntaxErrorKind;
+        use pegatexto_vm::matcher::try_match;
+
+        for seed in 0..200 {
+            let mut rng = Rng::new(seed);
+            let grammar = Grammar { classes: vec![class_definition()], rules: random_grammar(&mut rng) };
+            let formatted = format_grammar(&grammar);
+            let parsed = match parse_grammar(&formatted) {
+                Ok(parsed) => parsed,
+                // generated ranges may be reversed, which the syntax rejects
+                Err(error) if error.kind == SyntaxErrorKind::InvalidRange => continue,
+                Err(error) => panic!("seed {}: {}\n{}", seed, error, formatted),
+            };
+            assert_eq!(format_grammar(&parsed), formatted, "seed {}", seed);
+
+            let compile = |grammar: &Grammar| {
+                let mut compiler = Compiler::new();
+                compiler.compile_parsed(grammar).map(|_| compiler.emit_owned())
+            };
+            let (expected, actual) = match (compile(&grammar), compile(&parsed)) {
+                (Ok(expected), Ok(actual)) => (expected, actual),
+                (Err(_), Err(_)) => continue,
+                _ => panic!("seed {}: only one grammar compiles\n{}", seed, formatted),
+            };
+            for _ in 0..16 {
+                let input = random_input(&mut rng);
+                let (expected, actual) = (try_match(&expected.as_bytecode(), &input), try_match(&actual.as_bytecode(), &input));
+                assert_eq!(actual, expected, "seed {}: {:?}\n{}", seed, input, formatted);
+            }
+        }
+    }
+}
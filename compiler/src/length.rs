@@ -0,0 +1,155 @@
+use crate::first::FirstSets;
+
+use pegatexto_vm::grammar::expression::Expression;
+
+use std::collections::HashSet;
+
+/// Number of chars `expr` consumes whenever it matches, if always the same
+pub fn fixed_length(expr: &Expression, first: &FirstSets) -> Option<usize> {
+    length_range(expr, first).filter(|(min, max)| min == max).map(|(min, _)| min)
+}
+
+/// Least and most chars `expr` may consume when it matches, if bounded
+pub fn length_range(expr: &Expression, first: &FirstSets) -> Option<(usize, usize)> {
+    length_range_visiting(expr, first, &mut HashSet::new())
+}
+
+/// Least number of chars `expr` consumes when it matches; rules reached
+/// again while being measured count as consuming none
+pub fn min_length(expr: &Expression, first: &FirstSets) -> usize {
+    min_length_visiting(expr, first, &mut HashSet::new())
+}
+
+fn min_length_visiting(expr: &Expression, first: &FirstSets, visiting: &mut HashSet<String>) -> usize {
+    match expr {
+        Expression::Literal(s) | Expression::LiteralNoCase(s) => s.chars().count(),
+        Expression::Capture(e, _) => min_length_visiting(e, first, visiting),
+        Expression::NonTerminal(name) => match first.rule(name) {
+            Some(rule) if visiting.insert(name.clone()) => {
+                let min = min_length_visiting(rule, first, visiting);
+                visiting.remove(name);
+                min
+            },
+            _ => 0,
+        },
+        Expression::Sequence(es) => es.iter().fold(0, |min, e| min.saturating_add(min_length_visiting(e, first, visiting))),
+        Expression::Choice(es) => es.iter().map(|e| min_length_visiting(e, first, visiting)).min().unwrap_or(0),
+        Expression::Quantifier(e, n) if *n > 0 => min_length_visiting(e, first, visiting).saturating_mul(*n as usize),
+        Expression::Quantifier(_, _) => 0,
+        _ => match length_range_visiting(expr, first, visiting) {
+            Some((min, _)) => min,
+            None => 0,
+        },
+    }
+}
+
+fn length_range_visiting(expr: &Expression, first: &FirstSets, visiting: &mut HashSet<String>) -> Option<(usize, usize)> {
+    match expr {
+        Expression::Char(_) | Expression::CharNoCase(_) | Expression::Class(_) | Expression::UnicodeClass(_)
+            | Expression::NamedClass(_) | Expression::Set(_) | Expression::SetNoCase(_) | Expression::InverseSet(_)
+            | Expression::Range(_, _) | Expression::Any => Some((1, 1)),
+        // case folding maps single chars, so lengths don't change
+        Expression::Literal(s) | Expression::LiteralNoCase(s) => {
+            let length = s.chars().count();
+            Some((length, length))
+        },
+        Expression::And(_) | Expression::Not(_) | Expression::Behind(_) | Expression::NotBehind(_)
+            | Expression::EndOfInput => Some((0, 0)),
+        Expression::Capture(e, _) => length_range_visiting(e, first, visiting),
+        Expression::NonTerminal(name) => {
+            let rule = first.rule(name)?;
+            if !visiting.insert(name.clone()) {
+                return None;
+            }
+            let range = length_range_visiting(rule, first, visiting);
+            visiting.remove(name);
+            range
+        },
+        Expression::Sequence(es) => {
+            es.iter().try_fold((0, 0), |(min, max): (usize, usize), e| {
+                let (e_min, e_max) = length_range_visiting(e, first, visiting)?;
+                Some((min.saturating_add(e_min), max.saturating_add(e_max)))
+            })
+        },
+        Expression::Choice(es) => {
+            let mut ranges = es.iter().map(|e| length_range_visiting(e, first, visiting));
+            let first_range = ranges.next()??;
+            ranges.try_fold(first_range, |(min, max), range| {
+                let (e_min, e_max) = range?;
+                Some((min.min(e_min), max.max(e_max)))
+            })
+        },
+        // `e^-n` matches e at most n times
+        Expression::Quantifier(e, n) if *n < 0 => {
+            let (_, max) = length_range_visiting(e, first, visiting)?;
+            Some((0, max.saturating_mul(n.unsigned_abs() as usize)))
+        },
+        Expression::Quantifier(e, _) => match length_range_visiting(e, first, visiting)? {
+            // repetitions of empty matches end at once
+            (_, 0) => Some((0, 0)),
+            _ => None,
+        },
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use Expression::*;
+
+    fn nt(name: &str) -> Expression {
+        NonTerminal(name.to_string())
+    }
+
+    #[test]
+    fn test_fixed_length() {
+        let grammar = vec![
+            ("Pair", Char('<') + Any),
+            ("Either", Literal("αβ".to_string()) / nt("Pair")),
+            ("Loop", Char('x') + nt("Loop")),
+        ];
+        let first = FirstSets::new(&grammar);
+        assert_eq!(fixed_length(&nt("Pair"), &first), Some(2));
+        assert_eq!(fixed_length(&(!Char('a') + nt("Either") + (Set("ab".to_string()) >> "c")), &first), Some(3));
+        assert_eq!(fixed_length(&Sequence(vec![]), &first), Some(0));
+        assert_eq!(fixed_length(&(Char('a') / Literal("ab".to_string())), &first), None);
+        assert_eq!(fixed_length(&(Char('a')^(-1)), &first), None);
+        assert_eq!(fixed_length(&nt("Loop"), &first), None);
+        assert_eq!(fixed_length(&nt("Undefined"), &first), None);
+    }
+
+    #[test]
+    fn test_length_range() {
+        let grammar = vec![
+            ("Either", Char('a') / Literal("bc".to_string())),
+            ("Loop", Char('x') + nt("Loop")),
+        ];
+        let first = FirstSets::new(&grammar);
+        assert_eq!(length_range(&(Char('a') + (Char('b')^(-1))), &first), Some((1, 2)));
+        assert_eq!(length_range(&((nt("Either") + Char('d')) >> "c"), &first), Some((2, 3)));
+        assert_eq!(length_range(&((Literal("ab".to_string()) / Sequence(vec![]))^(-3)), &first), Some((0, 6)));
+        assert_eq!(length_range(&(Char('a') / (Literal("αβγ".to_string()) + !Any)), &first), Some((1, 3)));
+        assert_eq!(length_range(&(Char('a')^1), &first), None);
+        assert_eq!(length_range(&(Char('a') + (Char('b')^0)), &first), None);
+        assert_eq!(length_range(&(Expression::And(Box::new(Any))^0), &first), Some((0, 0)));
+        assert_eq!(length_range(&nt("Loop"), &first), None);
+    }
+
+    #[test]
+    fn test_min_length() {
+        let grammar = vec![
+            ("Either", Char('a') / Literal("bc".to_string())),
+            ("Loop", (Char('x') + nt("Loop")) / Sequence(vec![])),
+            ("Left", nt("Left") + Char('x')),
+        ];
+        let first = FirstSets::new(&grammar);
+        assert_eq!(min_length(&(nt("Either") + (Char('b')^2)), &first), 3);
+        assert_eq!(min_length(&(Char('a') / (Literal("αβ".to_string()) + !Any)), &first), 1);
+        assert_eq!(min_length(&((Char('a')^(-1)) >> "c"), &first), 0);
+        assert_eq!(min_length(&(Char('a')^0), &first), 0);
+        assert_eq!(min_length(&nt("Loop"), &first), 0);
+        assert_eq!(min_length(&nt("Left"), &first), 1);
+        assert_eq!(min_length(&nt("Undefined"), &first), 0);
+    }
+}
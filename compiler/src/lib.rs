@@ -1,69 +1,228 @@
-use pegatexto_vm::bytecode::{Bytecode, OwnedBytecode};
+pub mod charset;
+pub mod class;
+#[cfg(any(test, feature = "interpreter"))]
+pub mod differential;
+pub mod first;
+pub mod format;
+pub mod length;
+pub mod optimizer;
+pub mod program;
+pub mod syntax;
+
+use charset::CharsetTest;
+use class::ClassDefinition;
+use first::FirstSets;
+use program::{Encoding, Label, Op, Program};
+use syntax::Grammar;
+
+use pegatexto_vm::bytecode::{Bytecode, Metadata, OwnedBytecode};
 use pegatexto_vm::bytecode::address::Address;
-use pegatexto_vm::bytecode::builder::Builder as BytecodeBuilder;
+use pegatexto_vm::bytecode::builder::{BuildError, Builder as BytecodeBuilder};
 use pegatexto_vm::bytecode::instruction::Instruction;
+use pegatexto_vm::bytecode::symbols::SymbolTable;
 use pegatexto_vm::grammar::expression::Expression;
+use pegatexto_vm::grammar::unicode;
 
 use std::collections::HashMap;
-use std::fmt::Debug;
+use std::convert::TryFrom;
+use std::fmt::{self, Debug};
 use std::vec::Vec;
 
+fn undefined_class<'e>(expr: &'e Expression, classes: &HashMap<String, ClassDefinition>) -> Option<&'e str> {
+    match expr {
+        Expression::NamedClass(name) if !classes.contains_key(name) => Some(name),
+        Expression::Choice(es) => es.iter().find_map(|e| undefined_class(e, classes)),
+        _ => None,
+    }
+}
+
+enum Alternative<'g> {
+    Expression(&'g Expression),
+    Charset(CharsetTest),
+}
+
 struct RuleCompileInfo {
     index: Option<i32>,
-    call_addresses: Vec<Address>,
+    label: Option<Label>,
     address: Address,
 }
 impl RuleCompileInfo {
     fn new() -> RuleCompileInfo {
         RuleCompileInfo {
             index: None,
-            call_addresses: Vec::new(),
+            label: None,
             address: Address::default(),
         }
     }
 }
 
+#[derive(Clone, Copy, Debug)]
+pub struct CompileOptions {
+    pub optimize: bool,
+    /// Maximum number of instructions of a rule inlined into its callers, 0 disables inlining
+    pub inline_threshold: usize,
+}
+
+impl Default for CompileOptions {
+    fn default() -> CompileOptions {
+        CompileOptions { optimize: true, inline_threshold: 8 }
+    }
+}
+
 pub struct Compiler {
     builder: BytecodeBuilder,
     rulemap: HashMap<String, RuleCompileInfo>,
+    capture_names: Vec<String>,
+    classes: HashMap<String, ClassDefinition>,
+    tail_calls: Vec<Address>,
+    options: CompileOptions,
 }
 
 #[derive(Debug)]
 pub enum CompileError {
     EmptyGrammar,
+    UndefinedRule(String),
+    UndefinedClass(String),
+    /// Class definition that may match something other than a single char
+    InvalidClass(String),
+    TooManyCaptureNames,
+    /// Lookbehind whose expression has no bounded length, or may be longer
+    /// than 255 chars
+    UnboundedLookbehind,
+    /// Quantifier count past what the bytecode can represent
+    QuantifierOutOfRange(i32),
+    Build(BuildError),
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CompileError::EmptyGrammar => write!(f, "grammar has no rules"),
+            CompileError::UndefinedRule(name) => write!(f, "undefined rule {}", name),
+            CompileError::UndefinedClass(name) => write!(f, "undefined class @{}", name),
+            CompileError::InvalidClass(name) => write!(f, "class @{} may match more than a single char", name),
+            CompileError::TooManyCaptureNames => write!(f, "more than 256 capture names"),
+            CompileError::UnboundedLookbehind => write!(f, "lookbehind of unbounded length"),
+            CompileError::QuantifierOutOfRange(n) => write!(f, "quantifier count {} out of range", n),
+            CompileError::Build(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl From<BuildError> for CompileError {
+    fn from(error: BuildError) -> CompileError {
+        CompileError::Build(error)
+    }
+}
+
+impl Default for Compiler {
+    fn default() -> Compiler {
+        Compiler::new()
+    }
 }
 
 impl Compiler {
     pub fn new() -> Compiler {
-        Compiler { builder: BytecodeBuilder::new(), rulemap: HashMap::new() }
+        Compiler::with_options(CompileOptions::default())
     }
 
-    pub fn emit(&self) -> Bytecode {
+    pub fn with_options(options: CompileOptions) -> Compiler {
+        Compiler {
+            builder: BytecodeBuilder::new(),
+            rulemap: HashMap::new(),
+            capture_names: Vec::new(),
+            classes: HashMap::new(),
+            tail_calls: Vec::new(),
+            options,
+        }
+    }
+
+    pub fn emit(&self) -> Bytecode<'_> {
         self.builder.build()
     }
 
     pub fn emit_owned(self) -> OwnedBytecode {
-        self.builder.build_owned()
+        let metadata = Metadata {
+            symbols: self.symbols(),
+            capture_names: self.capture_names,
+            debug_info: None,
+            tail_calls: self.tail_calls,
+        };
+        self.builder.build_owned().with_metadata(metadata)
+    }
+
+    pub fn capture_names(&self) -> &[String] {
+        &self.capture_names
+    }
+
+    /// Addresses of the `Jump`s the optimizer made from calls before `Return`
+    pub fn tail_calls(&self) -> &[Address] {
+        &self.tail_calls
+    }
+
+    pub fn symbols(&self) -> SymbolTable {
+        let mut symbols = SymbolTable::new();
+        for (name, rule_info) in self.rulemap.iter() {
+            if rule_info.index.is_some() {
+                symbols.insert(rule_info.address, name);
+            }
+        }
+        symbols
+    }
+
+    /// Declares a class usable as `Expression::NamedClass(name)` in grammars
+    /// compiled afterwards, replacing any previous class with that name.
+    pub fn define_class(&mut self, name: &str, expr: &Expression) -> Result<(), CompileError> {
+        if let Some(undefined) = undefined_class(expr, &self.classes) {
+            return Err(CompileError::UndefinedClass(undefined.to_string()));
+        }
+        match ClassDefinition::lower(expr, &self.classes) {
+            Some(definition) => {
+                self.classes.insert(name.to_string(), definition);
+                Ok(())
+            },
+            None => Err(CompileError::InvalidClass(name.to_string())),
+        }
+    }
+
+    /// Defines the classes of a parsed grammar, then compiles its rules
+    pub fn compile_parsed(&mut self, grammar: &Grammar) -> Result<(), CompileError> {
+        for (name, expr) in grammar.classes.iter() {
+            self.define_class(name, expr)?;
+        }
+        self.compile_grammar(&grammar.rules)
     }
 
     pub fn compile_grammar(&mut self, grammar: &[(&str, Expression)]) -> Result<(), CompileError> {
-        if grammar.len() == 0 {
+        if grammar.is_empty() {
             return Err(CompileError::EmptyGrammar)
         }
-        for (i, (name, expr)) in grammar.iter().enumerate() {
-            let current_address = self.builder.current_address();
-            let mut rule_info = self.rule_info(name);
+        let mut program = Program::new();
+        let mut rule_labels = Vec::with_capacity(grammar.len());
+        for (i, (name, _expr)) in grammar.iter().enumerate() {
+            let label = program.new_label();
+            let rule_info = self.rule_info(name);
             rule_info.index = Some(i as i32);
-            rule_info.address = current_address;
-            self.compile_expr(expr);
-            self.builder.push_instruction(&Instruction::Return);
+            rule_info.label = Some(label);
+            rule_labels.push(label);
+        }
+        let first = FirstSets::new(grammar);
+        for ((_name, expr), label) in grammar.iter().zip(rule_labels.iter()) {
+            program.place(*label);
+            self.compile_expr(expr, &mut program, &first)?;
+            program.push_instruction(Instruction::Return);
         }
+        if self.options.optimize {
+            optimizer::inline_rules(&mut program, &rule_labels, self.options.inline_threshold);
+            optimizer::optimize(&mut program, &rule_labels);
+        }
+        let Encoding { addresses, jumps } = program.encode(&mut self.builder)?;
+        // the optimizer only jumps to rule entries for tail calls
+        self.tail_calls.extend(jumps.iter().filter(|(_, label)| rule_labels.contains(label)).map(|(address, _)| *address));
         for (name, _expr) in grammar.iter() {
-            let rule_info = &self.rulemap[*name];
-            let rule_address = rule_info.address;
-            let iter = rule_info.call_addresses.iter();
-            for call_addr in iter {
-                self.builder.patch_jump(*call_addr, rule_address);
+            let rule_info = self.rule_info(name);
+            if let Some(address) = rule_info.label.and_then(|label| addresses.get(&label)) {
+                rule_info.address = *address;
             }
         }
         Ok(())
@@ -73,125 +232,607 @@ impl Compiler {
         self.rulemap.entry(name.to_string()).or_insert_with(RuleCompileInfo::new)
     }
 
-    pub fn compile_expr(&mut self, expr: &Expression) {
+    fn capture_id(&mut self, name: &str) -> Result<u8, CompileError> {
+        let index = match self.capture_names.iter().position(|n| n == name) {
+            Some(index) => index,
+            None => {
+                self.capture_names.push(name.to_string());
+                self.capture_names.len() - 1
+            },
+        };
+        u8::try_from(index).map_err(|_| CompileError::TooManyCaptureNames)
+    }
+
+    fn charset_test(&self, expr: &Expression) -> Option<CharsetTest> {
+        if self.options.optimize { charset::charset_test(expr) } else { None }
+    }
+
+    fn compile_charset_test(&self, test: CharsetTest, program: &mut Program) {
+        if test.negated {
+            program.push_instruction(Instruction::NotCharset(test.charset));
+        }
+        else {
+            program.push_instruction(Instruction::Charset(test.charset));
+        }
+    }
+
+    // Adjacent alternatives that match a single char are merged into one
+    // charset test, their order doesn't matter since they consume the same
+    fn choice_alternatives<'g>(&self, es: &'g [Expression]) -> Vec<Alternative<'g>> {
+        let mut alternatives = Vec::new();
+        let mut run: Vec<(&'g Expression, CharsetTest)> = Vec::new();
+        let flush = |run: &mut Vec<(&'g Expression, CharsetTest)>, alternatives: &mut Vec<Alternative<'g>>| {
+            match run.len() {
+                0 => (),
+                1 => alternatives.push(Alternative::Expression(run[0].0)),
+                _ => {
+                    let test = run[1..].iter().fold(run[0].1, |union, (_, test)| union.union(test));
+                    alternatives.push(Alternative::Charset(test));
+                },
+            }
+            run.clear();
+        };
+        for e in es.iter() {
+            match self.charset_test(e) {
+                Some(test) => run.push((e, test)),
+                None => {
+                    flush(&mut run, &mut alternatives);
+                    alternatives.push(Alternative::Expression(e));
+                },
+            }
+        }
+        flush(&mut run, &mut alternatives);
+        alternatives
+    }
+
+    fn compile_alternative<'g>(&mut self, alternative: &Alternative<'g>, program: &mut Program<'g>, first: &FirstSets) -> Result<(), CompileError> {
+        match alternative {
+            Alternative::Expression(e) => self.compile_expr(e, program, first),
+            Alternative::Charset(test) => {
+                self.compile_charset_test(*test, program);
+                Ok(())
+            },
+        }
+    }
+
+    // Set of chars tested before trying a choice alternative, so doomed
+    // alternatives fail without running them
+    fn head_test(&self, expr: &Expression, first: &FirstSets) -> Option<String> {
+        if !self.options.optimize {
+            return None;
+        }
+        // alternatives that only test one char would do the same test themselves
+        if let Expression::Char(_) | Expression::Set(_) | Expression::Class(_) | Expression::Range(_, _) | Expression::CharNoCase(_) | Expression::SetNoCase(_) = expr {
+            return None;
+        }
+        let head = first.head(expr)?;
+        if head.is_empty() || head.contains(&'\0') {
+            return None;
+        }
+        Some(head.into_iter().collect())
+    }
+
+    // Each alternative of a choice may have its own lengths, so each one
+    // moves back by each of its lengths, longest first, and matches from
+    // there, restoring the position if it fails. Unless the alternative
+    // always has the same length, it must also end where it started.
+    fn compile_behind<'g>(&mut self, expr: &'g Expression, negated: bool, program: &mut Program<'g>, first: &FirstSets) -> Result<(), CompileError> {
+        let alternatives = match expr {
+            Expression::Choice(es) if length::fixed_length(expr, first).is_none() => es.iter().collect(),
+            _ => vec![expr],
+        };
+        let mut attempts = Vec::new();
+        for alternative in alternatives {
+            let (min, max) = length::length_range(alternative, first)
+                .filter(|(_, max)| *max <= u8::MAX as usize)
+                .ok_or(CompileError::UnboundedLookbehind)?;
+            attempts.extend((min..=max).rev().map(|length| (alternative, length as u8, min == max)));
+        }
+        let end_label = program.new_label();
+        program.push_instruction(Instruction::Push);
+        for (i, (alternative, length, fixed)) in attempts.iter().enumerate() {
+            let next_label = if i + 1 < attempts.len() { program.new_label() } else { end_label };
+            program.push_instruction(Instruction::Behind(*length));
+            program.push(Op::JumpIfFail(next_label));
+            self.compile_expr(alternative, program, first)?;
+            if !fixed {
+                program.push(Op::JumpIfFail(next_label));
+                program.push_instruction(Instruction::FailIfMoved);
+            }
+            if next_label != end_label {
+                program.push(Op::JumpIfSuccess(end_label));
+                program.place(next_label);
+                program.push_instruction(Instruction::Peek);
+            }
+        }
+        program.place(end_label);
+        if negated {
+            program.push_instruction(Instruction::ToggleSuccess);
+        }
+        program.push_instruction(Instruction::Peek);
+        program.push_instruction(Instruction::Pop);
+        Ok(())
+    }
+
+    // A loop whose body may match empty ends after an iteration that
+    // doesn't move, with its minimum count met. Each iteration is pushed,
+    // so FailIfMoved can tell.
+    fn compile_nullable_loop<'g>(&mut self, expr: &'g Expression, min: u8, program: &mut Program<'g>, first: &FirstSets) -> Result<(), CompileError> {
+        let iteration_end = program.new_label();
+        let empty_label = program.new_label();
+        let end_label = program.new_label();
+        program.push_instruction(Instruction::QuantifierInit);
+        program.push_instruction(Instruction::Push);
+        self.compile_expr(expr, program, first)?;
+        program.push(Op::JumpIfFail(iteration_end));
+        program.push_instruction(Instruction::FailIfMoved);
+        program.push(Op::JumpIfSuccess(empty_label));
+        program.push_instruction(Instruction::ToggleSuccess);
+        program.place(iteration_end);
+        program.push_instruction(Instruction::Pop);
+        program.push_instruction(Instruction::QuantifierNext);
+        program.push_instruction(Instruction::FailIfLessThan(min));
+        program.push_instruction(Instruction::Pop);
+        program.push(Op::Jump(end_label));
+        program.place(empty_label);
+        program.push_instruction(Instruction::Pop);
+        program.push_instruction(Instruction::Pop);
+        program.push_instruction(Instruction::Succeed);
+        program.place(end_label);
+        Ok(())
+    }
+
+    // At most `max` repetitions of a body that may match empty, stopping
+    // after an iteration that doesn't move
+    fn compile_nullable_bounded<'g>(&mut self, expr: &'g Expression, max: i32, program: &mut Program<'g>, first: &FirstSets) -> Result<(), CompileError> {
+        let exit_label = program.new_label();
+        let end_label = program.new_label();
+        for _ in 1..max {
+            program.push_instruction(Instruction::Push);
+            self.compile_expr(expr, program, first)?;
+            program.push(Op::JumpIfFail(exit_label));
+            program.push_instruction(Instruction::FailIfMoved);
+            program.push(Op::JumpIfSuccess(exit_label));
+            program.push_instruction(Instruction::Pop);
+        }
+        self.compile_expr(expr, program, first)?;
+        program.push(Op::Jump(end_label));
+        program.place(exit_label);
+        program.push_instruction(Instruction::Pop);
+        program.place(end_label);
+        program.push_instruction(Instruction::Succeed);
+        Ok(())
+    }
+
+    fn compile_expr<'g>(&mut self, expr: &'g Expression, program: &mut Program<'g>, first: &FirstSets) -> Result<(), CompileError> {
         match expr {
             Expression::Char(c) => {
-                self.builder.push_instruction(&Instruction::Char(*c));
+                program.push_instruction(Instruction::Char(*c));
             },
             Expression::Literal(s) => {
-                self.builder.push_instruction(&Instruction::Literal(s));
+                program.push_instruction(Instruction::Literal(s));
+            },
+            Expression::LiteralNoCase(s) => {
+                if s.chars().all(|c| unicode::case_variants(c).len() == 1) {
+                    program.push_instruction(Instruction::Literal(s));
+                }
+                else {
+                    program.push_instruction(Instruction::LiteralNoCase(s));
+                }
             },
             Expression::Class(c) => {
-                self.builder.push_instruction(&Instruction::Class(*c));
+                program.push_instruction(Instruction::Class(*c));
+            },
+            Expression::UnicodeClass(class) => {
+                program.push_instruction(Instruction::UnicodeClass(*class));
+            },
+            Expression::NamedClass(name) => {
+                match self.classes.get(name) {
+                    Some(definition) if definition.items.is_empty() => {
+                        program.push_instruction(Instruction::Charset(definition.charset));
+                    },
+                    Some(definition) => program.push(Op::ClassSet(definition.encode())),
+                    None => return Err(CompileError::UndefinedClass(name.clone())),
+                }
+            },
+            Expression::Set(_) | Expression::InverseSet(_) | Expression::Range(_, _) | Expression::CharNoCase(_) | Expression::SetNoCase(_)
+                if self.charset_test(expr).is_some() => {
+                self.compile_charset_test(self.charset_test(expr).unwrap(), program);
+            },
+            Expression::CharNoCase(c) if unicode::case_variants(*c).len() == 1 => {
+                program.push_instruction(Instruction::Char(*c));
+            },
+            Expression::CharNoCase(_) => {
+                // a single char has no string to borrow, so its variants go in a class set
+                let definition = ClassDefinition::lower(expr, &self.classes).unwrap();
+                program.push(Op::ClassSet(definition.encode()));
+            },
+            Expression::SetNoCase(s) => {
+                program.push_instruction(Instruction::SetNoCase(s));
             },
             Expression::Set(s) => {
-                self.builder.push_instruction(&Instruction::Set(s));
+                program.push_instruction(Instruction::Set(s));
             },
             Expression::InverseSet(s) => {
-                self.builder.push_instruction(&Instruction::NotSet(s));
+                program.push_instruction(Instruction::NotSet(s));
             },
             Expression::Range(min, max) => {
-                let b_min = *min as u8;
-                let b_max = *max as u8;
-                self.builder.push_instruction(&Instruction::Range(b_min, b_max));
+                let instruction = if min > max {
+                    Instruction::Fail
+                }
+                else if min == max {
+                    Instruction::Char(*min)
+                }
+                // for ASCII, matching bytes is the same as matching chars
+                else if max.is_ascii() {
+                    Instruction::Range(*min as u8, *max as u8)
+                }
+                else {
+                    Instruction::CharRange(*min, *max)
+                };
+                program.push_instruction(instruction);
             },
             Expression::Any => {
-                self.builder.push_instruction(&Instruction::Any);
+                program.push_instruction(Instruction::Any);
+            },
+            Expression::EndOfInput => {
+                program.push_instruction(Instruction::EndOfInput);
             },
             Expression::NonTerminal(s) => {
-                let addr = self.builder.current_address();
-                self.builder.push_instruction(&Instruction::Call(Address::default()));
-                self.rule_info(s).call_addresses.push(addr);
+                match self.rulemap.get(s).and_then(|rule_info| rule_info.label) {
+                    Some(label) => program.push(Op::Call(label)),
+                    None => return Err(CompileError::UndefinedRule(s.clone())),
+                }
             },
             Expression::Quantifier(e, n) => {
                 match n {
                     -1 => {
-                        self.compile_expr(e);
-                        self.builder.push_instruction(&Instruction::Succeed);
+                        self.compile_expr(e, program, first)?;
+                        program.push_instruction(Instruction::Succeed);
                     },
+                    0..=255 if length::min_length(e, first) == 0 => self.compile_nullable_loop(e, *n as u8, program, first)?,
+                    -255..=-2 if length::min_length(e, first) == 0 => self.compile_nullable_bounded(e, -n, program, first)?,
                     0 => {
-                        let addr = self.builder.current_address();
-                        self.compile_expr(e);
-                        self.builder.push_instruction(&Instruction::JumpIfSuccess(addr));
-                        self.builder.push_instruction(&Instruction::Succeed);
+                        let loop_label = program.new_label();
+                        program.place(loop_label);
+                        self.compile_expr(e, program, first)?;
+                        program.push(Op::JumpIfSuccess(loop_label));
+                        program.push_instruction(Instruction::Succeed);
                     },
-                    1 => {
-                        self.builder.push_instruction(&Instruction::QuantifierInit);
-                        self.compile_expr(e);
-                        self.builder.push_instruction(&Instruction::QuantifierNext);
-                        self.builder.push_instruction(&Instruction::FailIfLessThan(1));
-                        self.builder.push_instruction(&Instruction::Pop);
+                    // at least n
+                    1..=255 => {
+                        program.push_instruction(Instruction::QuantifierInit);
+                        self.compile_expr(e, program, first)?;
+                        program.push_instruction(Instruction::QuantifierNext);
+                        program.push_instruction(Instruction::FailIfLessThan(*n as u8));
+                        program.push_instruction(Instruction::Pop);
                     },
-                    _ => ()
+                    // at most -n
+                    -255..=-2 => {
+                        let end_label = program.new_label();
+                        for _ in 0..-n {
+                            self.compile_expr(e, program, first)?;
+                            program.push(Op::JumpIfFail(end_label));
+                        }
+                        program.place(end_label);
+                        program.push_instruction(Instruction::Succeed);
+                    },
+                    _ => return Err(CompileError::QuantifierOutOfRange(*n)),
                 }
             },
             Expression::And(e) => {
-                self.builder.push_instruction(&Instruction::Push);
-                self.compile_expr(e);
-                self.builder.push_instruction(&Instruction::Peek);
-                self.builder.push_instruction(&Instruction::Pop);
+                program.push_instruction(Instruction::Push);
+                self.compile_expr(e, program, first)?;
+                program.push_instruction(Instruction::Peek);
+                program.push_instruction(Instruction::Pop);
             },
             Expression::Not(e) => {
-                self.builder.push_instruction(&Instruction::Push);
-                self.compile_expr(e);
-                self.builder.push_instruction(&Instruction::ToggleSuccess);
-                self.builder.push_instruction(&Instruction::Peek);
-                self.builder.push_instruction(&Instruction::Pop);
+                program.push_instruction(Instruction::Push);
+                self.compile_expr(e, program, first)?;
+                program.push_instruction(Instruction::ToggleSuccess);
+                program.push_instruction(Instruction::Peek);
+                program.push_instruction(Instruction::Pop);
             },
+            Expression::Behind(e) => self.compile_behind(e, false, program, first)?,
+            Expression::NotBehind(e) => self.compile_behind(e, true, program, first)?,
             Expression::Sequence(es) => {
                 let n = es.len();
                 match n {
                     0 => (),
-                    1 => self.compile_expr(&es[0]),
+                    1 => self.compile_expr(&es[0], program, first)?,
                     _ => {
-                        self.builder.push_instruction(&Instruction::Push);
-                        self.compile_expr(&es[0]);
-                        let mut jump_fail_patches = Vec::with_capacity(n - 1);
+                        let fail_label = program.new_label();
+                        let end_label = program.new_label();
+                        program.push_instruction(Instruction::Push);
+                        self.compile_expr(&es[0], program, first)?;
                         for e in es[1..].iter() {
-                            let addr = self.builder.current_address();
-                            self.builder.push_instruction(&Instruction::JumpIfFail(Address::default()));
-                            jump_fail_patches.push(addr);
-                            self.compile_expr(e);
+                            program.push(Op::JumpIfFail(fail_label));
+                            self.compile_expr(e, program, first)?;
                         }
-                        let jump_success_patch = self.builder.current_address();
-                        self.builder.push_instruction(&Instruction::JumpIfSuccess(Address::default()));
-                        let fail_address = self.builder.current_address();
-                        for patch_addr in jump_fail_patches.iter() {
-                            self.builder.patch_jump(*patch_addr, fail_address);
-                        }
-                        self.builder.push_instruction(&Instruction::Peek);
-                        let end_address = self.builder.current_address();
-                        self.builder.patch_jump(jump_success_patch, end_address);
-                        self.builder.push_instruction(&Instruction::Pop);
+                        program.push(Op::JumpIfSuccess(end_label));
+                        program.place(fail_label);
+                        program.push_instruction(Instruction::Peek);
+                        program.place(end_label);
+                        program.push_instruction(Instruction::Pop);
                     }
                 }
             },
             Expression::Choice(es) => {
-                let n = es.len();
+                let alternatives = self.choice_alternatives(es);
+                let n = alternatives.len();
                 match n {
                     0 => (),
-                    1 => self.compile_expr(&es[0]),
+                    1 => self.compile_alternative(&alternatives[0], program, first)?,
                     _ => {
-                        let mut jump_success_patches = Vec::with_capacity(n - 1);
-                        self.compile_expr(&es[0]);
-                        for e in es[1..].iter() {
-                            let addr = self.builder.current_address();
-                            self.builder.push_instruction(&Instruction::JumpIfSuccess(Address::default()));
-                            jump_success_patches.push(addr);
-                            self.compile_expr(e);
-                        }
-                        let end_address = self.builder.current_address();
-                        for patch_addr in jump_success_patches.iter() {
-                            self.builder.patch_jump(*patch_addr, end_address);
+                        let end_label = program.new_label();
+                        let mut next_label = None;
+                        for (i, alternative) in alternatives.iter().enumerate() {
+                            if i > 0 {
+                                program.push(Op::JumpIfSuccess(end_label));
+                            }
+                            if let Some(label) = next_label.take() {
+                                program.place(label);
+                            }
+                            let head = match alternative {
+                                Alternative::Expression(e) => self.head_test(e, first),
+                                Alternative::Charset(_) => None,
+                            };
+                            if let Some(set) = head {
+                                let label = if i + 1 < n { *next_label.insert(program.new_label()) } else { end_label };
+                                program.push(Op::TestSet(set, label));
+                            }
+                            self.compile_alternative(alternative, program, first)?;
                         }
+                        program.place(end_label);
                     }
                 }
             },
-            Expression::Capture(e, _name) => {
-                self.compile_expr(e);
-                self.builder.push_instruction(&Instruction::Capture(0));
+            Expression::Capture(e, name) => {
+                let id = self.capture_id(name)?;
+                let end_label = program.new_label();
+                program.push_instruction(Instruction::Push);
+                self.compile_expr(e, program, first)?;
+                program.push(Op::JumpIfFail(end_label));
+                program.push_instruction(Instruction::Capture(id));
+                program.place(end_label);
+                program.push_instruction(Instruction::Pop);
             },
         }
+        Ok(())
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pegatexto_vm::bytecode::instruction::InstructionIterator;
+    use pegatexto_vm::grammar::character_class::CharacterClass;
+    use pegatexto_vm::grammar::unicode::UnicodeClass;
+    use pegatexto_vm::matcher::{try_match, MatchError};
+
+    #[test]
+    fn test_capture_ids() {
+        use Expression::*;
+
+        // captures with the same name share an id, in order of appearance
+        let pair = (Char('a') >> "key") + Char('=') + (Char('b') >> "value") + Char(';') + (Char('c') >> "key");
+        let mut compiler = Compiler::new();
+        compiler.compile_grammar(&[("Main", pair >> "pair")]).unwrap();
+        assert_eq!(compiler.capture_names(), ["pair", "key", "value"]);
+        let bytecode = compiler.emit();
+        let ids: Vec<u8> = InstructionIterator::new(&bytecode).filter_map(|instruction| match instruction {
+            Instruction::Capture(id) => Some(id),
+            _ => None,
+        }).collect();
+        assert_eq!(ids, [1, 2, 1, 0]);
+    }
+
+    #[test]
+    fn test_compile_ranges() {
+        for optimize in [false, true] {
+            let grammar = [
+                ("Main", (Expression::Range('α', 'ω') / Expression::Range('0', '9') / Expression::Range('x', 'x'))^1),
+                ("Empty", Expression::Range('b', 'a')),
+            ];
+            let mut compiler = Compiler::with_options(CompileOptions { optimize, ..CompileOptions::default() });
+            compiler.compile_grammar(&grammar).unwrap();
+            let bytecode = compiler.emit();
+            assert_eq!(try_match(&bytecode, "αβ9xω!"), Ok(8));
+            assert_eq!(try_match(&bytecode, "Ω"), Err(MatchError::NoMatch(0)));
+            assert_eq!(try_match(&bytecode, "y"), Err(MatchError::NoMatch(0)));
+            assert_eq!(try_match(&bytecode, "\u{3b1}\u{303}"), Ok(2));
+        }
+    }
+
+    #[test]
+    fn test_compile_unicode_identifier() {
+        // UAX #31 default identifiers, without the Other_ID_Start and Other_ID_Continue additions
+        let class = |name| Expression::UnicodeClass(UnicodeClass::from_name(name).unwrap());
+        let start = || class("L") / class("Nl");
+        let grammar = [
+            ("Identifier", start() + ((start() / class("Mn") / class("Mc") / class("Nd") / class("Pc"))^0)),
+        ];
+        let mut compiler = Compiler::new();
+        compiler.compile_grammar(&grammar).unwrap();
+        let bytecode = compiler.emit();
+        assert_eq!(try_match(&bytecode, "Straße_2 = 1"), Ok("Straße_2".len()));
+        assert_eq!(try_match(&bytecode, "λόγος"), Ok("λόγος".len()));
+        assert_eq!(try_match(&bytecode, "變數١٢"), Ok("變數١٢".len()));
+        assert_eq!(try_match(&bytecode, "_x"), Err(MatchError::NoMatch(0)));
+        assert_eq!(try_match(&bytecode, "١x"), Err(MatchError::NoMatch(0)));
+    }
+
+    #[test]
+    fn test_named_classes() {
+        use pegatexto_vm::bytecode::instruction::InstructionIterator;
+        use pegatexto_vm::bytecode::opcode::Opcode;
+
+        let named = |name: &str| Expression::NamedClass(name.to_string());
+        let mut compiler = Compiler::new();
+        let ident_start = Expression::Class(CharacterClass::Alphabetic) / Expression::Char('_') / Expression::Char('$');
+        compiler.define_class("IdentStart", &ident_start).unwrap();
+        compiler.define_class("IdentRest", &(named("IdentStart") / Expression::Class(CharacterClass::Digit))).unwrap();
+        compiler.define_class("Sign", &Expression::Set("+-".to_string())).unwrap();
+        assert!(matches!(compiler.define_class("Bad", &(named("Sign") / named("Nope"))), Err(CompileError::UndefinedClass(name)) if name == "Nope"));
+        assert!(matches!(compiler.define_class("Bad", &Expression::Literal("ab".to_string())), Err(CompileError::InvalidClass(name)) if name == "Bad"));
+
+        let grammar = [("Identifier", (named("Sign")^(-1)) + named("IdentStart") + (named("IdentRest")^0))];
+        compiler.compile_grammar(&grammar).unwrap();
+        let bytecode = compiler.emit();
+        assert_eq!(try_match(&bytecode, "$foo_1 = 2"), Ok("$foo_1".len()));
+        assert_eq!(try_match(&bytecode, "-жук9"), Ok("-жук9".len()));
+        assert_eq!(try_match(&bytecode, "1x"), Err(MatchError::NoMatch(0)));
+        let instructions: Vec<_> = InstructionIterator::new(&bytecode).collect();
+        let count = |opcode: Opcode| instructions.iter().filter(|instruction| instruction.opcode() as u8 == opcode as u8).count();
+        assert_eq!(count(Opcode::ClassSet), 2);
+        assert_eq!(count(Opcode::Charset), 1);
+
+        let undefined = [("Main", named("Nope"))];
+        assert!(matches!(Compiler::new().compile_grammar(&undefined), Err(CompileError::UndefinedClass(name)) if name == "Nope"));
+    }
+
+    #[test]
+    fn test_compile_no_case() {
+        let keyword = |s: &str| Expression::LiteralNoCase(s.to_string());
+        for optimize in [false, true] {
+            let grammar = [
+                ("Select", keyword("select") + Expression::Char(' ') + (Expression::CharNoCase('k') / keyword("x-header") / keyword("123"))
+                    + Expression::SetNoCase("ßé".to_string())),
+            ];
+            let mut compiler = Compiler::with_options(CompileOptions { optimize, ..CompileOptions::default() });
+            compiler.compile_grammar(&grammar).unwrap();
+            let bytecode = compiler.emit();
+            assert_eq!(try_match(&bytecode, "SELECT kß"), Ok("SELECT kß".len()));
+            assert_eq!(try_match(&bytecode, "Select \u{212A}É"), Ok("Select \u{212A}É".len()));
+            assert_eq!(try_match(&bytecode, "select X-Header\u{1E9E}"), Ok("select X-Header\u{1E9E}".len()));
+            assert_eq!(try_match(&bytecode, "select 123é"), Ok("select 123é".len()));
+            assert_eq!(try_match(&bytecode, "selectk"), Err(MatchError::NoMatch(6)));
+            assert_eq!(try_match(&bytecode, "select ks"), Err(MatchError::NoMatch(8)));
+        }
+    }
+
+    #[test]
+    fn test_compile_behind() {
+        let literal = |s: &str| Expression::Literal(s.to_string());
+        let behind = |e| Expression::Behind(Box::new(e));
+        let alnum = || Expression::Class(CharacterClass::Alphanumeric);
+        let word = || ("Word", Expression::NotBehind(Box::new(alnum())) + alnum());
+        for optimize in [false, true] {
+            let compile = |grammar: &[(&str, Expression)]| {
+                let mut compiler = Compiler::with_options(CompileOptions { optimize, ..CompileOptions::default() });
+                compiler.compile_grammar(grammar).unwrap();
+                compiler.emit_owned()
+            };
+            let after_ab = compile(&[("Main", ((behind(literal("ab")) + Expression::Char('c')) / Expression::Set("ab ".to_string()))^0)]);
+            assert_eq!(try_match(&after_ab.as_bytecode(), "abc ac"), Ok(5));
+            let words = compile(&[("Main", (Expression::Char(' ') / Expression::NonTerminal("Word".to_string()))^0), word()]);
+            assert_eq!(try_match(&words.as_bytecode(), "a 1 x"), Ok(5));
+            assert_eq!(try_match(&words.as_bytecode(), "a b1"), Ok(3));
+            let choice = compile(&[
+                ("Choice", Expression::Any + Expression::Any + behind(literal("αβ") / Expression::Char('x') / Expression::NonTerminal("Word".to_string()))),
+                word(),
+            ]);
+            let choice = choice.as_bytecode();
+            assert_eq!(try_match(&choice, "αβ"), Ok(4));
+            assert_eq!(try_match(&choice, "_x"), Ok(2));
+            assert_eq!(try_match(&choice, "_1"), Ok(2));
+            assert!(try_match(&choice, "11").is_err());
+            assert_eq!(try_match(&choice, "x"), Err(MatchError::NoMatch(1)));
+        }
+
+        let unbounded = [("Main", behind(Expression::Char('a')^0))];
+        assert!(matches!(Compiler::new().compile_grammar(&unbounded), Err(CompileError::UnboundedLookbehind)));
+    }
+
+    #[test]
+    fn test_compile_bounded_behind() {
+        use pegatexto_vm::interpreter::Interpreter;
+
+        let cases = [
+            ("S <- ('ab' / 'a' / 'b' / 'xb') <&('a' 'b'?) 'c'", ["abc", "ac", "bc", "xbc"], [Some(3), Some(2), None, None]),
+            ("S <- ('ad' / 'bcd' / 'xd' / 'd') <&(('a' / 'bc') 'd') 'c'", ["adc", "bcdc", "xdc", "dc"], [Some(3), Some(4), None, None]),
+            ("S <- ('ab' / 'x')? <&'ab'? 'c'", ["c", "abc", "xc", "ab"], [Some(1), Some(3), Some(2), None]),
+            ("S <- 'x'? <!'ab'? 'c'", ["c", "xc", "abc", "x"], [None, None, None, None]),
+        ];
+        for (source, inputs, expected) in cases.iter() {
+            let grammar = syntax::parse_grammar(source).unwrap();
+            let interpreter = Interpreter::new(&grammar.rules);
+            for optimize in [false, true] {
+                let mut compiler = Compiler::with_options(CompileOptions { optimize, ..CompileOptions::default() });
+                compiler.compile_parsed(&grammar).unwrap();
+                let bytecode = compiler.emit();
+                for (input, expected) in inputs.iter().zip(expected.iter()) {
+                    assert_eq!(&try_match(&bytecode, input).ok(), expected, "{} on {:?}", source, input);
+                    assert_eq!(interpreter.try_match(input).ok(), *expected, "{} on {:?}", source, input);
+                }
+            }
+        }
+
+        let too_long = [("Main", Expression::Behind(Box::new(Expression::Any^(-256))))];
+        assert!(matches!(Compiler::new().compile_grammar(&too_long), Err(CompileError::UnboundedLookbehind)));
+    }
+
+    #[test]
+    fn test_compile_nullable_loops() {
+        use pegatexto_vm::interpreter::Interpreter;
+
+        let cases = [
+            ("S <- ('a'?)* 'b'", ["b", "aab", "c"], [Some(1), Some(3), None]),
+            ("S <- ('a'?)^2 'b'", ["b", "ab", "aac"], [Some(1), Some(2), None]),
+            ("S <- ('a'* / 'b')^-3 'c'", ["c", "aac", "abc"], [Some(1), Some(3), None]),
+            ("S <- A* 'y'\nA <- 'x' / &'y'", ["y", "xxy", "xz"], [Some(1), Some(3), None]),
+        ];
+        for (source, inputs, expected) in cases.iter() {
+            let grammar = syntax::parse_grammar(source).unwrap();
+            let interpreter = Interpreter::new(&grammar.rules);
+            for optimize in [false, true] {
+                let mut compiler = Compiler::with_options(CompileOptions { optimize, ..CompileOptions::default() });
+                compiler.compile_parsed(&grammar).unwrap();
+                let bytecode = compiler.emit();
+                for (input, expected) in inputs.iter().zip(expected.iter()) {
+                    assert_eq!(&try_match(&bytecode, input).ok(), expected, "{} on {:?}", source, input);
+                    assert_eq!(interpreter.try_match(input).ok(), *expected, "{} on {:?}", source, input);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_compile_end_of_input() {
+        use pegatexto_vm::matcher::try_match_full;
+
+        for optimize in [false, true] {
+            let digits = || Expression::Class(CharacterClass::Digit)^1;
+            let mut compiler = Compiler::with_options(CompileOptions { optimize, ..CompileOptions::default() });
+            compiler.compile_grammar(&[("Main", (digits() + Expression::EndOfInput) / (digits() + Expression::Char(';')))]).unwrap();
+            let bytecode = compiler.emit();
+            assert_eq!(try_match(&bytecode, "123"), Ok(3));
+            assert_eq!(try_match(&bytecode, "12;3"), Ok(3));
+            assert_eq!(try_match(&bytecode, "12x"), Err(MatchError::NoMatch(2)));
+            assert_eq!(try_match_full(&bytecode, "12;"), Ok(3));
+            assert_eq!(try_match_full(&bytecode, "12;3"), Err(MatchError::TrailingInput(3)));
+        }
+    }
+
+    #[test]
+    fn test_compile_quantifiers() {
+        let ab = || Expression::Char('a') + Expression::Char('b');
+        for optimize in [false, true] {
+            let compile = |quantifier| {
+                let mut compiler = Compiler::with_options(CompileOptions { optimize, ..CompileOptions::default() });
+                compiler.compile_grammar(&[("Main", (ab()^quantifier) + Expression::Char('.'))]).unwrap();
+                compiler.emit_owned()
+            };
+            let at_least_two = compile(2);
+            assert_eq!(try_match(&at_least_two.as_bytecode(), "ababab."), Ok(7));
+            assert_eq!(try_match(&at_least_two.as_bytecode(), "ab."), Err(MatchError::NoMatch(2)));
+            let at_most_two = compile(-2);
+            assert_eq!(try_match(&at_most_two.as_bytecode(), "."), Ok(1));
+            assert_eq!(try_match(&at_most_two.as_bytecode(), "abab."), Ok(5));
+            assert_eq!(try_match(&at_most_two.as_bytecode(), "ababab."), Err(MatchError::NoMatch(4)));
+        }
+        let too_many = [("Main", Expression::Any^256)];
+        assert!(matches!(Compiler::new().compile_grammar(&too_many), Err(CompileError::QuantifierOutOfRange(256))));
+    }
+}
@@ -1,9 +1,18 @@
 use pegatexto_compiler::Compiler;
+use pegatexto_compiler::syntax::parse_grammar;
 
-use pegatexto_disassembler::dump_bytecode;
+use pegatexto_disassembler::dump_bytecode_with_symbols;
 use pegatexto_vm::grammar::character_class::CharacterClass;
 use pegatexto_vm::grammar::expression::Expression;
 use pegatexto_vm::matcher::*;
+use pegatexto_vm::profiler::Profiler;
+use pegatexto_vm::source_map::LineIndex;
+use pegatexto_vm::tracer::{Debugger, PrintTracer};
+
+use std::env;
+use std::fs;
+use std::io;
+use std::process;
 
 fn test_grammar() -> [(&'static str, Expression); 7] {
     use Expression::*;
@@ -30,16 +39,73 @@ fn test_grammar() -> [(&'static str, Expression); 7] {
     ]
 }
 
+fn read(path: &str) -> String {
+    fs::read_to_string(path).unwrap_or_else(|error| {
+        eprintln!("{}: {}", path, error);
+        process::exit(2);
+    })
+}
+
+// usage: [--trace | --debug | --profile] [<grammar> <input>], matching the
+// test grammar when no paths are given
 fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let (mode, paths) = match args.split_first() {
+        Some((mode, paths)) if mode.starts_with("--") => (Some(mode.as_str()), paths),
+        _ => (None, args.as_slice()),
+    };
     let mut compiler = Compiler::new();
-
-    compiler.compile_grammar(&test_grammar()).unwrap();
+    let text = match paths {
+        [] => {
+            compiler.compile_grammar(&test_grammar()).unwrap();
+            "3 + 5*9 / (1+1) - 12".to_string()
+        },
+        [grammar, input] => {
+            let source = read(grammar);
+            let compiled = parse_grammar(&source).map_err(|error| error.to_string())
+                .and_then(|grammar| compiler.compile_parsed(&grammar).map_err(|error| error.to_string()));
+            if let Err(error) = compiled {
+                eprintln!("{}: {}", grammar, error);
+                process::exit(1);
+            }
+            read(input)
+        },
+        _ => {
+            eprintln!("usage: [--trace | --debug | --profile] [<grammar> <input>]");
+            process::exit(2);
+        },
+    };
+    let text = text.as_str();
     let bytecode = compiler.emit();
-    dump_bytecode(&bytecode);
+    dump_bytecode_with_symbols(&bytecode, &compiler.symbols());
 
-    let result = try_match_then(&bytecode, "3 + 5*9 / (1+1) - 12", |s, i, args| {
-        println!("!! ACTION {:?} {} {}", s, i, args.len());
-        args.iter().max().copied().unwrap_or(s.len())
-    });
-    println!("{:?}", result);
+    let action = |capture: &Capture, args: &[usize]| {
+        println!("!! ACTION {:?} {} {}", capture.text, capture.id, args.len());
+        args.iter().max().copied().unwrap_or(capture.text.len())
+    };
+    let result = match mode {
+        Some("--trace") => {
+            let mut tracer = PrintTracer::new(io::stdout(), compiler.symbols());
+            try_match_traced_then(&bytecode, text, &mut tracer, action)
+        },
+        Some("--debug") => {
+            let stdin = io::stdin();
+            let mut debugger = Debugger::new(stdin.lock(), io::stdout(), compiler.symbols());
+            try_match_traced_then(&bytecode, text, &mut debugger, action)
+        },
+        Some("--profile") => {
+            let mut profiler = Profiler::new(compiler.symbols()).with_tail_calls(compiler.tail_calls());
+            let result = try_match_traced_then(&bytecode, text, &mut profiler, action);
+            profiler.write_report(&mut io::stdout()).unwrap();
+            result
+        },
+        _ => try_match_captures_then(&bytecode, text, action),
+    };
+    match result {
+        Ok(result) => println!("{:?}", result),
+        Err(err) => match err.position(&LineIndex::new(text)) {
+            Some(position) => println!("{}: {}", position, err),
+            None => println!("{}", err),
+        },
+    }
 }
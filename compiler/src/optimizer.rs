@@ -0,0 +1,538 @@
+use crate::program::{Label, Op, Program};
+
+use pegatexto_vm::bytecode::instruction::Instruction;
+
+use std::collections::{HashMap, HashSet};
+
+const MAX_PASSES: usize = 16;
+
+/// Rewrites `program` until no pass makes progress.
+///
+/// `roots` are the labels that must stay reachable, such as rule entries.
+pub fn optimize(program: &mut Program, roots: &[Label]) {
+    // jump cycles can be threaded forever, so the number of passes is bounded
+    for _ in 0..MAX_PASSES {
+        let mut changed = tail_calls(program);
+        changed |= thread_jumps(program);
+        changed |= simplify_known_branches(program);
+        changed |= remove_redundant_backtracking(program);
+        changed |= remove_jumps_to_next(program);
+        changed |= remove_dead_code(program, roots);
+        changed |= remove_unused_labels(program, roots);
+        if !changed {
+            break;
+        }
+    }
+}
+
+fn label_after(program: &mut Program, index: usize) -> Label {
+    if let Some(Op::Label(label)) = program.ops.get(index + 1) {
+        return *label;
+    }
+    let label = program.new_label();
+    program.ops.insert(index + 1, Op::Label(label));
+    label
+}
+
+fn thread_jumps(program: &mut Program) -> bool {
+    let mut changed = false;
+    let mut positions = program.label_positions();
+    for index in 0..program.ops.len() {
+        let op = program.ops[index].clone();
+        let target_index = match op.target().and_then(|target| positions.get(&target)) {
+            Some(position) => program.skip_labels(*position),
+            None => continue,
+        };
+        let target = op.target().unwrap();
+        let target_op = program.ops.get(target_index).cloned();
+        let next_op = program.ops.get(program.skip_labels(target_index + 1)).cloned();
+        let new_op = match (&op, &target_op) {
+            // jump to a jump
+            (Op::Jump(_), Some(Op::Jump(l)))
+            | (Op::JumpIfFail(_), Some(Op::Jump(l)))
+            | (Op::JumpIfSuccess(_), Some(Op::Jump(l)))
+            | (Op::JumpIfFail(_), Some(Op::JumpIfFail(l)))
+            | (Op::JumpIfSuccess(_), Some(Op::JumpIfSuccess(l)))
+            | (Op::TestSet(_, _), Some(Op::Jump(l)))
+            | (Op::TestSet(_, _), Some(Op::JumpIfFail(l))) if *l != target => {
+                let mut new_op = op.clone();
+                new_op.set_target(*l);
+                Some(new_op)
+            },
+            // conditional jump to the opposite condition, which is never taken
+            (Op::JumpIfFail(_), Some(Op::JumpIfSuccess(_)))
+            | (Op::TestSet(_, _), Some(Op::JumpIfSuccess(_)))
+            | (Op::JumpIfSuccess(_), Some(Op::JumpIfFail(_))) if target_index > index => {
+                let fallthrough = label_after(program, target_index);
+                positions = program.label_positions();
+                let mut new_op = op.clone();
+                new_op.set_target(fallthrough);
+                Some(new_op)
+            },
+            // jump to the end of a rule
+            (Op::Jump(_), Some(Op::Instruction(i @ Instruction::Return))) => Some(Op::Instruction(i.clone())),
+            // call to a rule that only calls another rule
+            (Op::Call(_), Some(Op::Call(l))) if *l != target && next_op == Some(Op::Instruction(Instruction::Return)) => {
+                Some(Op::Call(*l))
+            },
+            (Op::Call(_), Some(Op::Jump(l))) if *l != target => Some(Op::Call(*l)),
+            _ => None,
+        };
+        if let Some(new_op) = new_op {
+            if new_op != program.ops[index] {
+                program.ops[index] = new_op;
+                changed = true;
+            }
+        }
+    }
+    changed
+}
+
+// `Call` right before `Return` jumps instead, so the called rule returns
+// straight to our caller and no state is pushed
+fn tail_calls(program: &mut Program) -> bool {
+    let mut changed = false;
+    for index in 0..program.ops.len() {
+        if let Op::Call(label) = program.ops[index] {
+            let next = program.skip_labels(index + 1);
+            if program.ops.get(next) == Some(&Op::Instruction(Instruction::Return)) {
+                program.ops[index] = Op::Jump(label);
+                changed = true;
+            }
+        }
+    }
+    changed
+}
+
+// Branches right after `Succeed` or `Fail` always go the same way
+fn simplify_known_branches(program: &mut Program) -> bool {
+    let mut changed = false;
+    let mut index = 1;
+    while index < program.ops.len() {
+        let success = match program.ops[index - 1] {
+            Op::Instruction(Instruction::Succeed) => true,
+            Op::Instruction(Instruction::Fail) => false,
+            _ => {
+                index += 1;
+                continue;
+            },
+        };
+        match program.ops[index] {
+            Op::JumpIfFail(_) if success => {
+                program.ops.remove(index);
+                changed = true;
+                continue;
+            },
+            Op::JumpIfSuccess(_) if !success => {
+                program.ops.remove(index);
+                changed = true;
+                continue;
+            },
+            Op::JumpIfFail(label) | Op::JumpIfSuccess(label) => {
+                program.ops[index] = Op::Jump(label);
+                changed = true;
+            },
+            _ => (),
+        }
+        index += 1;
+    }
+    changed
+}
+
+// Instructions that don't touch the text position, captures or the backtrack stack
+fn is_pure(op: &Op) -> bool {
+    matches!(op,
+        Op::Instruction(Instruction::Succeed)
+        | Op::Instruction(Instruction::Fail)
+        | Op::Instruction(Instruction::ToggleSuccess))
+}
+
+fn remove_redundant_backtracking(program: &mut Program) -> bool {
+    let mut changed = false;
+    let mut index = 0;
+    while index < program.ops.len() {
+        if program.ops[index] != Op::Instruction(Instruction::Push) {
+            index += 1;
+            continue;
+        }
+        let mut end = index + 1;
+        while program.ops.get(end).is_some_and(is_pure) {
+            end += 1;
+        }
+        let peek = Op::Instruction(Instruction::Peek);
+        let pop = Op::Instruction(Instruction::Pop);
+        match (program.ops.get(end), program.ops.get(end + 1)) {
+            (Some(a), Some(b)) if *a == peek && *b == pop => {
+                program.ops.drain(end..end + 2);
+                program.ops.remove(index);
+                changed = true;
+            },
+            (Some(a), _) if *a == pop => {
+                program.ops.remove(end);
+                program.ops.remove(index);
+                changed = true;
+            },
+            _ => index += 1,
+        }
+    }
+    changed
+}
+
+fn remove_jumps_to_next(program: &mut Program) -> bool {
+    let mut changed = false;
+    let mut index = 0;
+    while index < program.ops.len() {
+        // a test jumping to the next op still sets the failure flag
+        let is_jump = matches!(program.ops[index], Op::Jump(_) | Op::JumpIfFail(_) | Op::JumpIfSuccess(_));
+        let jumps_to_next = match program.ops[index].target() {
+            Some(target) if is_jump => program.ops[index + 1..]
+                .iter()
+                .take_while(|op| op.is_label())
+                .any(|op| *op == Op::Label(target)),
+            _ => false,
+        };
+        if jumps_to_next {
+            program.ops.remove(index);
+            changed = true;
+        }
+        else {
+            index += 1;
+        }
+    }
+    changed
+}
+
+fn remove_dead_code(program: &mut Program, roots: &[Label]) -> bool {
+    let positions = program.label_positions();
+    let mut reachable = vec![false; program.ops.len()];
+    let mut pending: Vec<usize> = roots.iter().filter_map(|root| positions.get(root).copied()).collect();
+    pending.push(0);
+    while let Some(index) = pending.pop() {
+        if index >= program.ops.len() || reachable[index] {
+            continue;
+        }
+        reachable[index] = true;
+        let op = &program.ops[index];
+        if let Some(target) = op.target() {
+            if let Some(position) = positions.get(&target) {
+                pending.push(*position);
+            }
+        }
+        if !op.is_terminal() {
+            pending.push(index + 1);
+        }
+    }
+    let before = program.ops.len();
+    let mut index = 0;
+    program.ops.retain(|op| {
+        index += 1;
+        op.is_label() || reachable[index - 1]
+    });
+    program.ops.len() != before
+}
+
+fn remove_unused_labels(program: &mut Program, roots: &[Label]) -> bool {
+    let mut used: HashSet<Label> = roots.iter().copied().collect();
+    used.extend(program.ops.iter().filter_map(Op::target));
+    let before = program.ops.len();
+    program.ops.retain(|op| match op {
+        Op::Label(label) => used.contains(label),
+        _ => true,
+    });
+    program.ops.len() != before
+}
+
+// Ops of each rule between its entry label and its `Return`, as emitted by
+// the compiler before any optimization
+fn rule_bodies<'a>(program: &Program<'a>, rules: &[Label]) -> HashMap<Label, Vec<Op<'a>>> {
+    let positions = program.label_positions();
+    let mut bodies = HashMap::new();
+    for rule in rules {
+        let start = match positions.get(rule) {
+            Some(position) => position + 1,
+            None => continue,
+        };
+        let body = program.ops[start..]
+            .iter()
+            .take_while(|op| **op != Op::Instruction(Instruction::Return))
+            .cloned()
+            .collect();
+        bodies.insert(*rule, body);
+    }
+    bodies
+}
+
+fn recursive_rules(bodies: &HashMap<Label, Vec<Op>>) -> HashSet<Label> {
+    let calls = |rule: &Label| -> Vec<Label> {
+        bodies.get(rule)
+            .map(|body| body.iter().filter_map(|op| match op {
+                Op::Call(label) => Some(*label),
+                _ => None,
+            }).collect())
+            .unwrap_or_default()
+    };
+    bodies.keys()
+        .copied()
+        .filter(|rule| {
+            let mut visited = HashSet::new();
+            let mut pending = calls(rule);
+            while let Some(callee) = pending.pop() {
+                if callee == *rule {
+                    return true;
+                }
+                if visited.insert(callee) {
+                    pending.extend(calls(&callee));
+                }
+            }
+            false
+        })
+        .collect()
+}
+
+/// Replaces calls to non-recursive rules with at most `threshold` ops by a
+/// copy of their body. Must run before [`optimize`], while every rule body
+/// still ends with its own `Return`.
+pub fn inline_rules(program: &mut Program, rules: &[Label], threshold: usize) {
+    if threshold == 0 {
+        return;
+    }
+    let recursive = recursive_rules(&rule_bodies(program, rules));
+    // rules inlined into a caller may make it small enough to be inlined too,
+    // which takes at most one round per rule since none of them is recursive
+    for _ in 0..rules.len() {
+        let inlinable: HashMap<_, _> = rule_bodies(program, rules)
+            .into_iter()
+            .filter(|(rule, body)| {
+                !recursive.contains(rule) && body.iter().filter(|op| !op.is_label()).count() <= threshold
+            })
+            .collect();
+        let ops = std::mem::take(&mut program.ops);
+        let mut changed = false;
+        for op in ops {
+            let body = match op {
+                Op::Call(label) => inlinable.get(&label),
+                _ => None,
+            };
+            let body = match body {
+                Some(body) => body,
+                None => {
+                    program.ops.push(op);
+                    continue;
+                },
+            };
+            let mut renamed = HashMap::new();
+            for op in body.iter() {
+                if let Op::Label(label) = op {
+                    renamed.insert(*label, program.new_label());
+                }
+            }
+            for op in body.iter() {
+                let mut op = op.clone();
+                match op {
+                    Op::Label(ref mut label) => *label = renamed[label],
+                    _ => if let Some(target) = op.target().and_then(|target| renamed.get(&target)) {
+                        op.set_target(*target);
+                    },
+                }
+                program.ops.push(op);
+            }
+            changed = true;
+        }
+        if !changed {
+            break;
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::{CompileOptions, Compiler};
+
+    use pegatexto_vm::grammar::character_class::CharacterClass;
+    use pegatexto_vm::grammar::expression::Expression::{self, *};
+    use pegatexto_vm::bytecode::instruction::InstructionIterator;
+    use pegatexto_vm::bytecode::instruction::Instruction::{Call, Charset, NotCharset, NotSet, Range as ByteRange, Set as StringSet, TestSet};
+    use pegatexto_vm::bytecode::verifier::verify;
+    use pegatexto_vm::matcher::{try_match_captures_then, try_match_traced, Capture};
+    use pegatexto_vm::tracer::{Step, TraceControl, Tracer};
+
+    fn nt(name: &str) -> Expression {
+        NonTerminal(name.to_string())
+    }
+
+    fn arithmetic_grammar() -> Vec<(&'static str, Expression)> {
+        vec![
+            ("Exp", nt("Term") + ((nt("TermOp") + nt("Term"))^0)),
+            ("Term", nt("Factor") + ((nt("FactorOp") + nt("Factor"))^0)),
+            ("Factor", nt("Number") / (Char('(') + nt("Sp") + nt("Exp") + Char(')') + nt("Sp"))),
+            ("Sp", Class(CharacterClass::Whitespace)^0),
+            ("Number", ((Char('-')^(-1)) + (Class(CharacterClass::Digit)^1) + nt("Sp")) >> "number"),
+            ("TermOp", Set("+-".to_string()) + nt("Sp")),
+            ("FactorOp", Set("*/".to_string()) + nt("Sp")),
+        ]
+    }
+
+    fn trampoline_grammar() -> Vec<(&'static str, Expression)> {
+        vec![
+            ("Main", nt("Word") + !Any),
+            ("Word", nt("Letters")),
+            ("Letters", (Class(CharacterClass::Alphabetic)^1) >> "letters"),
+            ("Unused", -(fail()) + !(succeed()) + Literal("dead".to_string())),
+        ]
+    }
+
+    fn fail() -> Expression {
+        !Sequence(vec![])
+    }
+
+    fn succeed() -> Expression {
+        Sequence(vec![])
+    }
+
+    fn compile(grammar: &[(&str, Expression)], optimize: bool) -> Compiler {
+        compile_with(grammar, CompileOptions { optimize, ..CompileOptions::default() })
+    }
+
+    fn compile_with(grammar: &[(&str, Expression)], options: CompileOptions) -> Compiler {
+        let mut compiler = Compiler::with_options(options);
+        compiler.compile_grammar(grammar).unwrap();
+        compiler
+    }
+
+    fn assert_same_matches(grammar: &[(&str, Expression)], inputs: &[&str]) {
+        let plain = compile(grammar, false);
+        let optimized = compile(grammar, true);
+        verify(&optimized.emit()).unwrap();
+        for input in inputs {
+            let collect = |capture: &Capture, args: &[String]| {
+                format!("{}{:?}{:?}", capture.id, capture.text, args)
+            };
+            assert_eq!(
+                try_match_captures_then(&plain.emit(), input, collect),
+                try_match_captures_then(&optimized.emit(), input, collect),
+                "input {:?}", input
+            );
+        }
+    }
+
+    fn assert_smaller(grammar: &[(&str, Expression)]) {
+        let options = CompileOptions { inline_threshold: 0, ..CompileOptions::default() };
+        assert!(compile_with(grammar, options).emit().len() < compile(grammar, false).emit().len());
+    }
+
+    #[test]
+    fn test_optimized_arithmetic() {
+        assert_same_matches(&arithmetic_grammar(), &[
+            "", "1", "-12", "1 + 2", "3 + 5*9 / (1+1) - 12", "(1", "1 +", "((2))", "a", "2 * (3 + x)",
+        ]);
+    }
+
+    #[test]
+    fn test_optimized_trampolines() {
+        assert_smaller(&trampoline_grammar());
+        assert_same_matches(&trampoline_grammar(), &["", "abc", "abc1", "1", "dead"]);
+    }
+
+    fn count_calls(compiler: &Compiler) -> usize {
+        InstructionIterator::new(&compiler.emit()).filter(|instruction| matches!(instruction, Call(_))).count()
+    }
+
+    // executed instructions and maximum state stack depth
+    fn run_stats(compiler: &Compiler, text: &str) -> (usize, usize) {
+        struct StatsTracer(usize, usize);
+        impl Tracer for StatsTracer {
+            fn trace(&mut self, step: &Step) -> TraceControl {
+                self.0 += 1;
+                self.1 = self.1.max(step.state_depth);
+                TraceControl::Continue
+            }
+        }
+        let mut tracer = StatsTracer(0, 0);
+        try_match_traced(&compiler.emit(), text, &mut tracer).unwrap();
+        (tracer.0, tracer.1)
+    }
+
+    #[test]
+    fn test_inline_rules() {
+        let grammar = arithmetic_grammar();
+        let inlined = compile(&grammar, true);
+        let not_inlined = compile_with(&grammar, CompileOptions { inline_threshold: 0, ..CompileOptions::default() });
+        assert!(count_calls(&inlined) < count_calls(&not_inlined));
+        // Sp is small enough to be inlined everywhere, but is still kept as a rule
+        let sp = inlined.symbols().address_of("Sp").unwrap();
+        assert!(!InstructionIterator::new(&inlined.emit()).any(|instruction| instruction == Call(sp)));
+
+        let recursive = vec![
+            ("List", Char('(') + (nt("List")^0) + Char(')')),
+        ];
+        assert_eq!(count_calls(&compile(&recursive, true)), 1);
+        assert_same_matches(&recursive, &["()", "(()())", "(()", ")"]);
+    }
+
+    #[test]
+    fn test_tail_calls() {
+        // A0 <- 'a' / A1, A1 <- 'b' / A2, ..., A9 <- 'z'
+        let names: Vec<String> = (0..10).map(|i| format!("A{}", i)).collect();
+        let mut grammar: Vec<_> = names.windows(2)
+            .enumerate()
+            .map(|(i, pair)| (pair[0].as_str(), Char((b'a' + i as u8) as char) / nt(&pair[1])))
+            .collect();
+        grammar.push(("A9", Char('z')));
+        let options = CompileOptions { inline_threshold: 0, ..CompileOptions::default() };
+        let optimized = compile_with(&grammar, options);
+        let plain = compile_with(&grammar, CompileOptions { optimize: false, ..options });
+        assert_eq!(count_calls(&optimized), 0);
+        assert_eq!(run_stats(&plain, "z").1, 9);
+        assert_eq!(run_stats(&optimized, "z").1, 0);
+        assert_eq!(optimized.tail_calls().len(), 9);
+        assert!(plain.tail_calls().is_empty());
+        assert_same_matches(&grammar, &["a", "e", "z", "y", "", "za"]);
+    }
+
+    #[test]
+    fn test_head_fail() {
+        let keyword = |word: &str| Literal(word.to_string()) + nt("Sp");
+        let grammar = vec![
+            ("Statement", nt("If") / nt("While") / nt("Return") / nt("Assign")),
+            ("If", keyword("if") + nt("Name") + nt("Statement")),
+            ("While", keyword("while") + nt("Name") + nt("Statement")),
+            ("Return", keyword("return") + nt("Name")),
+            ("Assign", nt("Name") + keyword("=") + nt("Name")),
+            ("Name", (Class(CharacterClass::Alphabetic)^1) + nt("Sp")),
+            ("Sp", Class(CharacterClass::Whitespace)^0),
+        ];
+        let options = CompileOptions { inline_threshold: 0, ..CompileOptions::default() };
+        let optimized = compile_with(&grammar, options);
+        let tests = InstructionIterator::new(&optimized.emit())
+            .filter(|instruction| matches!(instruction, TestSet(_, _)))
+            .count();
+        assert_eq!(tests, 3);
+        let plain = compile(&grammar, false);
+        let text = "while x while y return z";
+        for text in [text, "x = y"] {
+            assert!(run_stats(&optimized, text).0 < run_stats(&plain, text).0);
+        }
+        assert_same_matches(&grammar, &[
+            text, "if a b = c", "return", "returnx", "x = y", "wh = ile", "", "=", "if if",
+        ]);
+    }
+
+    #[test]
+    fn test_charsets() {
+        let word_start = || Range('a', 'z') / Range('A', 'Z') / Char('_');
+        let grammar = vec![
+            ("Tokens", (nt("Token") + (Set(" \t".to_string())^0))^0),
+            ("Token", nt("Ident") / nt("Other")),
+            ("Ident", word_start() + ((word_start() / Class(CharacterClass::Digit))^0)),
+            ("Other", InverseSet("abc".to_string()) / Char('b') / Literal("cc".to_string())),
+        ];
+        let optimized = compile(&grammar, true);
+        let bytecode = optimized.emit();
+        let instructions: Vec<_> = InstructionIterator::new(&bytecode).collect();
+        let charsets = instructions.iter().filter(|i| matches!(i, Charset(_) | NotCharset(_))).count();
+        assert!(charsets >= 4);
+        assert!(!instructions.iter().any(|i| matches!(i, StringSet(_) | NotSet(_) | ByteRange(_, _))));
+        assert_same_matches(&grammar, &["", "abc", "_a1 Z_9", "1", "a", "b", "c", "cc", "α", "x\ty"]);
+    }
+}
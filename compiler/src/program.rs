@@ -0,0 +1,143 @@
+use pegatexto_vm::bytecode::address::Address;
+use pegatexto_vm::bytecode::builder::{BuildError, Builder as BytecodeBuilder};
+use pegatexto_vm::bytecode::class_set::ClassSet;
+use pegatexto_vm::bytecode::instruction::Instruction;
+
+use std::collections::HashMap;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Label(usize);
+
+/// Instruction list with symbolic jump targets, encoded into bytecode
+/// once every label is placed.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Op<'a> {
+    Label(Label),
+    Jump(Label),
+    JumpIfFail(Label),
+    JumpIfSuccess(Label),
+    Call(Label),
+    /// Fails and jumps unless the next char is in the set
+    TestSet(String, Label),
+    /// Encoded `ClassSet` instruction argument
+    ClassSet(Vec<u8>),
+    Instruction(Instruction<'a>),
+}
+
+impl Op<'_> {
+    pub fn target(&self) -> Option<Label> {
+        match *self {
+            Op::Jump(label) | Op::JumpIfFail(label) | Op::JumpIfSuccess(label) | Op::Call(label) | Op::TestSet(_, label) => Some(label),
+            _ => None,
+        }
+    }
+
+    pub fn set_target(&mut self, target: Label) {
+        match self {
+            Op::Jump(label) | Op::JumpIfFail(label) | Op::JumpIfSuccess(label) | Op::Call(label) | Op::TestSet(_, label) => *label = target,
+            _ => (),
+        }
+    }
+
+    pub fn is_label(&self) -> bool {
+        matches!(self, Op::Label(_))
+    }
+
+    /// Whether execution never continues to the next op
+    pub fn is_terminal(&self) -> bool {
+        matches!(self, Op::Jump(_) | Op::Instruction(Instruction::Return) | Op::Instruction(Instruction::Halt(_)))
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Program<'a> {
+    pub ops: Vec<Op<'a>>,
+    label_count: usize,
+}
+
+/// Addresses of an encoded program
+pub struct Encoding {
+    pub addresses: HashMap<Label, Address>,
+    /// Address and target of each `Jump`
+    pub jumps: Vec<(Address, Label)>,
+}
+
+impl<'a> Program<'a> {
+    pub fn new() -> Program<'a> {
+        Program { ops: Vec::new(), label_count: 0 }
+    }
+
+    pub fn new_label(&mut self) -> Label {
+        self.label_count += 1;
+        Label(self.label_count - 1)
+    }
+
+    pub fn place(&mut self, label: Label) {
+        self.ops.push(Op::Label(label));
+    }
+
+    pub fn push(&mut self, op: Op<'a>) {
+        self.ops.push(op);
+    }
+
+    pub fn push_instruction(&mut self, instruction: Instruction<'a>) {
+        self.ops.push(Op::Instruction(instruction));
+    }
+
+    pub fn label_positions(&self) -> HashMap<Label, usize> {
+        self.ops.iter()
+            .enumerate()
+            .filter_map(|(i, op)| match op {
+                Op::Label(label) => Some((*label, i)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Index of the first op that is not a label, starting at `index`
+    pub fn skip_labels(&self, index: usize) -> usize {
+        let mut index = index;
+        while self.ops.get(index).is_some_and(Op::is_label) {
+            index += 1;
+        }
+        index
+    }
+
+    /// Appends the encoded program to `builder`, returning where its labels and jumps went.
+    /// Jumps to labels that were never placed are left pointing to address 0.
+    pub fn encode(&self, builder: &mut BytecodeBuilder) -> Result<Encoding, BuildError> {
+        let mut addresses = HashMap::new();
+        let mut patches = Vec::new();
+        let mut jumps = Vec::new();
+        for op in self.ops.iter() {
+            let address = builder.current_address()?;
+            let instruction = match *op {
+                Op::Label(label) => {
+                    addresses.insert(label, address);
+                    continue;
+                },
+                Op::Jump(label) => {
+                    patches.push((address, label));
+                    jumps.push((address, label));
+                    Instruction::Jump(Address::default())
+                },
+                Op::JumpIfFail(label) => { patches.push((address, label)); Instruction::JumpIfFail(Address::default()) },
+                Op::JumpIfSuccess(label) => { patches.push((address, label)); Instruction::JumpIfSuccess(Address::default()) },
+                Op::Call(label) => { patches.push((address, label)); Instruction::Call(Address::default()) },
+                Op::TestSet(ref set, label) => { patches.push((address, label)); Instruction::TestSet(Address::default(), set) },
+                Op::ClassSet(ref bytes) => {
+                    Instruction::ClassSet(ClassSet::parse(bytes).expect("class set encoded by the compiler").0)
+                },
+                Op::Instruction(ref instruction) => instruction.clone(),
+            };
+            builder.push_instruction(&instruction);
+        }
+        builder.current_address()?;
+        for (address, label) in patches {
+            if let Some(target) = addresses.get(&label) {
+                builder.patch_jump(address, *target);
+            }
+        }
+        Ok(Encoding { addresses, jumps })
+    }
+}
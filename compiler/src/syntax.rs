@@ -0,0 +1,598 @@
+//! Textual grammar syntax, in the style of `assembler/assembly_grammar.txt`:
+//!
+//! ```text
+//! # comments run to the end of the line
+//! @IdentStart <- \a / '_'          # class, referenced as @IdentStart
+//! Ident <- {@IdentStart \w*}       # capture named after the rule
+//! List <- {item: Ident} (',' Ident)* !.
+//! ```
+//!
+//! Terminals are `'lit'`/`"lit"`, `[set]`/`[^set]` with ranges and class
+//! escapes, `.`, `\d`-style classes and `\p{Greek}`. A trailing `i` makes
+//! literals and sets case-insensitive. Prefixes are `&`, `!`, `<&` and `<!`
+//! (lookbehind), with `!.` being the end of input. Suffixes are `*`, `+`,
+//! `?` and `^n`, which means at least `n` or at most `-n` repetitions.
+
+use pegatexto_vm::grammar::character_class::CharacterClass;
+use pegatexto_vm::grammar::expression::Expression;
+use pegatexto_vm::grammar::unicode::UnicodeClass;
+use pegatexto_vm::source_map::{LineIndex, Position};
+
+use std::convert::TryFrom;
+use std::fmt;
+use std::ops::Range;
+
+/// Parsed grammar, borrowing rule and class names from its source
+#[derive(Clone, Debug, PartialEq)]
+pub struct Grammar<'a> {
+    /// Classes in definition order, for `Compiler::define_class`
+    pub classes: Vec<(&'a str, Expression)>,
+    pub rules: Vec<(&'a str, Expression)>,
+}
+
+impl<'a> Grammar<'a> {
+    /// Replaces each rule's expression by `f` of its name and expression
+    pub fn map_rules<F: FnMut(&str, Expression) -> Expression>(self, mut f: F) -> Grammar<'a> {
+        let rules = self.rules.into_iter().map(|(name, expr)| (name, f(name, expr))).collect();
+        Grammar { classes: self.classes, rules }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum SyntaxErrorKind {
+    Expected(&'static str),
+    UnterminatedLiteral,
+    UnterminatedSet,
+    EmptySet,
+    InvalidEscape,
+    InvalidRange,
+    UnknownUnicodeClass(String),
+    /// Case-insensitive set with ranges, classes or negated
+    InvalidNoCaseSet,
+    InvalidQuantifier,
+    DuplicateDefinition(String),
+}
+
+#[derive(Debug, PartialEq)]
+pub struct SyntaxError {
+    pub kind: SyntaxErrorKind,
+    pub offset: usize,
+}
+
+impl SyntaxError {
+    pub fn position(&self, index: &LineIndex) -> Position {
+        index.position(self.offset)
+    }
+}
+
+impl fmt::Display for SyntaxError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.kind {
+            SyntaxErrorKind::Expected(what) => write!(f, "expected {}", what),
+            SyntaxErrorKind::UnterminatedLiteral => write!(f, "unterminated literal"),
+            SyntaxErrorKind::UnterminatedSet => write!(f, "unterminated set"),
+            SyntaxErrorKind::EmptySet => write!(f, "empty set"),
+            SyntaxErrorKind::InvalidEscape => write!(f, "invalid escape sequence"),
+            SyntaxErrorKind::InvalidRange => write!(f, "invalid range"),
+            SyntaxErrorKind::UnknownUnicodeClass(name) => write!(f, "unknown unicode class {:?}", name),
+            SyntaxErrorKind::InvalidNoCaseSet => write!(f, "case-insensitive sets may only list chars"),
+            SyntaxErrorKind::InvalidQuantifier => write!(f, "invalid quantifier count"),
+            SyntaxErrorKind::DuplicateDefinition(name) => write!(f, "{} is already defined", name),
+        }
+    }
+}
+
+/// Definition as written in the source, before grammar-wide checks like duplicates
+pub struct Definition<'a> {
+    pub name: &'a str,
+    pub is_class: bool,
+    pub expr: Expression,
+    /// Source of the definition, without the space and comments after it
+    pub span: Range<usize>,
+}
+
+pub fn parse_definitions(source: &str) -> Result<Vec<Definition<'_>>, SyntaxError> {
+    parse_with_comments(source).map(|(definitions, _)| definitions)
+}
+
+/// Parses the definitions along with the spans of the comments, in source order
+pub(crate) fn parse_with_comments(source: &str) -> Result<(Vec<Definition<'_>>, Vec<Range<usize>>), SyntaxError> {
+    let mut parser = Parser { source, pos: 0, rule: "", comments: Vec::new() };
+    let mut definitions = Vec::new();
+    parser.skip_space();
+    while parser.pos < source.len() {
+        let start = parser.pos;
+        let is_class = parser.eat("@");
+        let name = parser.identifier().ok_or_else(|| parser.error_at(start, SyntaxErrorKind::Expected("rule definition")))?;
+        parser.skip_space();
+        if !parser.eat("<-") {
+            return Err(parser.error(SyntaxErrorKind::Expected("'<-'")));
+        }
+        parser.skip_space();
+        parser.rule = name;
+        let expr = parser.choice()?;
+        // the expression ends before the space and comments skipped after it
+        let mut end = start + source[start..parser.pos].trim_end().len();
+        for comment in parser.comments.iter().rev().take_while(|comment| comment.start > start) {
+            if comment.end == end {
+                end = start + source[start..comment.start].trim_end().len();
+            }
+        }
+        definitions.push(Definition { name, is_class, expr, span: start..end });
+    }
+    Ok((definitions, parser.comments))
+}
+
+pub fn parse_grammar(source: &str) -> Result<Grammar<'_>, SyntaxError> {
+    let mut grammar = Grammar { classes: Vec::new(), rules: Vec::new() };
+    for definition in parse_definitions(source)? {
+        let definitions = if definition.is_class { &mut grammar.classes } else { &mut grammar.rules };
+        if definitions.iter().any(|(defined, _)| *defined == definition.name) {
+            let error = SyntaxErrorKind::DuplicateDefinition(definition.name.to_string());
+            return Err(SyntaxError { kind: error, offset: definition.span.start });
+        }
+        definitions.push((definition.name, definition.expr));
+    }
+    Ok(grammar)
+}
+
+// Items of a set in source order, with runs of chars joined
+fn push_set_char(items: &mut Vec<Expression>, c: char) {
+    match items.pop() {
+        Some(Expression::Char(previous)) => items.push(Expression::Set([previous, c].iter().collect())),
+        Some(Expression::Set(mut chars)) => {
+            chars.push(c);
+            items.push(Expression::Set(chars));
+        },
+        last => {
+            items.extend(last);
+            items.push(Expression::Char(c));
+        },
+    }
+}
+
+fn set_chars(items: &[Expression]) -> Option<String> {
+    match items {
+        [Expression::Char(c)] => Some(c.to_string()),
+        [Expression::Set(chars)] => Some(chars.clone()),
+        _ => None,
+    }
+}
+
+struct Parser<'a> {
+    source: &'a str,
+    pos: usize,
+    /// Name of the rule being parsed, for unnamed captures
+    rule: &'a str,
+    comments: Vec<Range<usize>>,
+}
+
+impl<'a> Parser<'a> {
+    fn rest(&self) -> &'a str {
+        &self.source[self.pos..]
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.rest().chars().next()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += c.len_utf8();
+        Some(c)
+    }
+
+    fn eat(&mut self, s: &str) -> bool {
+        if self.rest().starts_with(s) {
+            self.pos += s.len();
+            true
+        }
+        else {
+            false
+        }
+    }
+
+    fn error(&self, kind: SyntaxErrorKind) -> SyntaxError {
+        self.error_at(self.pos, kind)
+    }
+
+    fn error_at(&self, offset: usize, kind: SyntaxErrorKind) -> SyntaxError {
+        SyntaxError { kind, offset }
+    }
+
+    fn expect(&mut self, s: &'static str, what: &'static str) -> Result<(), SyntaxError> {
+        if self.eat(s) { Ok(()) } else { Err(self.error(SyntaxErrorKind::Expected(what))) }
+    }
+
+    fn skip_space(&mut self) {
+        loop {
+            let rest = self.rest();
+            let trimmed = rest.trim_start();
+            self.pos += rest.len() - trimmed.len();
+            if !trimmed.starts_with('#') {
+                break;
+            }
+            let start = self.pos;
+            self.pos += trimmed.find('\n').unwrap_or(trimmed.len());
+            // lookahead may skip the same comment again
+            if self.comments.last().is_none_or(|comment| comment.start < start) {
+                self.comments.push(start..self.pos);
+            }
+        }
+    }
+
+    fn identifier(&mut self) -> Option<&'a str> {
+        let rest = self.rest();
+        if !rest.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') {
+            return None;
+        }
+        let len = rest.find(|c: char| !c.is_ascii_alphanumeric() && c != '_').unwrap_or(rest.len());
+        self.pos += len;
+        Some(&rest[..len])
+    }
+
+    // A definition starts here, so the current sequence ends
+    fn at_definition(&mut self) -> bool {
+        let start = self.pos;
+        self.eat("@");
+        let found = self.identifier().is_some() && {
+            self.skip_space();
+            self.rest().starts_with("<-")
+        };
+        self.pos = start;
+        found
+    }
+
+    fn choice(&mut self) -> Result<Expression, SyntaxError> {
+        let mut alternatives = vec![self.sequence()?];
+        while self.eat("/") {
+            self.skip_space();
+            alternatives.push(self.sequence()?);
+        }
+        Ok(if alternatives.len() == 1 { alternatives.pop().unwrap() } else { Expression::Choice(alternatives) })
+    }
+
+    fn sequence(&mut self) -> Result<Expression, SyntaxError> {
+        let mut items = Vec::new();
+        while self.rest().starts_with(|c| "({'\"[.\\@&!<".contains(c) || c == '_' || char::is_ascii_alphabetic(&c))
+            && !self.at_definition() {
+            items.push(self.prefix()?);
+        }
+        match items.len() {
+            0 => Err(self.error(SyntaxErrorKind::Expected("expression"))),
+            1 => Ok(items.pop().unwrap()),
+            _ => Ok(Expression::Sequence(items)),
+        }
+    }
+
+    fn prefix(&mut self) -> Result<Expression, SyntaxError> {
+        let make: fn(Box<Expression>) -> Expression = if self.eat("<&") {
+            Expression::Behind
+        }
+        else if self.eat("<!") {
+            Expression::NotBehind
+        }
+        else if self.eat("&") {
+            Expression::And
+        }
+        else if self.eat("!") {
+            self.skip_space();
+            return match self.suffix()? {
+                Expression::Any => Ok(Expression::EndOfInput),
+                e => Ok(Expression::Not(Box::new(e))),
+            };
+        }
+        else {
+            return self.suffix();
+        };
+        self.skip_space();
+        Ok(make(Box::new(self.suffix()?)))
+    }
+
+    fn suffix(&mut self) -> Result<Expression, SyntaxError> {
+        let mut expr = self.primary()?;
+        self.skip_space();
+        loop {
+            let count = if self.eat("*") {
+                0
+            }
+            else if self.eat("+") {
+                1
+            }
+            else if self.eat("?") {
+                -1
+            }
+            else if self.eat("^") {
+                let start = self.pos;
+                self.eat("-");
+                let len = self.rest().find(|c: char| !c.is_ascii_digit()).unwrap_or(self.rest().len());
+                self.pos += len;
+                self.source[start..self.pos].parse().map_err(|_| self.error_at(start, SyntaxErrorKind::InvalidQuantifier))?
+            }
+            else {
+                return Ok(expr);
+            };
+            expr = Expression::Quantifier(Box::new(expr), count);
+            self.skip_space();
+        }
+    }
+
+    fn primary(&mut self) -> Result<Expression, SyntaxError> {
+        let start = self.pos;
+        let expr = match self.bump() {
+            Some('(') => {
+                self.skip_space();
+                let e = self.choice()?;
+                self.expect(")", "')'")?;
+                e
+            },
+            Some('{') => {
+                self.skip_space();
+                let before_name = self.pos;
+                let name = match self.identifier() {
+                    Some(name) if self.eat(":") => {
+                        self.skip_space();
+                        name
+                    },
+                    _ => {
+                        self.pos = before_name;
+                        self.rule
+                    },
+                };
+                let e = self.choice()?;
+                self.expect("}", "'}'")?;
+                Expression::Capture(Box::new(e), name.to_string())
+            },
+            Some(quote @ '\'') | Some(quote @ '"') => self.literal(quote, start)?,
+            Some('[') => self.set(start)?,
+            Some('.') => Expression::Any,
+            Some('\\') => self.class_escape(start)?.ok_or_else(|| self.error_at(start, SyntaxErrorKind::InvalidEscape))?,
+            Some('@') => {
+                let name = self.identifier().ok_or_else(|| self.error(SyntaxErrorKind::Expected("class name")))?;
+                Expression::NamedClass(name.to_string())
+            },
+            _ => {
+                self.pos = start;
+                let name = self.identifier().ok_or_else(|| self.error(SyntaxErrorKind::Expected("expression")))?;
+                Expression::NonTerminal(name.to_string())
+            },
+        };
+        Ok(expr)
+    }
+
+    fn no_case(&mut self) -> bool {
+        let suffix = self.rest().strip_prefix('i')
+            .is_some_and(|rest| !rest.starts_with(|c: char| c.is_ascii_alphanumeric() || c == '_'));
+        suffix && self.eat("i")
+    }
+
+    fn literal(&mut self, quote: char, start: usize) -> Result<Expression, SyntaxError> {
+        let mut s = String::new();
+        loop {
+            match self.bump() {
+                Some(c) if c == quote => break,
+                Some('\\') => s.push(self.escape(self.pos - 1)?),
+                Some(c) => s.push(c),
+                None => return Err(self.error_at(start, SyntaxErrorKind::UnterminatedLiteral)),
+            }
+        }
+        let no_case = self.no_case();
+        let mut chars = s.chars();
+        Ok(match (chars.next(), chars.next(), no_case) {
+            (Some(c), None, false) => Expression::Char(c),
+            (Some(c), None, true) => Expression::CharNoCase(c),
+            (_, _, false) => Expression::Literal(s),
+            (_, _, true) => Expression::LiteralNoCase(s),
+        })
+    }
+
+    // Escapes valid in literals and sets, after the backslash
+    fn escape(&mut self, start: usize) -> Result<char, SyntaxError> {
+        let c = match self.bump() {
+            Some('n') => '\n',
+            Some('r') => '\r',
+            Some('t') => '\t',
+            Some('0') => '\0',
+            Some('u') if self.eat("{") => {
+                let len = self.rest().find('}').ok_or_else(|| self.error_at(start, SyntaxErrorKind::InvalidEscape))?;
+                let hex = &self.rest()[..len];
+                self.pos += len + 1;
+                u32::from_str_radix(hex, 16).ok().and_then(char::from_u32)
+                    .ok_or_else(|| self.error_at(start, SyntaxErrorKind::InvalidEscape))?
+            },
+            Some(c) if "\\'\"[]-^".contains(c) => c,
+            _ => return Err(self.error_at(start, SyntaxErrorKind::InvalidEscape)),
+        };
+        Ok(c)
+    }
+
+    // Class escape after the backslash, `\d` or `\p{Name}`
+    fn class_escape(&mut self, start: usize) -> Result<Option<Expression>, SyntaxError> {
+        if self.eat("p{") {
+            let len = self.rest().find('}').ok_or_else(|| self.error_at(start, SyntaxErrorKind::Expected("'}'")))?;
+            let name = &self.rest()[..len];
+            self.pos += len + 1;
+            return match UnicodeClass::from_name(name) {
+                Some(class) => Ok(Some(Expression::UnicodeClass(class))),
+                None => Err(self.error_at(start, SyntaxErrorKind::UnknownUnicodeClass(name.to_string()))),
+            };
+        }
+        let class = self.peek()
+            .and_then(|c| u8::try_from(c).ok())
+            .and_then(|b| CharacterClass::try_from(b).ok());
+        if class.is_some() {
+            self.pos += 1;
+        }
+        Ok(class.map(Expression::Class))
+    }
+
+    fn set(&mut self, start: usize) -> Result<Expression, SyntaxError> {
+        let negated = self.eat("^");
+        let mut items = Vec::new();
+        loop {
+            let item_start = self.pos;
+            let c = match self.bump() {
+                Some(']') => break,
+                Some('\\') if !self.rest().starts_with("u{") => match self.class_escape(item_start)? {
+                    Some(class) => {
+                        items.push(class);
+                        continue;
+                    },
+                    None => self.escape(item_start)?,
+                },
+                Some('\\') => self.escape(item_start)?,
+                Some(c) => c,
+                None => return Err(self.error_at(start, SyntaxErrorKind::UnterminatedSet)),
+            };
+            if self.rest().starts_with('-') && !self.rest().starts_with("-]") {
+                self.pos += 1;
+                let max = match self.bump() {
+                    Some('\\') => self.escape(self.pos - 1)?,
+                    Some(c) => c,
+                    None => return Err(self.error_at(start, SyntaxErrorKind::UnterminatedSet)),
+                };
+                if max < c {
+                    return Err(self.error_at(item_start, SyntaxErrorKind::InvalidRange));
+                }
+                items.push(Expression::Range(c, max));
+            }
+            else {
+                push_set_char(&mut items, c);
+            }
+        }
+        if self.no_case() {
+            return match set_chars(&items) {
+                Some(chars) if !negated => Ok(Expression::SetNoCase(chars)),
+                _ => Err(self.error_at(start, SyntaxErrorKind::InvalidNoCaseSet)),
+            };
+        }
+        if negated {
+            if let Some(chars) = set_chars(&items) {
+                return Ok(Expression::InverseSet(chars));
+            }
+        }
+        let expr = match items.len() {
+            0 => return Err(self.error_at(start, SyntaxErrorKind::EmptySet)),
+            1 => items.pop().unwrap(),
+            _ => Expression::Choice(items),
+        };
+        Ok(if negated { !expr + Expression::Any } else { expr })
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Compiler;
+    use pegatexto_vm::matcher::{try_match, try_match_then, MatchError};
+
+    fn matcher(source: &str) -> impl Fn(&str) -> Result<usize, MatchError> {
+        let grammar = parse_grammar(source).unwrap();
+        let mut compiler = Compiler::new();
+        compiler.compile_parsed(&grammar).unwrap();
+        let bytecode = compiler.emit_owned();
+        move |text| try_match(&bytecode.as_bytecode(), text)
+    }
+
+    fn error(source: &str) -> (SyntaxErrorKind, usize) {
+        let error = parse_grammar(source).err().unwrap();
+        (error.kind, error.offset)
+    }
+
+    #[test]
+    fn test_parse_grammar() {
+        let list = matcher("
+            # comma separated identifiers
+            List <- Ident (',' Sp Ident)* !.
+            Ident <- @IdentStart \\w*   Sp
+            Sp <- [ \\t]*
+            @IdentStart <- \\a / [_$]
+        ");
+        assert_eq!(list("a, b1,$c"), Ok(8));
+        assert_eq!(list("a, 1"), Err(MatchError::NoMatch(3)));
+        assert_eq!(list("a,b "), Ok(4));
+
+        let terminals = matcher(r#"S <- 'ab'i "\u{3b1}\n" [^a-z\d] [x-z_] \p{Greek}+ ("k"i / [q]i) .?"#);
+        assert_eq!(terminals("aBα\n-_αβk"), Ok(12));
+        assert_eq!(terminals("ABα\n-yαq!"), Ok(11));
+        assert_eq!(terminals("abα\n-a"), Err(MatchError::NoMatch(6)));
+
+        let quantifiers = matcher("S <- 'a'^2 'b'^-2 <&'b' !'b' 'd'? !.");
+        assert_eq!(quantifiers("aaabbbd"), Err(MatchError::NoMatch(3)));
+        assert_eq!(quantifiers("aaabbd"), Ok(6));
+        assert_eq!(quantifiers("aab"), Ok(3));
+        assert_eq!(quantifiers("ab"), Err(MatchError::NoMatch(1)));
+    }
+
+    #[test]
+    fn test_parse_captures() {
+        let grammar = parse_grammar("Sum <- {Num ('+' Num)*}  Num <- {digits: [0-9]+}").unwrap();
+        let mut compiler = Compiler::new();
+        compiler.compile_parsed(&grammar).unwrap();
+        let names = compiler.capture_names().to_vec();
+        let bytecode = compiler.emit();
+        let result = try_match_then(&bytecode, "1+23", |text, id, args: &[String]| {
+            format!("{}({}{})", names[id as usize], text, args.iter().map(|a| format!(" {}", a)).collect::<String>())
+        });
+        assert_eq!(result, Ok((4, Some("Sum(1+23 digits(1) digits(23))".to_string()))));
+    }
+
+    #[test]
+    fn test_parsed_expressions() {
+        use Expression::*;
+        let grammar = parse_grammar("S <- {'a' [^bc] / !.}  T <- [x-z\\d]+ S\n@C <- 'q'i").unwrap();
+        let expected = Grammar {
+            classes: vec![("C", CharNoCase('q'))],
+            rules: vec![
+                ("S", ((Char('a') + InverseSet("bc".to_string())) / EndOfInput) >> "S"),
+                ("T", ((Range('x', 'z') / Class(CharacterClass::Digit)) ^ 1) + NonTerminal("S".to_string())),
+            ],
+        };
+        assert_eq!(grammar, expected);
+
+        // drop the captures of every rule
+        let uncaptured = grammar.map_rules(|_, expr| expr.map(&mut |e| match e {
+            Capture(e, _) => *e,
+            e => e,
+        }));
+        assert_eq!(uncaptured.rules[0], ("S", (Char('a') + InverseSet("bc".to_string())) / EndOfInput));
+        assert_eq!(uncaptured.rules[1], expected.rules[1]);
+        assert_eq!(uncaptured.rules[1].1.referenced_nonterminals().into_iter().collect::<Vec<_>>(), vec!["S"]);
+    }
+
+    #[test]
+    fn test_parse_definitions() {
+        let source = "S <- A # first\n# second\n@A <- 'a'\nS <- 'b' # 'c'\n  # last\n";
+        let definitions = parse_definitions(source).unwrap();
+        let spans: Vec<_> = definitions.iter().map(|d| (d.name, d.is_class, &source[d.span.clone()])).collect();
+        assert_eq!(spans, vec![("S", false, "S <- A"), ("A", true, "@A <- 'a'"), ("S", false, "S <- 'b'")]);
+        let (_, comments) = parse_with_comments(source).unwrap();
+        let comments: Vec<_> = comments.into_iter().map(|span| &source[span]).collect();
+        assert_eq!(comments, vec!["# first", "# second", "# 'c'", "# last"]);
+        assert!(parse_definitions("S <- ").is_err());
+    }
+
+    #[test]
+    fn test_syntax_errors() {
+        use SyntaxErrorKind::*;
+        assert_eq!(error("S <- 'a"), (UnterminatedLiteral, 5));
+        assert_eq!(error("S <- [ab"), (UnterminatedSet, 5));
+        assert_eq!(error("S <- []"), (EmptySet, 5));
+        assert_eq!(error("S <- [z-a]"), (InvalidRange, 6));
+        assert_eq!(error("S <- 'a\\q'"), (InvalidEscape, 7));
+        assert_eq!(error("S <- \\q"), (InvalidEscape, 5));
+        assert_eq!(error("S <- \\p{Klingon}"), (UnknownUnicodeClass("Klingon".to_string()), 5));
+        assert_eq!(error("S <- [a-c]i"), (InvalidNoCaseSet, 5));
+        assert_eq!(error("S <- 'a'é"), (Expected("rule definition"), 8));
+        assert_eq!(error("S <- [a]α"), (Expected("rule definition"), 8));
+        assert_eq!(error("S <- 'a'^99999999999"), (InvalidQuantifier, 9));
+        assert_eq!(error("S <- ('a'"), (Expected("')'"), 9));
+        assert_eq!(error("S <- 'a' /"), (Expected("expression"), 10));
+        assert_eq!(error("S 'a'"), (Expected("'<-'"), 2));
+        assert_eq!(error("S <- 'a' )"), (Expected("rule definition"), 9));
+        assert_eq!(error("S <- 'a' S <- 'b'"), (DuplicateDefinition("S".to_string()), 9));
+        let error = parse_grammar("S <- 'a'\n  'b' ]").err().unwrap();
+        assert_eq!(error.position(&LineIndex::new("S <- 'a'\n  'b' ]")).to_string(), "2:7");
+    }
+}
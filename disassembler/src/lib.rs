@@ -1,4 +1,5 @@
-use pegatexto_vm::bytecode::instruction::InstructionIterator;
+use pegatexto_vm::bytecode::instruction::{Instruction, InstructionIterator};
+use pegatexto_vm::bytecode::symbols::SymbolTable;
 
 fn usize_digits(x: usize) -> usize {
     let mut digits = 0;
@@ -11,11 +12,25 @@ fn usize_digits(x: usize) -> usize {
 }
 
 pub fn dump_bytecode(bytecode: &[u8]) {
+    dump_bytecode_with_symbols(bytecode, &SymbolTable::new());
+}
+
+pub fn dump_bytecode_with_symbols(bytecode: &[u8], symbols: &SymbolTable) {
     let mut iter = InstructionIterator::new(bytecode);
     let address_digits = usize_digits(iter.bytes_len());
-    let mut current = 0;
+    let mut current = iter.current();
     while let Some(instruction) = iter.next() {
-        println!("{:width$} | {}", current, instruction, width = address_digits);
-        current = iter.current().into();
+        if let Some(name) = symbols.name_at(current) {
+            println!("{:width$} | .{}", "", name, width = address_digits);
+        }
+        let call_target = match instruction {
+            Instruction::Call(address) => symbols.name_at(address),
+            _ => None,
+        };
+        match call_target {
+            Some(name) => println!("{:width$} | {} ; {}", current, instruction, name, width = address_digits),
+            None => println!("{:width$} | {}", current, instruction, width = address_digits),
+        }
+        current = iter.current();
     }
 }
@@ -1,17 +1,25 @@
 pub mod address;
 pub mod builder;
+pub mod charset;
+pub mod class_set;
+pub mod file;
 pub mod instruction;
 pub mod opcode;
 pub mod parser;
+pub mod symbols;
+pub mod verifier;
 
+use address::Address;
 use builder::Builder;
 use instruction::Instruction;
+use symbols::SymbolTable;
 
+use std::io::{self, Read, Write};
 use std::ops::Deref;
 
 pub struct Bytecode<'a>(&'a [u8]);
 
-impl<'a> Bytecode<'_> {
+impl<'a> Bytecode<'a> {
     pub fn from_bytes(bytes: &'a [u8]) -> Result<Bytecode<'a>, parser::ParseError> {
         match Bytecode::check_error(bytes) {
             Some(err) => Err(err),
@@ -19,6 +27,11 @@ impl<'a> Bytecode<'_> {
         }
     }
 
+    pub fn from_bytes_verified(bytes: &'a [u8]) -> Result<Bytecode<'a>, verifier::VerifyError> {
+        verifier::verify(bytes)?;
+        Ok(Bytecode(bytes))
+    }
+
     pub fn from_bytes_unchecked(bytes: &'a [u8]) -> Bytecode<'a> {
         Bytecode(bytes)
     }
@@ -33,23 +46,36 @@ impl<'a> Bytecode<'_> {
     }
 }
 
-impl<'a> Deref for Bytecode<'_> {
+impl Deref for Bytecode<'_> {
     type Target = [u8];
 
     fn deref(&self) -> &Self::Target {
-        &self.0
+        self.0
     }
 }
 
 
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Metadata {
+    pub symbols: SymbolTable,
+    pub capture_names: Vec<String>,
+    /// Grammar source the bytecode was compiled from
+    pub debug_info: Option<String>,
+    /// Addresses of the `Jump`s that replaced a `Call` before `Return`
+    pub tail_calls: Vec<Address>,
+}
+
 #[derive(Clone)]
-pub struct OwnedBytecode(Vec<u8>);
+pub struct OwnedBytecode {
+    bytes: Vec<u8>,
+    metadata: Metadata,
+}
 
 impl OwnedBytecode {
     pub fn from_bytes(bytes: Vec<u8>) -> Result<OwnedBytecode, parser::ParseError> {
         match Bytecode::check_error(&bytes) {
             Some(err) => Err(err),
-            None => Ok(OwnedBytecode(bytes)),
+            None => Ok(OwnedBytecode::from_bytes_unchecked(bytes)),
         }
     }
 
@@ -58,7 +84,7 @@ impl OwnedBytecode {
     }
 
     pub fn from_bytes_unchecked(bytes: Vec<u8>) -> OwnedBytecode {
-        OwnedBytecode(bytes)
+        OwnedBytecode { bytes, metadata: Metadata::default() }
     }
 
     pub fn from_instructions(instructions: &[Instruction]) -> OwnedBytecode {
@@ -66,7 +92,31 @@ impl OwnedBytecode {
     }
 
     pub fn as_bytecode(&self) -> Bytecode<'_> {
-        Bytecode::from_bytes_unchecked(&self.0)
+        Bytecode::from_bytes_unchecked(&self.bytes)
+    }
+
+    pub fn verify(&self) -> Result<(), verifier::VerifyError> {
+        verifier::verify(&self.bytes)
+    }
+
+    pub fn with_metadata(self, metadata: Metadata) -> OwnedBytecode {
+        OwnedBytecode { metadata, ..self }
+    }
+
+    pub fn metadata(&self) -> &Metadata {
+        &self.metadata
+    }
+
+    pub fn symbols(&self) -> &SymbolTable {
+        &self.metadata.symbols
+    }
+
+    pub fn write_to<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        file::write_bytecode(out, self)
+    }
+
+    pub fn read_from<R: Read>(input: &mut R) -> Result<OwnedBytecode, parser::ParseError> {
+        file::read_bytecode(input)
     }
 }
 
@@ -3,11 +3,11 @@ use std::convert::From;
 use std::fmt;
 use std::ops::{Add, Sub, AddAssign, SubAssign, Deref};
 
-#[derive(Clone, Copy, Debug, Default, Eq, Ord)]
-pub struct Address(u16);
+#[derive(Clone, Copy, Debug, Default, Eq)]
+pub struct Address(u32);
 
 impl Address {
-    pub fn new(address: u16) -> Address {
+    pub fn new(address: u32) -> Address {
         Address(address)
     }
 
@@ -16,7 +16,7 @@ impl Address {
     }
 
     pub fn max_value() -> Address {
-        Address::new(u16::max_value())
+        Address::new(u32::MAX)
     }
 }
 
@@ -26,48 +26,54 @@ impl PartialEq for Address {
     }
 }
 
+impl Ord for Address {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.cmp(&other.0)
+    }
+}
+
 impl PartialOrd for Address {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
         Some(self.cmp(other))
     }
 }
 
-impl Add<u16> for Address {
+impl Add<u32> for Address {
     type Output = Self;
 
-    fn add(self, other: u16) -> Self::Output {
-        Address(self.0 + other)
+    fn add(self, other: u32) -> Self::Output {
+        Address(self.0.saturating_add(other))
     }
 }
 
-impl Sub<u16> for Address {
+impl Sub<u32> for Address {
     type Output = Self;
 
-    fn sub(self, other: u16) -> Self::Output {
-        Address(self.0 - other)
+    fn sub(self, other: u32) -> Self::Output {
+        Address(self.0.saturating_sub(other))
     }
 }
 
-impl AddAssign<u16> for Address {
-    fn add_assign(&mut self, other: u16) {
-        self.0 += other
+impl AddAssign<u32> for Address {
+    fn add_assign(&mut self, other: u32) {
+        self.0 = self.0.saturating_add(other)
     }
 }
 
-impl SubAssign<u16> for Address {
-    fn sub_assign(&mut self, other: u16) {
-        self.0 -= other
+impl SubAssign<u32> for Address {
+    fn sub_assign(&mut self, other: u32) {
+        self.0 = self.0.saturating_sub(other)
     }
 }
 
-impl From<[u8; 2]> for Address {
-    fn from(bytes: [u8; 2]) -> Address {
-        Address(u16::from_le_bytes(bytes))
+impl From<[u8; 4]> for Address {
+    fn from(bytes: [u8; 4]) -> Address {
+        Address(u32::from_le_bytes(bytes))
     }
 }
 
-impl From<Address> for [u8; 2] {
-    fn from(address: Address) -> [u8; 2] {
+impl From<Address> for [u8; 4] {
+    fn from(address: Address) -> [u8; 4] {
         address.0.to_le_bytes()
     }
 }
@@ -79,7 +85,7 @@ impl From<Address> for usize {
 }
 
 impl Deref for Address {
-    type Target = u16;
+    type Target = u32;
     fn deref(&self) -> &Self::Target {
         &self.0
     }
@@ -87,7 +93,7 @@ impl Deref for Address {
 
 impl fmt::Display for Address {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}", self.0)
+        fmt::Display::fmt(&self.0, f)
     }
 }
 
@@ -2,9 +2,25 @@ use super::{Bytecode, OwnedBytecode};
 use super::address::Address;
 use super::instruction::Instruction;
 
+use std::convert::TryFrom;
+use std::fmt;
 use std::mem;
 
-#[derive(Clone)]
+#[derive(Debug, PartialEq)]
+pub enum BuildError {
+    /// The program grew past the largest encodable address
+    AddressOverflow(usize),
+}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BuildError::AddressOverflow(len) => write!(f, "bytecode size {} exceeds the addressable range", len),
+        }
+    }
+}
+
+#[derive(Clone, Default)]
 pub struct Builder(Vec<u8>);
 
 impl Builder {
@@ -20,7 +36,7 @@ impl Builder {
         builder
     }
 
-    pub fn build(&self) -> Bytecode {
+    pub fn build(&self) -> Bytecode<'_> {
         Bytecode::from_bytes_unchecked(&self.0)
     }
 
@@ -28,9 +44,12 @@ impl Builder {
         OwnedBytecode::from_bytes_unchecked(self.0)
     }
 
-    pub fn current_address(&self) -> Address {
+    pub fn current_address(&self) -> Result<Address, BuildError> {
         let len = self.0.len();
-        Address::new(len as u16)
+        match u32::try_from(len) {
+            Ok(address) => Ok(Address::new(address)),
+            Err(_) => Err(BuildError::AddressOverflow(len)),
+        }
     }
 
     pub fn push_instruction(&mut self, instruction: &Instruction) -> &mut Self {
@@ -38,31 +57,48 @@ impl Builder {
         self.push_byte(opcode as u8);
         use Instruction::*;
         match instruction {
-            FailIfLessThan(n) | Capture(n) => self.push_byte(*n),
+            FailIfLessThan(n) | Capture(n) | Behind(n) => self.push_byte(*n),
             Jump(addr) | JumpIfFail(addr) | JumpIfSuccess(addr) | Call(addr) => {
                 self.push_address(*addr);
             },
             Byte(b) => self.push_byte(*b),
             Char(c) => self.push_char(*c),
             Class(c) => self.push_byte(*c as u8),
-            Literal(s) | Set(s) | NotSet(s) => {
+            Literal(s) | Set(s) | NotSet(s) | LiteralNoCase(s) | SetNoCase(s) => {
                 self.push_bytes(s.as_bytes());
                 self.push_byte(0);
             },
             Range(b_min, b_max) => {
                 self.push_byte(*b_min);
                 self.push_byte(*b_max);
-            }
+            },
+            ClassSet(class_set) => {
+                self.push_bytes(class_set.as_bytes());
+            },
+            UnicodeClass(class) => {
+                self.push_bytes(&class.to_bytes());
+            },
+            CharRange(min, max) => {
+                self.push_bytes(&(*min as u32).to_le_bytes());
+                self.push_bytes(&(*max as u32).to_le_bytes());
+            },
+            Charset(charset) | NotCharset(charset) => {
+                self.push_bytes(charset.as_bytes());
+            },
+            TestSet(addr, s) => {
+                self.push_address(*addr);
+                self.push_bytes(s.as_bytes());
+                self.push_byte(0);
+            },
             _ => ()
         };
         self
     }
 
     pub fn patch_jump(&mut self, jump_op: Address, address: Address) -> &mut Self {
-        let bytes: [u8; 2] = address.into();
+        let bytes: [u8; 4] = address.into();
         let jump_op: usize = jump_op.into();
-        self.0[jump_op + 1] = bytes[0];
-        self.0[jump_op + 2] = bytes[1];
+        self.0[jump_op + 1 .. jump_op + 5].copy_from_slice(&bytes);
         self
     }
 
@@ -85,8 +121,36 @@ impl Builder {
     }
 
     fn push_address(&mut self, address: Address) {
-        let bytes: [u8; 2] = address.into();
+        let bytes: [u8; 4] = address.into();
         self.push_bytes(&bytes);
     }
 }
 
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bytecode::instruction::InstructionIterator;
+
+    #[test]
+    fn test_far_jump() {
+        let mut builder = Builder::new();
+        let jump = builder.current_address().unwrap();
+        builder.push_instruction(&Instruction::Jump(Address::zero()));
+        for _ in 0..70000 {
+            builder.push_instruction(&Instruction::Any);
+        }
+        let target = builder.current_address().unwrap();
+        assert_eq!(target, Address::new(70005));
+        builder.push_instruction(&Instruction::Succeed);
+        builder.patch_jump(jump, target);
+
+        let bytecode = builder.build();
+        let mut iter = InstructionIterator::new(&bytecode);
+        assert_eq!(iter.next(), Some(Instruction::Jump(target)));
+        iter.jump(target);
+        assert_eq!(iter.next(), Some(Instruction::Succeed));
+        assert_eq!(iter.next(), None);
+    }
+}
@@ -0,0 +1,145 @@
+use std::convert::TryFrom;
+use std::fmt;
+
+/// Set of the chars from U+0000 to U+00FF, one bit per char
+#[derive(Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct Charset([u8; 32]);
+
+impl Charset {
+    pub fn new() -> Charset {
+        Charset::default()
+    }
+
+    pub fn from_bytes(bytes: [u8; 32]) -> Charset {
+        Charset(bytes)
+    }
+
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+
+    pub fn insert(&mut self, b: u8) {
+        self.0[(b >> 3) as usize] |= 1 << (b & 7);
+    }
+
+    pub fn insert_range(&mut self, min: u8, max: u8) {
+        for b in min..=max {
+            self.insert(b);
+        }
+    }
+
+    /// Inserts `c`, returning false if it is out of the charset's range
+    pub fn insert_char(&mut self, c: char) -> bool {
+        match u8::try_from(c) {
+            Ok(b) => {
+                self.insert(b);
+                true
+            },
+            Err(_) => false,
+        }
+    }
+
+    pub fn contains(&self, b: u8) -> bool {
+        self.0[(b >> 3) as usize] & (1 << (b & 7)) != 0
+    }
+
+    pub fn contains_char(&self, c: char) -> bool {
+        u8::try_from(c).is_ok_and(|b| self.contains(b))
+    }
+
+    pub fn union(&self, other: &Charset) -> Charset {
+        let mut result = *self;
+        result.0.iter_mut().zip(other.0.iter()).for_each(|(a, b)| *a |= b);
+        result
+    }
+
+    pub fn intersection(&self, other: &Charset) -> Charset {
+        let mut result = *self;
+        result.0.iter_mut().zip(other.0.iter()).for_each(|(a, b)| *a &= b);
+        result
+    }
+
+    pub fn difference(&self, other: &Charset) -> Charset {
+        let mut result = *self;
+        result.0.iter_mut().zip(other.0.iter()).for_each(|(a, b)| *a &= !b);
+        result
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.iter().all(|b| *b == 0)
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.iter().map(|b| b.count_ones() as usize).sum()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = u8> + '_ {
+        (0..=255u8).filter(move |b| self.contains(*b))
+    }
+}
+
+impl fmt::Display for Charset {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fn write_char(f: &mut fmt::Formatter<'_>, b: u8) -> fmt::Result {
+            match b {
+                b']' | b'\\' | b'-' | b'^' => write!(f, "\\{}", b as char),
+                b' ' ..= b'~' => write!(f, "{}", b as char),
+                _ => write!(f, "\\x{:02x}", b),
+            }
+        }
+        write!(f, "[")?;
+        let mut bytes = self.iter().peekable();
+        while let Some(min) = bytes.next() {
+            let mut max = min;
+            while bytes.peek() == Some(&max.wrapping_add(1)) && max != 255 {
+                max = bytes.next().unwrap();
+            }
+            write_char(f, min)?;
+            if max > min {
+                if max > min + 1 {
+                    write!(f, "-")?;
+                }
+                write_char(f, max)?;
+            }
+        }
+        write!(f, "]")
+    }
+}
+
+impl fmt::Debug for Charset {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Charset({})", self)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_charset() {
+        let mut digits = Charset::new();
+        digits.insert_range(b'0', b'9');
+        assert_eq!(digits.len(), 10);
+        assert!(digits.contains(b'0') && digits.contains(b'9'));
+        assert!(!digits.contains(b'a'));
+        assert!(!digits.contains_char('٣'));
+
+        let mut hex = digits;
+        hex.insert_range(b'a', b'f');
+        hex.insert_range(b'A', b'F');
+        assert!(hex.insert_char('ÿ'));
+        assert!(!hex.insert_char('α'));
+        assert_eq!(hex.to_string(), "[0-9A-Fa-f\\xff]");
+        assert_eq!(hex.intersection(&digits), digits);
+        assert_eq!(hex.difference(&digits).union(&digits), hex);
+        assert!(digits.difference(&hex).is_empty());
+
+        let mut pair = Charset::new();
+        pair.insert(b'-');
+        pair.insert(b'.');
+        assert_eq!(pair.to_string(), "[\\-.]");
+        assert_eq!(Charset::from_bytes(*pair.as_bytes()), pair);
+    }
+}
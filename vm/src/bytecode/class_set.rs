@@ -0,0 +1,196 @@
+use super::charset::Charset;
+use super::parser::ParseError;
+use crate::grammar::character_class::CharacterClass;
+use crate::grammar::unicode::UnicodeClass;
+use crate::slice_to_array;
+
+use std::convert::{TryFrom, TryInto};
+use std::fmt;
+
+// Layout: charset for U+0000..U+00FF (32 bytes), item count (u8), items.
+// Items only apply to chars past U+00FF, each is a tag byte followed by:
+//   range: min and max codepoints, u32 little endian each
+//   class: CharacterClass byte
+//   unicode class: UnicodeClass bytes
+const CHARSET_SIZE: usize = 32;
+const TAG_RANGE: u8 = 0;
+const TAG_CLASS: u8 = 1;
+const TAG_UNICODE_CLASS: u8 = 2;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ClassItem {
+    Range(char, char),
+    Class(CharacterClass),
+    UnicodeClass(UnicodeClass),
+}
+
+impl ClassItem {
+    pub fn is_member(&self, c: char) -> bool {
+        match self {
+            ClassItem::Range(min, max) => c >= *min && c <= *max,
+            ClassItem::Class(class) => class.is_member(c),
+            ClassItem::UnicodeClass(class) => class.is_member(c),
+        }
+    }
+
+    fn parse(bytes: &[u8]) -> Result<(ClassItem, usize), ParseError> {
+        let tag = *bytes.first().ok_or(ParseError::MissingArgument)?;
+        let bytes = &bytes[1..];
+        match tag {
+            TAG_RANGE => {
+                let min = slice_to_array!(bytes, u8, 4).ok_or(ParseError::MissingArgument)?;
+                let max = slice_to_array!(&bytes[4..], u8, 4).ok_or(ParseError::MissingArgument)?;
+                let min = char::from_u32(u32::from_le_bytes(min)).ok_or(ParseError::InvalidRange)?;
+                let max = char::from_u32(u32::from_le_bytes(max)).ok_or(ParseError::InvalidRange)?;
+                if min > max {
+                    return Err(ParseError::InvalidRange);
+                }
+                Ok((ClassItem::Range(min, max), 9))
+            },
+            TAG_CLASS => {
+                let class = *bytes.first().ok_or(ParseError::MissingArgument)?;
+                Ok((ClassItem::Class(CharacterClass::try_from(class)?), 2))
+            },
+            TAG_UNICODE_CLASS => {
+                let class = slice_to_array!(bytes, u8, 5).ok_or(ParseError::MissingArgument)?;
+                let class = UnicodeClass::from_bytes(class).ok_or(ParseError::InvalidCharacterClass)?;
+                Ok((ClassItem::UnicodeClass(class), 6))
+            },
+            _ => Err(ParseError::InvalidCharacterClass),
+        }
+    }
+
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        match self {
+            ClassItem::Range(min, max) => {
+                bytes.push(TAG_RANGE);
+                bytes.extend_from_slice(&(*min as u32).to_le_bytes());
+                bytes.extend_from_slice(&(*max as u32).to_le_bytes());
+            },
+            ClassItem::Class(class) => {
+                bytes.push(TAG_CLASS);
+                bytes.push(*class as u8);
+            },
+            ClassItem::UnicodeClass(class) => {
+                bytes.push(TAG_UNICODE_CLASS);
+                bytes.extend_from_slice(&class.to_bytes());
+            },
+        }
+    }
+}
+
+impl fmt::Display for ClassItem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClassItem::Range(min, max) => write!(f, "[{}-{}]", min.escape_debug(), max.escape_debug()),
+            ClassItem::Class(class) => write!(f, "\\{}", *class as u8 as char),
+            ClassItem::UnicodeClass(class) => write!(f, "{}", class),
+        }
+    }
+}
+
+/// Union of a charset, for chars up to U+00FF, and class items, for the
+/// chars past it, borrowing its encoded bytes.
+#[derive(Clone, Copy, PartialEq)]
+pub struct ClassSet<'a>(&'a [u8]);
+
+impl<'a> ClassSet<'a> {
+    /// Encodes a class set, items with at most 255 entries
+    pub fn encode(charset: &Charset, items: &[ClassItem]) -> Vec<u8> {
+        let mut bytes = charset.as_bytes().to_vec();
+        bytes.push(items.len() as u8);
+        for item in items.iter() {
+            item.encode(&mut bytes);
+        }
+        bytes
+    }
+
+    /// Parses a class set at the start of `bytes`, returning it and its size
+    pub fn parse(bytes: &'a [u8]) -> Result<(ClassSet<'a>, usize), ParseError> {
+        let count = *bytes.get(CHARSET_SIZE).ok_or(ParseError::MissingArgument)?;
+        let mut size = CHARSET_SIZE + 1;
+        for _ in 0..count {
+            let (_, item_size) = ClassItem::parse(&bytes[size..])?;
+            size += item_size;
+        }
+        Ok((ClassSet(&bytes[..size]), size))
+    }
+
+    pub fn as_bytes(&self) -> &'a [u8] {
+        self.0
+    }
+
+    pub fn charset(&self) -> Charset {
+        Charset::from_bytes(self.0[..CHARSET_SIZE].try_into().unwrap())
+    }
+
+    pub fn items(&self) -> impl Iterator<Item = ClassItem> + 'a {
+        let mut bytes = &self.0[CHARSET_SIZE + 1..];
+        std::iter::from_fn(move || {
+            // already validated by `parse`
+            let (item, size) = ClassItem::parse(bytes).ok()?;
+            bytes = &bytes[size..];
+            Some(item)
+        })
+    }
+
+    pub fn contains(&self, c: char) -> bool {
+        match u8::try_from(c) {
+            Ok(b) => self.0[(b >> 3) as usize] & (1 << (b & 7)) != 0,
+            Err(_) => self.items().any(|item| item.is_member(c)),
+        }
+    }
+}
+
+impl fmt::Display for ClassSet<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.charset())?;
+        for item in self.items() {
+            write!(f, " {}", item)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for ClassSet<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "ClassSet({})", self)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_class_set() {
+        let mut charset = Charset::new();
+        charset.insert(b'_');
+        charset.insert(b'$');
+        let greek = UnicodeClass::from_name("Greek").unwrap();
+        let items = [
+            ClassItem::Range('←', '↓'),
+            ClassItem::Class(CharacterClass::Alphabetic),
+            ClassItem::UnicodeClass(greek),
+        ];
+        let mut bytes = ClassSet::encode(&charset, &items);
+        bytes.push(255);
+        let (class_set, size) = ClassSet::parse(&bytes).unwrap();
+        assert_eq!(size, bytes.len() - 1);
+        assert_eq!(class_set.charset(), charset);
+        assert_eq!(class_set.items().collect::<Vec<_>>(), items);
+        assert!(class_set.contains('_') && class_set.contains('↑') && class_set.contains('ж') && class_set.contains('ͅ'));
+        // items don't apply to chars in the charset range
+        assert!(!class_set.contains('a') && !class_set.contains('é'));
+        assert!(!class_set.contains('⇒') && !class_set.contains('!'));
+        assert_eq!(class_set.to_string(), "[$_] [←-↓] \\a \\p{Greek}");
+
+        assert_eq!(ClassSet::parse(&bytes[..32]).err(), Some(ParseError::MissingArgument));
+        assert_eq!(ClassSet::parse(&bytes[..40]).err(), Some(ParseError::MissingArgument));
+        let mut invalid = ClassSet::encode(&charset, &[ClassItem::Range('b', 'a')]);
+        assert_eq!(ClassSet::parse(&invalid).err(), Some(ParseError::InvalidRange));
+        invalid[33] = 7;
+        assert_eq!(ClassSet::parse(&invalid).err(), Some(ParseError::InvalidCharacterClass));
+    }
+}
@@ -0,0 +1,291 @@
+use super::{Bytecode, Metadata, OwnedBytecode};
+use super::address::Address;
+use super::instruction::{Instruction, InstructionIterator};
+use super::parser::ParseError;
+use super::symbols::SymbolTable;
+use crate::slice_to_array;
+
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::io::{self, Read, Write};
+use std::str;
+
+// Layout, all integers little endian:
+//   magic "PEGB", version: u16
+//   sections: tag: u8, length: u32, payload
+// The code section is required, every other section is optional.
+// Unknown sections are skipped, so new optional sections don't need a
+// version bump, but new opcodes do since older readers would reject them.
+//
+// Versions:
+//   1: opcodes `any` to `halt`, 2-byte addresses
+//   2: 4-byte addresses
+//   3: opcodes `tset`, `cset`, `ncset`, `crng`, `ucls`, `clset`, `istr`,
+//      `iset`, `back` and `eoi`
+//   4: opcode `fmov`
+
+pub const MAGIC: [u8; 4] = *b"PEGB";
+pub const FORMAT_VERSION: u16 = 4;
+
+const SECTION_CODE: u8 = 1;
+const SECTION_SYMBOLS: u8 = 2;
+const SECTION_CAPTURE_NAMES: u8 = 3;
+const SECTION_DEBUG_INFO: u8 = 4;
+const SECTION_TAIL_CALLS: u8 = 5;
+
+impl From<io::Error> for ParseError {
+    fn from(error: io::Error) -> ParseError {
+        ParseError::Io(error.kind())
+    }
+}
+
+fn push_u32(bytes: &mut Vec<u8>, value: usize) {
+    bytes.extend_from_slice(&(value as u32).to_le_bytes());
+}
+
+fn push_string(bytes: &mut Vec<u8>, s: &str) {
+    push_u32(bytes, s.len());
+    bytes.extend_from_slice(s.as_bytes());
+}
+
+fn write_section<W: Write>(out: &mut W, tag: u8, payload: &[u8]) -> io::Result<()> {
+    out.write_all(&[tag])?;
+    out.write_all(&(payload.len() as u32).to_le_bytes())?;
+    out.write_all(payload)
+}
+
+pub fn write_bytecode<W: Write>(out: &mut W, bytecode: &OwnedBytecode) -> io::Result<()> {
+    let metadata = bytecode.metadata();
+    out.write_all(&MAGIC)?;
+    out.write_all(&FORMAT_VERSION.to_le_bytes())?;
+    write_section(out, SECTION_CODE, &bytecode.as_bytecode())?;
+    if !metadata.symbols.is_empty() {
+        let mut payload = Vec::new();
+        push_u32(&mut payload, metadata.symbols.len());
+        for (address, name) in metadata.symbols.iter() {
+            let address: [u8; 4] = address.into();
+            payload.extend_from_slice(&address);
+            push_string(&mut payload, name);
+        }
+        write_section(out, SECTION_SYMBOLS, &payload)?;
+    }
+    if !metadata.capture_names.is_empty() {
+        let mut payload = Vec::new();
+        push_u32(&mut payload, metadata.capture_names.len());
+        for name in metadata.capture_names.iter() {
+            push_string(&mut payload, name);
+        }
+        write_section(out, SECTION_CAPTURE_NAMES, &payload)?;
+    }
+    if let Some(source) = &metadata.debug_info {
+        let mut payload = Vec::new();
+        push_string(&mut payload, source);
+        write_section(out, SECTION_DEBUG_INFO, &payload)?;
+    }
+    if !metadata.tail_calls.is_empty() {
+        let mut payload = Vec::new();
+        push_u32(&mut payload, metadata.tail_calls.len());
+        for address in metadata.tail_calls.iter() {
+            let address: [u8; 4] = (*address).into();
+            payload.extend_from_slice(&address);
+        }
+        write_section(out, SECTION_TAIL_CALLS, &payload)?;
+    }
+    Ok(())
+}
+
+struct SectionReader<'a>(&'a [u8]);
+
+impl<'a> SectionReader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], ParseError> {
+        if self.0.len() < n {
+            return Err(ParseError::InvalidSection);
+        }
+        let (taken, rest) = self.0.split_at(n);
+        self.0 = rest;
+        Ok(taken)
+    }
+
+    fn read_u32(&mut self) -> Result<usize, ParseError> {
+        let bytes = slice_to_array!(self.take(4)?, u8, 4).ok_or(ParseError::InvalidSection)?;
+        Ok(u32::from_le_bytes(bytes) as usize)
+    }
+
+    fn read_address(&mut self) -> Result<Address, ParseError> {
+        let bytes = slice_to_array!(self.take(4)?, u8, 4).ok_or(ParseError::InvalidSection)?;
+        Ok(Address::from(bytes))
+    }
+
+    fn read_string(&mut self) -> Result<&'a str, ParseError> {
+        let len = self.read_u32()?;
+        Ok(str::from_utf8(self.take(len)?)?)
+    }
+
+    fn finish(&self) -> Result<(), ParseError> {
+        if self.0.is_empty() { Ok(()) } else { Err(ParseError::InvalidSection) }
+    }
+}
+
+pub fn read_bytecode<R: Read>(input: &mut R) -> Result<OwnedBytecode, ParseError> {
+    let mut bytes = Vec::new();
+    input.read_to_end(&mut bytes)?;
+    let mut reader = SectionReader(&bytes);
+    if reader.take(MAGIC.len()).ok() != Some(&MAGIC[..]) {
+        return Err(ParseError::InvalidMagic);
+    }
+    let version = slice_to_array!(reader.take(2)?, u8, 2).ok_or(ParseError::InvalidSection)?;
+    let version = u16::from_le_bytes(version);
+    if version != FORMAT_VERSION {
+        return Err(ParseError::UnsupportedVersion(version));
+    }
+
+    let mut code = None;
+    let mut metadata = Metadata::default();
+    while !reader.0.is_empty() {
+        let tag = reader.take(1)?[0];
+        let len = reader.read_u32()?;
+        let mut section = SectionReader(reader.take(len)?);
+        match tag {
+            SECTION_CODE if code.is_some() => return Err(ParseError::InvalidSection),
+            SECTION_CODE => {
+                code = Some(section.take(len)?);
+            },
+            SECTION_SYMBOLS => {
+                let mut symbols = SymbolTable::new();
+                for _ in 0..section.read_u32()? {
+                    let address = section.read_address()?;
+                    symbols.insert(address, section.read_string()?);
+                }
+                metadata.symbols = symbols;
+            },
+            SECTION_CAPTURE_NAMES => {
+                let count = section.read_u32()?;
+                let mut names = Vec::new();
+                for _ in 0..count {
+                    names.push(section.read_string()?.to_string());
+                }
+                metadata.capture_names = names;
+            },
+            SECTION_DEBUG_INFO => {
+                metadata.debug_info = Some(section.read_string()?.to_string());
+            },
+            SECTION_TAIL_CALLS => {
+                let count = section.read_u32()?;
+                let mut addresses = Vec::new();
+                for _ in 0..count {
+                    addresses.push(section.read_address()?);
+                }
+                metadata.tail_calls = addresses;
+            },
+            _ => continue,
+        }
+        section.finish()?;
+    }
+
+    let code = code.ok_or(ParseError::MissingCode)?;
+    if let Some(error) = Bytecode::check_error(code) {
+        return Err(error);
+    }
+    check_metadata(code, &metadata)?;
+    Ok(OwnedBytecode::from_bytes_unchecked(code.to_vec()).with_metadata(metadata))
+}
+
+// Symbols name the first instruction of a rule and tail calls are jumps,
+// so each address must start an instruction of the right kind
+fn check_metadata(code: &[u8], metadata: &Metadata) -> Result<(), ParseError> {
+    let mut instructions = HashMap::new();
+    let mut iter = InstructionIterator::new(code);
+    loop {
+        let address: usize = iter.current().into();
+        match iter.next() {
+            Some(instruction) => instructions.insert(address, matches!(instruction, Instruction::Jump(_))),
+            None => break,
+        };
+    }
+    let symbols_valid = metadata.symbols.iter().all(|(address, _)| instructions.contains_key(&address.into()));
+    let tail_calls_valid = metadata.tail_calls.iter().all(|address| instructions.get(&(*address).into()) == Some(&true));
+    if symbols_valid && tail_calls_valid { Ok(()) } else { Err(ParseError::InvalidSection) }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bytecode::instruction::Instruction::*;
+
+    fn sample() -> OwnedBytecode {
+        let mut metadata = Metadata::default();
+        metadata.symbols.insert(Address::new(0), "Main");
+        metadata.symbols.insert(Address::new(6), "Digit");
+        metadata.capture_names.push("digit".to_string());
+        metadata.debug_info = Some("Main <- Digit\nDigit <- [0-9]".to_string());
+        metadata.tail_calls.push(Address::new(0));
+        OwnedBytecode::from_instructions(&[Jump(Address::new(6)), Return, Range(b'0', b'9'), Capture(0), Return])
+            .with_metadata(metadata)
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let bytecode = sample();
+        let mut file = Vec::new();
+        bytecode.write_to(&mut file).unwrap();
+        assert_eq!(&file[..6], b"PEGB\x04\x00");
+
+        let read = OwnedBytecode::read_from(&mut &file[..]).unwrap();
+        assert_eq!(&*read.as_bytecode(), &*bytecode.as_bytecode());
+        assert_eq!(read.metadata(), bytecode.metadata());
+
+        let bare = OwnedBytecode::from_instructions(&[Any]);
+        let mut file = Vec::new();
+        bare.write_to(&mut file).unwrap();
+        let read = OwnedBytecode::read_from(&mut &file[..]).unwrap();
+        assert_eq!(read.metadata(), &Metadata::default());
+    }
+
+    #[test]
+    fn test_read_errors() {
+        let mut file = Vec::new();
+        sample().write_to(&mut file).unwrap();
+
+        let mut bad_magic = file.clone();
+        bad_magic[0] = b'X';
+        assert_eq!(OwnedBytecode::read_from(&mut &bad_magic[..]).err(), Some(ParseError::InvalidMagic));
+
+        let mut bad_version = file.clone();
+        bad_version[4] = 9;
+        assert_eq!(OwnedBytecode::read_from(&mut &bad_version[..]).err(), Some(ParseError::UnsupportedVersion(9)));
+        // older files may hold addresses or opcodes read differently
+        bad_version[4] = 2;
+        assert_eq!(OwnedBytecode::read_from(&mut &bad_version[..]).err(), Some(ParseError::UnsupportedVersion(2)));
+
+        let truncated = &file[..file.len() - 1];
+        assert_eq!(OwnedBytecode::read_from(&mut &truncated[..]).err(), Some(ParseError::InvalidSection));
+
+        let no_code = b"PEGB\x04\x00";
+        assert_eq!(OwnedBytecode::read_from(&mut &no_code[..]).err(), Some(ParseError::MissingCode));
+
+        let invalid_code = b"PEGB\x04\x00\x01\x01\x00\x00\x00\xff";
+        assert_eq!(OwnedBytecode::read_from(&mut &invalid_code[..]).err(), Some(ParseError::InvalidOpcode));
+
+        let mut two_codes = file.clone();
+        two_codes.extend_from_slice(&file[6..]);
+        assert_eq!(OwnedBytecode::read_from(&mut &two_codes[..]).err(), Some(ParseError::InvalidSection));
+
+        let with_metadata = |edit: fn(&mut Metadata)| {
+            let bytecode = sample();
+            let mut metadata = bytecode.metadata().clone();
+            edit(&mut metadata);
+            let bytecode = bytecode.with_metadata(metadata);
+            let mut file = Vec::new();
+            bytecode.write_to(&mut file).unwrap();
+            OwnedBytecode::read_from(&mut &file[..]).err()
+        };
+        assert_eq!(with_metadata(|metadata| metadata.symbols.insert(Address::new(3), "Inside")), Some(ParseError::InvalidSection));
+        assert_eq!(with_metadata(|metadata| metadata.symbols.insert(Address::new(100), "Past")), Some(ParseError::InvalidSection));
+        assert_eq!(with_metadata(|metadata| metadata.tail_calls.push(Address::new(5))), Some(ParseError::InvalidSection));
+
+        let mut unknown_section = b"PEGB\x04\x00\x7f\x02\x00\x00\x00ab".to_vec();
+        unknown_section.extend_from_slice(&file[6..]);
+        assert!(OwnedBytecode::read_from(&mut &unknown_section[..]).is_ok());
+    }
+}
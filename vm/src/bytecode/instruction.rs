@@ -1,12 +1,15 @@
 use super::address::Address;
+use super::charset::Charset;
+use super::class_set::ClassSet;
 use super::opcode::Opcode;
 use super::parser::{self, ParseError};
 use crate::grammar::character_class::CharacterClass;
+use crate::grammar::unicode::UnicodeClass;
 
 use std::fmt;
 use std::iter::Iterator;
 
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum Instruction<'a> {
     Any,
     Succeed,
@@ -32,6 +35,23 @@ pub enum Instruction<'a> {
     Range(u8, u8),
     Capture(u8),
     Halt(Option<ParseError>),
+    /// Fails and jumps unless the next char is in the set, without consuming it
+    TestSet(Address, &'a str),
+    Charset(Charset),
+    NotCharset(Charset),
+    /// Inclusive range of codepoints, while `Range` matches bytes
+    CharRange(char, char),
+    UnicodeClass(UnicodeClass),
+    ClassSet(ClassSet<'a>),
+    /// Literal compared under simple case folding
+    LiteralNoCase(&'a str),
+    /// Set compared under simple case folding
+    SetNoCase(&'a str),
+    /// Moves back this many chars, failing if there are not as many before
+    Behind(u8),
+    EndOfInput,
+    /// Fails unless the position is the one saved on top of the stack
+    FailIfMoved,
 }
 
 impl Instruction<'_> {
@@ -62,6 +82,17 @@ impl Instruction<'_> {
             Range(_, _) => Opcode::Range,
             Capture(_) => Opcode::Capture,
             Halt(_) => Opcode::Halt,
+            TestSet(_, _) => Opcode::TestSet,
+            Charset(_) => Opcode::Charset,
+            NotCharset(_) => Opcode::NotCharset,
+            CharRange(_, _) => Opcode::CharRange,
+            UnicodeClass(_) => Opcode::UnicodeClass,
+            ClassSet(_) => Opcode::ClassSet,
+            LiteralNoCase(_) => Opcode::LiteralNoCase,
+            SetNoCase(_) => Opcode::SetNoCase,
+            Behind(_) => Opcode::Behind,
+            EndOfInput => Opcode::EndOfInput,
+            FailIfMoved => Opcode::FailIfMoved,
         }
     }
 }
@@ -71,15 +102,20 @@ impl fmt::Display for Instruction<'_> {
         use Instruction::*;
         let res = write!(f, "{}", self.opcode());
         match *self {
-            FailIfLessThan(n) | Capture(n) => write!(f, " {}", n),
+            FailIfLessThan(n) | Capture(n) | Behind(n) => write!(f, " {}", n),
             Jump(address) | JumpIfFail(address) | JumpIfSuccess(address) | Call(address) => {
                 write!(f, " {}", address)
             },
             Byte(byte) => write!(f, " {}", byte),
             Char(c) => write!(f, " {:?}", c),
             Class(character_class) => write!(f, " \\{}", character_class as u8 as char),
-            Literal(string) | Set(string) | NotSet(string) => write!(f, " {:?}", string),
-            Range(min, max) => write!(f, " [{}-{}]", min as char, max as char),
+            Literal(string) | Set(string) | NotSet(string) | LiteralNoCase(string) | SetNoCase(string) => write!(f, " {:?}", string),
+            Range(min, max) => write!(f, " [{}-{}]", (min as char).escape_debug(), (max as char).escape_debug()),
+            TestSet(address, string) => write!(f, " {} {:?}", address, string),
+            Charset(charset) | NotCharset(charset) => write!(f, " {}", charset),
+            CharRange(min, max) => write!(f, " [{}-{}]", min.escape_debug(), max.escape_debug()),
+            UnicodeClass(class) => write!(f, " {}", class),
+            ClassSet(class_set) => write!(f, " {}", class_set),
             //Halt(_) => Opcode::Halt,
             _ => res
         }
@@ -92,8 +128,8 @@ pub struct InstructionIterator<'a> {
 }
 
 impl<'a> InstructionIterator<'a> {
-    pub fn new(bytes: &'a [u8]) -> InstructionIterator {
-        InstructionIterator { bytes: bytes, current: Address::new(0) }
+    pub fn new(bytes: &'a [u8]) -> InstructionIterator<'a> {
+        InstructionIterator { bytes, current: Address::new(0) }
     }
 
     pub fn jump(&mut self, address: Address) {
@@ -121,7 +157,7 @@ impl<'a> Iterator for InstructionIterator<'a> {
             let slice = &self.bytes[current..];
             match parser::parse_instruction(slice) {
                 Ok((instruction, increment)) => {
-                    self.current += increment as u16;
+                    self.current += increment as u32;
                     Some(instruction)
                 },
                 Err(error) => {
@@ -143,7 +179,7 @@ mod tests {
         // \w*
         let bytecode = [
             Opcode::Class as u8, b'w',
-            Opcode::JumpIfSuccess as u8, 0, 0,
+            Opcode::JumpIfSuccess as u8, 0, 0, 0, 0,
             Opcode::Succeed as u8,
         ];
         let mut iter = InstructionIterator::new(&bytecode);
@@ -158,7 +194,7 @@ mod tests {
 
         let faulty_bytecode = [
             Opcode::Class as u8, b'w',
-            Opcode::JumpIfSuccess as u8, 0,
+            Opcode::JumpIfSuccess as u8, 0, 0, 0,
         ];
         let mut iter = InstructionIterator::new(&faulty_bytecode);
         assert_eq!(iter.next().unwrap(), Instruction::Class(CharacterClass::Alphanumeric));
@@ -27,9 +27,20 @@ pub enum Opcode {
     Range,
     Capture,
     Halt,
+    TestSet,
+    Charset,
+    NotCharset,
+    CharRange,
+    UnicodeClass,
+    ClassSet,
+    LiteralNoCase,
+    SetNoCase,
+    Behind,
+    EndOfInput,
+    FailIfMoved,
 }
 
-const OPCODE_TABLE: [Opcode; 24] = [
+const OPCODE_TABLE: [Opcode; 35] = [
     Opcode::Any,
     Opcode::Succeed,
     Opcode::Fail,
@@ -54,9 +65,20 @@ const OPCODE_TABLE: [Opcode; 24] = [
     Opcode::Range,
     Opcode::Capture,
     Opcode::Halt,
+    Opcode::TestSet,
+    Opcode::Charset,
+    Opcode::NotCharset,
+    Opcode::CharRange,
+    Opcode::UnicodeClass,
+    Opcode::ClassSet,
+    Opcode::LiteralNoCase,
+    Opcode::SetNoCase,
+    Opcode::Behind,
+    Opcode::EndOfInput,
+    Opcode::FailIfMoved,
 ];
 
-const OPCODE_ASSEMBLY_TABLE: [&str; 24] = [
+const OPCODE_ASSEMBLY_TABLE: [&str; 35] = [
     "any",
     "succ",
     "fail",
@@ -81,8 +103,26 @@ const OPCODE_ASSEMBLY_TABLE: [&str; 24] = [
     "rng",
     "cap",
     "halt",
+    "tset",
+    "cset",
+    "ncset",
+    "crng",
+    "ucls",
+    "clset",
+    "istr",
+    "iset",
+    "back",
+    "eoi",
+    "fmov",
 ];
 
+impl Opcode {
+    /// Opcode written as `name` in assembly
+    pub fn from_assembly(name: &str) -> Option<Opcode> {
+        OPCODE_ASSEMBLY_TABLE.iter().position(|n| *n == name).map(|index| OPCODE_TABLE[index])
+    }
+}
+
 pub struct TryFromByteError;
 
 impl TryFrom<u8> for Opcode {
@@ -7,10 +7,12 @@ use crate::grammar::character_class::{self, CharacterClass};
 use std::array;
 use std::convert::{From, TryFrom, TryInto};
 use std::cmp;
+use std::fmt;
+use std::io;
 use std::mem;
 use std::str;
 
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum ParseError {
     EmptyChunk,
     InvalidOpcode,
@@ -19,6 +21,31 @@ pub enum ParseError {
     Utf8Error,
     InvalidRange,
     MissingArgument,
+    InvalidMagic,
+    UnsupportedVersion(u16),
+    InvalidSection,
+    MissingCode,
+    Io(io::ErrorKind),
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use ParseError::*;
+        match self {
+            EmptyChunk => write!(f, "unexpected end of bytecode"),
+            InvalidOpcode => write!(f, "invalid opcode"),
+            InvalidCharacterClass => write!(f, "invalid character class"),
+            MissingStringTerminator => write!(f, "missing string terminator"),
+            Utf8Error => write!(f, "invalid UTF-8 in string argument"),
+            InvalidRange => write!(f, "invalid range"),
+            MissingArgument => write!(f, "missing instruction argument"),
+            InvalidMagic => write!(f, "not a pegatexto bytecode file"),
+            UnsupportedVersion(version) => write!(f, "unsupported bytecode format version {}, expected {}", version, super::file::FORMAT_VERSION),
+            InvalidSection => write!(f, "truncated or malformed section"),
+            MissingCode => write!(f, "missing code section"),
+            Io(kind) => write!(f, "I/O error: {:?}", kind),
+        }
+    }
 }
 
 impl From<opcode::TryFromByteError> for ParseError {
@@ -62,7 +89,7 @@ macro_rules! parse_instruction_address {
     ($ctor:ident, $bytes:ident) => {
         {
             let address = parse_address_argument($bytes)?;
-            Ok(($ctor(address), 3))
+            Ok(($ctor(address), 5))
         }
     }
 }
@@ -83,6 +110,14 @@ macro_rules! parse_instruction_string {
         }
     }
 }
+macro_rules! parse_instruction_charset {
+    ($ctor:ident, $bytes:ident) => {
+        {
+            let bytes = slice_to_array!($bytes, u8, 32).ok_or(ParseError::MissingArgument)?;
+            Ok(($ctor(super::charset::Charset::from_bytes(bytes)), 33))
+        }
+    }
+}
 macro_rules! parse_instruction_range {
     ($ctor:ident, $bytes:ident) => {
         {
@@ -93,7 +128,7 @@ macro_rules! parse_instruction_range {
 }
 
 fn parse_byte_argument(bytes: &[u8]) -> Result<u8, ParseError> {
-    match bytes.get(0) {
+    match bytes.first() {
         Some(b) => Ok(*b),
         None => Err(ParseError::MissingArgument),
     }
@@ -107,8 +142,8 @@ fn parse_char_argument(bytes: &[u8]) -> Result<char, ParseError> {
 }
 
 fn parse_address_argument(bytes: &[u8]) -> Result<Address, ParseError> {
-    match slice_to_array!(bytes, u8, 2) {
-        Some(two_byte_array) => Ok(Address::from(two_byte_array)),
+    match slice_to_array!(bytes, u8, 4) {
+        Some(four_byte_array) => Ok(Address::from(four_byte_array)),
         None => Err(ParseError::MissingArgument),
     }
 }
@@ -126,11 +161,23 @@ fn parse_range_argument(bytes: &[u8]) -> Result<(u8, u8), ParseError> {
     }
 }
 
+fn parse_char_range_argument(bytes: &[u8]) -> Result<(char, char), ParseError> {
+    let min = slice_to_array!(bytes, u8, 4).ok_or(ParseError::MissingArgument)?;
+    let max = slice_to_array!(&bytes[4..], u8, 4).ok_or(ParseError::MissingArgument)?;
+    let min = char::from_u32(u32::from_le_bytes(min)).ok_or(ParseError::InvalidRange)?;
+    let max = char::from_u32(u32::from_le_bytes(max)).ok_or(ParseError::InvalidRange)?;
+    if min <= max {
+        Ok((min, max))
+    } else {
+        Err(ParseError::InvalidRange)
+    }
+}
+
 fn parse_string_argument(bytes: &[u8]) -> Result<&str, ParseError> {
-    if bytes.len() == 0 || bytes[0] == 0 {
+    if bytes.is_empty() || bytes[0] == 0 {
         return Err(ParseError::MissingArgument)
     }
-    let s = match bytes.iter().enumerate().skip_while(|(_, b)| **b != 0).next() {
+    let s = match bytes.iter().enumerate().find(|(_, b)| **b == 0) {
         Some((size_until_null, _last_byte)) => {
             let slice = &bytes[0..size_until_null];
             str::from_utf8(slice)?
@@ -140,9 +187,9 @@ fn parse_string_argument(bytes: &[u8]) -> Result<&str, ParseError> {
     Ok(s)
 }
 
-pub fn parse_instruction(bytes: &[u8]) -> Result<(Instruction, usize), ParseError> {
+pub fn parse_instruction(bytes: &[u8]) -> Result<(Instruction<'_>, usize), ParseError> {
     use Instruction::*;
-    let opcode = match bytes.get(0) {
+    let opcode = match bytes.first() {
         Some(byte) => Opcode::try_from(*byte)?,
         None => Err(ParseError::EmptyChunk)?,
     };
@@ -169,9 +216,34 @@ pub fn parse_instruction(bytes: &[u8]) -> Result<(Instruction, usize), ParseErro
         Opcode::Literal => parse_instruction_string!(Literal, bytes),
         Opcode::Set => parse_instruction_string!(Set, bytes),
         Opcode::NotSet => parse_instruction_string!(NotSet, bytes),
+        Opcode::LiteralNoCase => parse_instruction_string!(LiteralNoCase, bytes),
+        Opcode::SetNoCase => parse_instruction_string!(SetNoCase, bytes),
+        Opcode::Behind => parse_instruction_byte!(Behind, bytes),
+        Opcode::EndOfInput => Ok((EndOfInput, 1)),
+        Opcode::FailIfMoved => Ok((FailIfMoved, 1)),
         Opcode::Range => parse_instruction_range!(Range, bytes),
         Opcode::Capture => parse_instruction_byte!(Capture, bytes),
         Opcode::Halt => Ok((Halt(None), 1)),
+        Opcode::Charset => parse_instruction_charset!(Charset, bytes),
+        Opcode::NotCharset => parse_instruction_charset!(NotCharset, bytes),
+        Opcode::ClassSet => {
+            let (class_set, size) = super::class_set::ClassSet::parse(bytes)?;
+            Ok((ClassSet(class_set), 1 + size))
+        },
+        Opcode::UnicodeClass => {
+            let bytes = slice_to_array!(bytes, u8, 5).ok_or(ParseError::MissingArgument)?;
+            let class = crate::grammar::unicode::UnicodeClass::from_bytes(bytes).ok_or(ParseError::InvalidCharacterClass)?;
+            Ok((UnicodeClass(class), 6))
+        },
+        Opcode::CharRange => {
+            let (min, max) = parse_char_range_argument(bytes)?;
+            Ok((CharRange(min, max), 9))
+        },
+        Opcode::TestSet => {
+            let address = parse_address_argument(bytes)?;
+            let string = parse_string_argument(&bytes[4..])?;
+            Ok((TestSet(address, string), 1 + 4 + string.len() + 1))
+        },
     }
 }
 
@@ -179,6 +251,7 @@ pub fn parse_instruction(bytes: &[u8]) -> Result<(Instruction, usize), ParseErro
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::bytecode::charset::Charset;
 
     macro_rules! test_parse {
         ($array:expr, $result:expr) => {
@@ -206,28 +279,32 @@ mod tests {
 
         test_parse!([Opcode::QuantifierNext as u8], Ok((Instruction::QuantifierNext, 1)));
 
-        test_parse!([Opcode::Jump as u8, 42, 0], Ok((Instruction::Jump(Address::new(42)), 3)));
-        test_parse!([Opcode::Jump as u8, 42, 0, 255], Ok((Instruction::Jump(Address::new(42)), 3)));
-        test_parse!([Opcode::Jump as u8, 0, 1], Ok((Instruction::Jump(Address::new(256)), 3)));
-        test_parse!([Opcode::Jump as u8, 42], Err(ParseError::MissingArgument));
+        test_parse!([Opcode::Jump as u8, 42, 0, 0, 0], Ok((Instruction::Jump(Address::new(42)), 5)));
+        test_parse!([Opcode::Jump as u8, 42, 0, 0, 0, 255], Ok((Instruction::Jump(Address::new(42)), 5)));
+        test_parse!([Opcode::Jump as u8, 0, 1, 0, 0], Ok((Instruction::Jump(Address::new(256)), 5)));
+        test_parse!([Opcode::Jump as u8, 0, 0, 1, 0], Ok((Instruction::Jump(Address::new(65536)), 5)));
+        test_parse!([Opcode::Jump as u8, 42, 0, 0], Err(ParseError::MissingArgument));
         test_parse!([Opcode::Jump as u8], Err(ParseError::MissingArgument));
 
-        test_parse!([Opcode::JumpIfFail as u8, 42, 0], Ok((Instruction::JumpIfFail(Address::new(42)), 3)));
-        test_parse!([Opcode::JumpIfFail as u8, 42, 0, 255], Ok((Instruction::JumpIfFail(Address::new(42)), 3)));
-        test_parse!([Opcode::JumpIfFail as u8, 0, 1], Ok((Instruction::JumpIfFail(Address::new(256)), 3)));
-        test_parse!([Opcode::JumpIfFail as u8, 42], Err(ParseError::MissingArgument));
+        test_parse!([Opcode::JumpIfFail as u8, 42, 0, 0, 0], Ok((Instruction::JumpIfFail(Address::new(42)), 5)));
+        test_parse!([Opcode::JumpIfFail as u8, 42, 0, 0, 0, 255], Ok((Instruction::JumpIfFail(Address::new(42)), 5)));
+        test_parse!([Opcode::JumpIfFail as u8, 0, 1, 0, 0], Ok((Instruction::JumpIfFail(Address::new(256)), 5)));
+        test_parse!([Opcode::JumpIfFail as u8, 0, 0, 1, 0], Ok((Instruction::JumpIfFail(Address::new(65536)), 5)));
+        test_parse!([Opcode::JumpIfFail as u8, 42, 0, 0], Err(ParseError::MissingArgument));
         test_parse!([Opcode::JumpIfFail as u8], Err(ParseError::MissingArgument));
 
-        test_parse!([Opcode::JumpIfSuccess as u8, 42, 0], Ok((Instruction::JumpIfSuccess(Address::new(42)), 3)));
-        test_parse!([Opcode::JumpIfSuccess as u8, 42, 0, 255], Ok((Instruction::JumpIfSuccess(Address::new(42)), 3)));
-        test_parse!([Opcode::JumpIfSuccess as u8, 0, 1], Ok((Instruction::JumpIfSuccess(Address::new(256)), 3)));
-        test_parse!([Opcode::JumpIfSuccess as u8, 42], Err(ParseError::MissingArgument));
+        test_parse!([Opcode::JumpIfSuccess as u8, 42, 0, 0, 0], Ok((Instruction::JumpIfSuccess(Address::new(42)), 5)));
+        test_parse!([Opcode::JumpIfSuccess as u8, 42, 0, 0, 0, 255], Ok((Instruction::JumpIfSuccess(Address::new(42)), 5)));
+        test_parse!([Opcode::JumpIfSuccess as u8, 0, 1, 0, 0], Ok((Instruction::JumpIfSuccess(Address::new(256)), 5)));
+        test_parse!([Opcode::JumpIfSuccess as u8, 0, 0, 1, 0], Ok((Instruction::JumpIfSuccess(Address::new(65536)), 5)));
+        test_parse!([Opcode::JumpIfSuccess as u8, 42, 0, 0], Err(ParseError::MissingArgument));
         test_parse!([Opcode::JumpIfSuccess as u8], Err(ParseError::MissingArgument));
 
-        test_parse!([Opcode::Call as u8, 42, 0], Ok((Instruction::Call(Address::new(42)), 3)));
-        test_parse!([Opcode::Call as u8, 42, 0, 255], Ok((Instruction::Call(Address::new(42)), 3)));
-        test_parse!([Opcode::Call as u8, 0, 1], Ok((Instruction::Call(Address::new(256)), 3)));
-        test_parse!([Opcode::Call as u8, 42], Err(ParseError::MissingArgument));
+        test_parse!([Opcode::Call as u8, 42, 0, 0, 0], Ok((Instruction::Call(Address::new(42)), 5)));
+        test_parse!([Opcode::Call as u8, 42, 0, 0, 0, 255], Ok((Instruction::Call(Address::new(42)), 5)));
+        test_parse!([Opcode::Call as u8, 0, 1, 0, 0], Ok((Instruction::Call(Address::new(256)), 5)));
+        test_parse!([Opcode::Call as u8, 0, 0, 1, 0], Ok((Instruction::Call(Address::new(65536)), 5)));
+        test_parse!([Opcode::Call as u8, 42, 0, 0], Err(ParseError::MissingArgument));
         test_parse!([Opcode::Call as u8], Err(ParseError::MissingArgument));
 
         test_parse!([Opcode::Return as u8], Ok((Instruction::Return, 1)));
@@ -259,6 +336,10 @@ mod tests {
         test_parse!([Opcode::Class as u8, b'u'], Ok((Instruction::Class(CharacterClass::Uppercase), 2)));
         test_parse!([Opcode::Class as u8, b'x'], Ok((Instruction::Class(CharacterClass::Hexadigit), 2)));
         test_parse!([Opcode::Class as u8, b'x', 0], Ok((Instruction::Class(CharacterClass::Hexadigit), 2)));
+        test_parse!([Opcode::Class as u8, b'D'], Ok((Instruction::Class(CharacterClass::NotDigit), 2)));
+        test_parse!([Opcode::Class as u8, b'W'], Ok((Instruction::Class(CharacterClass::NotAlphanumeric), 2)));
+        test_parse!([Opcode::Class as u8, b'S'], Ok((Instruction::Class(CharacterClass::NotWhitespace), 2)));
+        test_parse!([Opcode::Class as u8, b'Z'], Err(ParseError::InvalidCharacterClass));
         test_parse!([Opcode::Class as u8, 0], Err(ParseError::InvalidCharacterClass));
         test_parse!([Opcode::Class as u8], Err(ParseError::MissingArgument));
 
@@ -294,7 +375,50 @@ mod tests {
 
         test_parse!([Opcode::Halt as u8], Ok((Instruction::Halt(None), 1)));
 
-        test_parse!([Opcode::Halt as u8 + 1], Err(ParseError::InvalidOpcode));
+        test_parse!([Opcode::TestSet as u8, 42, 0, 0, 0, b'a', b'b', 0], Ok((Instruction::TestSet(Address::new(42), "ab"), 8)));
+        test_parse!([Opcode::TestSet as u8, 42, 0, 0, 0, b'a', b'b'], Err(ParseError::MissingStringTerminator));
+        test_parse!([Opcode::TestSet as u8, 42, 0, 0, 0, 0], Err(ParseError::MissingArgument));
+        test_parse!([Opcode::TestSet as u8, 42, 0, 0, 0], Err(ParseError::MissingArgument));
+        test_parse!([Opcode::TestSet as u8, 42], Err(ParseError::MissingArgument));
+
+        let mut charset = [Opcode::Charset as u8; 33];
+        charset[1..].copy_from_slice(&[0; 32]);
+        charset[7] = 0b10;
+        let mut expected = Charset::new();
+        expected.insert(b'1');
+        test_parse!(charset, Ok((Instruction::Charset(expected), 33)));
+        charset[0] = Opcode::NotCharset as u8;
+        test_parse!(charset, Ok((Instruction::NotCharset(expected), 33)));
+        test_parse!(charset[..32], Err(ParseError::MissingArgument));
+
+        test_parse!([Opcode::CharRange as u8, 0xb1, 0x03, 0, 0, 0xc9, 0x03, 0, 0], Ok((Instruction::CharRange('α', 'ω'), 9)));
+        test_parse!([Opcode::CharRange as u8, b'a', 0, 0, 0, b'a', 0, 0, 0, 1], Ok((Instruction::CharRange('a', 'a'), 9)));
+        test_parse!([Opcode::CharRange as u8, b'b', 0, 0, 0, b'a', 0, 0, 0], Err(ParseError::InvalidRange));
+        test_parse!([Opcode::CharRange as u8, 0, 0xd8, 0, 0, b'a', 0, 0, 0], Err(ParseError::InvalidRange));
+        test_parse!([Opcode::CharRange as u8, b'a', 0, 0, 0, b'z', 0, 0], Err(ParseError::MissingArgument));
+        test_parse!([Opcode::CharRange as u8, b'a', 0], Err(ParseError::MissingArgument));
+
+        let greek = crate::grammar::unicode::UnicodeClass::from_name("Greek").unwrap();
+        let mut unicode_class = vec![Opcode::UnicodeClass as u8];
+        unicode_class.extend_from_slice(&greek.to_bytes());
+        test_parse!(unicode_class, Ok((Instruction::UnicodeClass(greek), 6)));
+        test_parse!([Opcode::UnicodeClass as u8, 7, 0, 0, 0, 0], Err(ParseError::InvalidCharacterClass));
+        test_parse!([Opcode::UnicodeClass as u8, 0, 0, 0, 0], Err(ParseError::MissingArgument));
+
+        let mut class_set = vec![Opcode::ClassSet as u8];
+        class_set.extend_from_slice(&super::super::class_set::ClassSet::encode(&Charset::new(), &[]));
+        let expected = super::super::class_set::ClassSet::parse(&class_set[1..]).unwrap().0;
+        test_parse!(class_set, Ok((Instruction::ClassSet(expected), 34)));
+        test_parse!(class_set[..33], Err(ParseError::MissingArgument));
+
+        test_parse!([Opcode::LiteralNoCase as u8, b'i', b'F', 0], Ok((Instruction::LiteralNoCase("iF"), 4)));
+        test_parse!([Opcode::SetNoCase as u8, b'x', 0], Ok((Instruction::SetNoCase("x"), 3)));
+        test_parse!([Opcode::SetNoCase as u8, b'x'], Err(ParseError::MissingStringTerminator));
+        test_parse!([Opcode::Behind as u8, 3], Ok((Instruction::Behind(3), 2)));
+        test_parse!([Opcode::Behind as u8], Err(ParseError::MissingArgument));
+        test_parse!([Opcode::EndOfInput as u8], Ok((Instruction::EndOfInput, 1)));
+        test_parse!([Opcode::FailIfMoved as u8], Ok((Instruction::FailIfMoved, 1)));
+        test_parse!([Opcode::FailIfMoved as u8 + 1], Err(ParseError::InvalidOpcode));
         test_parse!([255], Err(ParseError::InvalidOpcode));
     }
 }
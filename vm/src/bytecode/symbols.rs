@@ -0,0 +1,50 @@
+use super::address::Address;
+
+/// Rule names by entry address, kept sorted by address.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SymbolTable(Vec<(Address, String)>);
+
+impl SymbolTable {
+    pub fn new() -> SymbolTable {
+        SymbolTable(Vec::new())
+    }
+
+    pub fn insert(&mut self, address: Address, name: &str) {
+        match self.0.binary_search_by_key(&address, |(addr, _)| *addr) {
+            Ok(i) => self.0[i].1 = name.to_string(),
+            Err(i) => self.0.insert(i, (address, name.to_string())),
+        }
+    }
+
+    pub fn name_at(&self, address: Address) -> Option<&str> {
+        self.0.binary_search_by_key(&address, |(addr, _)| *addr)
+            .ok()
+            .map(|i| self.0[i].1.as_str())
+    }
+
+    pub fn address_of(&self, name: &str) -> Option<Address> {
+        self.0.iter().find(|(_, n)| n == name).map(|(addr, _)| *addr)
+    }
+
+    /// Name of the rule whose body contains `address`
+    pub fn rule_containing(&self, address: Address) -> Option<&str> {
+        let index = match self.0.binary_search_by_key(&address, |(addr, _)| *addr) {
+            Ok(i) => i,
+            Err(0) => return None,
+            Err(i) => i - 1,
+        };
+        Some(self.0[index].1.as_str())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (Address, &str)> {
+        self.0.iter().map(|(addr, name)| (*addr, name.as_str()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
@@ -0,0 +1,197 @@
+use super::address::Address;
+use super::instruction::Instruction;
+use super::parser::{self, ParseError};
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+#[derive(Debug, PartialEq)]
+pub enum VerifyError {
+    Parse(Address, ParseError),
+    InvalidJumpTarget(Address, Address),
+    /// `Pop`, `Peek` or `Capture` without a backtrack entry to use
+    MissingBacktrackEntry(Address),
+    /// Control flow paths joining with different stack layouts
+    InconsistentStack(Address),
+    /// `Return` with backtrack entries still on the stack
+    UnbalancedReturn(Address),
+    /// `QuantifierNext` without a matching `QuantifierInit`
+    UnmatchedQuantifier(Address),
+}
+
+impl fmt::Display for VerifyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use VerifyError::*;
+        match self {
+            Parse(address, error) => write!(f, "{} at address {}", error, address),
+            InvalidJumpTarget(address, target) => write!(f, "jump at address {} targets {}, which is not an instruction", address, target),
+            MissingBacktrackEntry(address) => write!(f, "instruction at address {} has no backtrack entry", address),
+            InconsistentStack(address) => write!(f, "inconsistent backtrack stack at address {}", address),
+            UnbalancedReturn(address) => write!(f, "return at address {} leaves backtrack entries on the stack", address),
+            UnmatchedQuantifier(address) => write!(f, "quantifier step at address {} has no matching quantifier init", address),
+        }
+    }
+}
+
+// Backtrack stack entries, quantifiers hold their loop start address
+type Stack = Vec<Option<Address>>;
+
+struct Program<'a> {
+    instructions: BTreeMap<Address, (Instruction<'a>, Address)>,
+    end: Address,
+}
+
+impl<'a> Program<'a> {
+    fn decode(bytes: &'a [u8]) -> Result<Program<'a>, VerifyError> {
+        let mut instructions = BTreeMap::new();
+        let mut current = Address::zero();
+        while usize::from(current) < bytes.len() {
+            let address = current;
+            match parser::parse_instruction(&bytes[usize::from(current)..]) {
+                Ok((instruction, size)) => {
+                    current += size as u32;
+                    instructions.insert(address, (instruction, current));
+                },
+                Err(error) => return Err(VerifyError::Parse(address, error)),
+            }
+        }
+        Ok(Program { instructions, end: current })
+    }
+
+    fn check_target(&self, address: Address, target: Address) -> Result<(), VerifyError> {
+        if target == self.end || self.instructions.contains_key(&target) {
+            Ok(())
+        }
+        else {
+            Err(VerifyError::InvalidJumpTarget(address, target))
+        }
+    }
+
+    fn verify_rule(&self, entry: Address, rules: &mut BTreeSet<Address>) -> Result<(), VerifyError> {
+        let mut stacks: BTreeMap<Address, Stack> = BTreeMap::new();
+        let mut pending = vec![(entry, Stack::new())];
+        while let Some((address, stack)) = pending.pop() {
+            if let Some(known_stack) = stacks.get(&address) {
+                if *known_stack != stack {
+                    return Err(VerifyError::InconsistentStack(address));
+                }
+                continue;
+            }
+            stacks.insert(address, stack.clone());
+            let (instruction, next) = match self.instructions.get(&address) {
+                Some((instruction, next)) => (instruction, *next),
+                None => continue,
+            };
+            let mut stack = stack;
+            use Instruction::*;
+            match *instruction {
+                Jump(target) => {
+                    self.check_target(address, target)?;
+                    pending.push((target, stack));
+                    continue;
+                },
+                JumpIfFail(target) | JumpIfSuccess(target) | TestSet(target, _) => {
+                    self.check_target(address, target)?;
+                    pending.push((target, stack.clone()));
+                },
+                Call(target) => {
+                    self.check_target(address, target)?;
+                    rules.insert(target);
+                },
+                Return if !stack.is_empty() => return Err(VerifyError::UnbalancedReturn(address)),
+                Return => continue,
+                Halt(_) => continue,
+                Push => stack.push(None),
+                QuantifierInit => stack.push(Some(next)),
+                QuantifierNext => match stack.last() {
+                    Some(Some(loop_start)) => pending.push((*loop_start, stack.clone())),
+                    _ => return Err(VerifyError::UnmatchedQuantifier(address)),
+                },
+                Pop | Peek | Capture(_) | FailIfMoved if stack.is_empty() => {
+                    return Err(VerifyError::MissingBacktrackEntry(address));
+                },
+                Pop => {
+                    stack.pop();
+                },
+                _ => (),
+            }
+            pending.push((next, stack));
+        }
+        Ok(())
+    }
+}
+
+/// Checks that `bytes` decode to valid instructions, that every jump and
+/// call targets an instruction boundary, and that backtrack entries are
+/// balanced along every control flow path of every rule.
+pub fn verify(bytes: &[u8]) -> Result<(), VerifyError> {
+    let program = Program::decode(bytes)?;
+    let mut rules = BTreeSet::new();
+    let mut verified = BTreeSet::new();
+    rules.insert(Address::zero());
+    while let Some(&rule) = rules.iter().find(|rule| !verified.contains(*rule)) {
+        verified.insert(rule);
+        program.verify_rule(rule, &mut rules)?;
+    }
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bytecode::builder::Builder;
+    use Instruction::*;
+
+    macro_rules! test_verify {
+        ($instructions:expr, $result:expr) => {
+            let builder = Builder::with_instructions(&$instructions);
+            assert_eq!(verify(&builder.build()), $result)
+        }
+    }
+
+    #[test]
+    fn test_verify_valid() {
+        test_verify!([], Ok(()));
+        // Main <- ('a' / 'b') Digit*
+        // Digit <- [0-9]
+        test_verify!([
+            Push,
+            Char('a'),
+            JumpIfSuccess(Address::new(10)),
+            Char('b'),
+            JumpIfFail(Address::new(25)),
+            Call(Address::new(28)),
+            JumpIfSuccess(Address::new(15)),
+            Peek,
+            Pop,
+            Return,
+            Range(b'0', b'9'),
+            Return,
+        ], Ok(()));
+        // [0-9]+
+        test_verify!([QuantifierInit, Range(b'0', b'9'), QuantifierNext, FailIfLessThan(1), Pop], Ok(()));
+    }
+
+    #[test]
+    fn test_verify_errors() {
+        test_verify!([Char('a'), JumpIfSuccess(Address::new(1))], Err(VerifyError::InvalidJumpTarget(Address::new(2), Address::new(1))));
+        test_verify!([Jump(Address::new(7))], Err(VerifyError::InvalidJumpTarget(Address::zero(), Address::new(7))));
+        test_verify!([Call(Address::new(1))], Err(VerifyError::InvalidJumpTarget(Address::zero(), Address::new(1))));
+        test_verify!([Pop], Err(VerifyError::MissingBacktrackEntry(Address::zero())));
+        test_verify!([Peek], Err(VerifyError::MissingBacktrackEntry(Address::zero())));
+        test_verify!([Any, Capture(0)], Err(VerifyError::MissingBacktrackEntry(Address::new(1))));
+        test_verify!([Push, Return], Err(VerifyError::UnbalancedReturn(Address::new(1))));
+        test_verify!([Push, QuantifierNext], Err(VerifyError::UnmatchedQuantifier(Address::new(1))));
+        // Push only when 'a' fails
+        test_verify!([
+            Char('a'),
+            JumpIfSuccess(Address::new(8)),
+            Push,
+            Pop,
+        ], Err(VerifyError::InconsistentStack(Address::new(8))));
+        // Called rule pops its caller's state
+        test_verify!([Call(Address::new(6)), Return, Pop, Return], Err(VerifyError::MissingBacktrackEntry(Address::new(6))));
+        assert_eq!(verify(&[255]), Err(VerifyError::Parse(Address::zero(), ParseError::InvalidOpcode)));
+    }
+}
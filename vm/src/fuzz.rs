@@ -0,0 +1,177 @@
+use crate::bytecode::{Bytecode, OwnedBytecode};
+use crate::bytecode::builder::Builder;
+use crate::bytecode::instruction::InstructionIterator;
+use crate::bytecode::parser;
+use crate::bytecode::verifier::verify;
+use crate::matcher::try_match_traced;
+use crate::tracer::{Step, TraceControl, Tracer};
+
+/// Bound on executed instructions, as verified bytecode may still loop forever
+pub const MAX_STEPS: usize = 10_000;
+
+struct StepLimit(usize);
+
+impl Tracer for StepLimit {
+    fn trace(&mut self, _step: &Step) -> TraceControl {
+        self.0 += 1;
+        if self.0 > MAX_STEPS { TraceControl::Abort } else { TraceControl::Continue }
+    }
+}
+
+/// Parses one instruction, which must encode back to the bytes it was parsed from
+pub fn parse_instruction(data: &[u8]) {
+    if let Ok((instruction, size)) = parser::parse_instruction(data) {
+        let encoded = Builder::with_instructions(&[instruction]).build_owned();
+        assert_eq!(&*encoded.as_bytecode(), &data[..size]);
+    }
+}
+
+/// Checks, disassembles and verifies raw bytecode, and reads it as a
+/// bytecode file, which must write back to an equivalent file.
+pub fn bytecode(data: &[u8]) {
+    if let Ok(bytecode) = Bytecode::from_bytes(data) {
+        for instruction in InstructionIterator::new(&bytecode) {
+            let _ = instruction.to_string();
+        }
+    }
+    let _ = verify(data);
+
+    if let Ok(bytecode) = OwnedBytecode::read_from(&mut &data[..]) {
+        let mut written = Vec::new();
+        bytecode.write_to(&mut written).unwrap();
+        let read = OwnedBytecode::read_from(&mut &written[..]).unwrap();
+        assert_eq!(&*read.as_bytecode(), &*bytecode.as_bytecode());
+        assert_eq!(read.metadata(), bytecode.metadata());
+    }
+}
+
+/// Matches the text at the start of `data` with the bytecode after it, if
+/// verified. The first byte is the text length, and invalid UTF-8 in the
+/// text is replaced.
+pub fn match_bytecode(data: &[u8]) {
+    let (text_len, data) = match data.split_first() {
+        Some((len, data)) => (*len as usize % (data.len() + 1), data),
+        None => return,
+    };
+    let (text, bytes) = data.split_at(text_len);
+    let text = String::from_utf8_lossy(text);
+    if let Ok(bytecode) = Bytecode::from_bytes_verified(bytes) {
+        match try_match_traced(&bytecode, &text, &mut StepLimit(0)) {
+            Ok(end) => assert!(end <= text.len() && text.is_char_boundary(end)),
+            Err(error) => assert!(error.offset().is_none_or(|offset| offset <= text.len())),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bytecode::address::Address;
+    use crate::bytecode::charset::Charset;
+    use crate::bytecode::instruction::Instruction;
+    use crate::grammar::character_class::CharacterClass;
+
+    use std::fs;
+    use std::path::Path;
+
+    struct Rng(u64);
+
+    impl Rng {
+        fn below(&mut self, n: usize) -> usize {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            (self.0 % n as u64) as usize
+        }
+    }
+
+    fn replay_corpus(target: &str, run: fn(&[u8])) {
+        let corpus = Path::new(env!("CARGO_MANIFEST_DIR")).join("../fuzz/corpus").join(target);
+        let mut count = 0;
+        for entry in fs::read_dir(&corpus).unwrap() {
+            run(&fs::read(entry.unwrap().path()).unwrap());
+            count += 1;
+        }
+        assert!(count > 0, "empty corpus at {}", corpus.display());
+    }
+
+    #[test]
+    fn test_corpus() {
+        replay_corpus("parse_instruction", parse_instruction);
+        replay_corpus("bytecode", bytecode);
+        replay_corpus("match_bytecode", match_bytecode);
+    }
+
+    #[test]
+    fn test_random_bytes() {
+        let mut rng = Rng(0x2545_F491_4F6C_DD1D);
+        for _ in 0..20_000 {
+            let data: Vec<u8> = (0..rng.below(48)).map(|_| rng.below(256) as u8).collect();
+            parse_instruction(&data);
+            bytecode(&data);
+            match_bytecode(&data);
+        }
+    }
+
+    // Instructions with jumps to the start of random instructions, so most
+    // programs pass verification
+    fn random_program(rng: &mut Rng) -> Vec<u8> {
+        let count = 1 + rng.below(12);
+        let mut instructions: Vec<Instruction> = (0..count).map(|_| match rng.below(17) {
+            0 => Instruction::Byte(b"a\xce\x80"[rng.below(3)]),
+            1 => Instruction::Range(b'a', 0xce + rng.below(2) as u8),
+            2 => Instruction::Char(['a', 'α', 'b'][rng.below(3)]),
+            3 => Instruction::Any,
+            4 => Instruction::Push,
+            5 => Instruction::Peek,
+            6 => Instruction::Pop,
+            7 => Instruction::QuantifierInit,
+            8 => Instruction::QuantifierNext,
+            9 => Instruction::FailIfLessThan(rng.below(3) as u8),
+            10 => Instruction::Capture(rng.below(2) as u8),
+            11 => Instruction::Behind(rng.below(3) as u8),
+            12 => Instruction::Class(CharacterClass::Alphabetic),
+            13 => Instruction::Charset(Charset::from_bytes([0x55; 32])),
+            14 => Instruction::EndOfInput,
+            15 => Instruction::FailIfMoved,
+            _ => Instruction::Jump(Address::new(rng.below(count) as u32)),
+        }).collect();
+        // jump operands above are instruction indexes, turned into addresses here
+        let mut addresses = Vec::new();
+        let mut address = 0;
+        for instruction in instructions.iter() {
+            addresses.push(address);
+            address += Builder::with_instructions(std::slice::from_ref(instruction)).build_owned().as_bytecode().len() as u32;
+        }
+        for instruction in instructions.iter_mut() {
+            if let Instruction::Jump(target) = instruction {
+                let index: usize = (*target).into();
+                *instruction = match rng.below(3) {
+                    0 => Instruction::Jump(Address::new(addresses[index])),
+                    1 => Instruction::JumpIfFail(Address::new(addresses[index])),
+                    _ => Instruction::Call(Address::new(addresses[index])),
+                };
+            }
+        }
+        Builder::with_instructions(&instructions).build_owned().as_bytecode().to_vec()
+    }
+
+    #[test]
+    fn test_random_programs() {
+        let mut rng = Rng(0x9E37_79B9_7F4A_7C15);
+        let texts = ["", "a", "α", "aαb", "ab\u{80}", "αααα"];
+        let mut verified = 0;
+        for _ in 0..5_000 {
+            let program = random_program(&mut rng);
+            verified += verify(&program).is_ok() as usize;
+            for text in texts.iter() {
+                let mut data = vec![text.len() as u8];
+                data.extend_from_slice(text.as_bytes());
+                data.extend_from_slice(&program);
+                match_bytecode(&data);
+            }
+        }
+        assert!(verified > 500, "only {} programs verified", verified);
+    }
+}
@@ -1,3 +1,5 @@
 pub mod character_class;
 pub mod expression;
+pub mod unicode;
+mod unicode_tables;
 
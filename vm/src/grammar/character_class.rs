@@ -1,4 +1,4 @@
-#[derive(Debug, Copy, Clone, PartialEq)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
 #[repr(u8)]
 pub enum CharacterClass {
     Alphabetic = b'a',
@@ -11,6 +11,16 @@ pub enum CharacterClass {
     Whitespace = b's',
     Uppercase = b'u',
     Hexadigit = b'x',
+    NotAlphabetic = b'A',
+    NotAlphanumeric = b'W',
+    NotControl = b'C',
+    NotDigit = b'D',
+    NotGraphic = b'G',
+    NotLowercase = b'L',
+    NotPunctuation = b'P',
+    NotWhitespace = b'S',
+    NotUppercase = b'U',
+    NotHexadigit = b'X',
 }
 
 impl CharacterClass {
@@ -20,15 +30,55 @@ impl CharacterClass {
             Alphabetic => c.is_alphabetic(),
             Alphanumeric => c.is_alphanumeric(),
             Control => c.is_control(),
-            Digit => c.is_digit(10),
+            Digit => c.is_ascii_digit(),
             Graphic => c.is_ascii_graphic(),
             Lowercase => c.is_lowercase(),
             Punctuation => c.is_ascii_punctuation(),
             Whitespace => c.is_whitespace(),
             Uppercase => c.is_uppercase(),
-            Hexadigit => c.is_digit(16),
+            Hexadigit => c.is_ascii_hexdigit(),
+            _ => !self.negated().is_member(c),
         }
     }
+
+    pub fn is_negated(&self) -> bool {
+        (*self as u8).is_ascii_uppercase()
+    }
+
+    /// Class with the chars not in this one, `\d` for `\D` and vice versa
+    pub fn negated(&self) -> CharacterClass {
+        use CharacterClass::*;
+        match *self {
+            Alphabetic => NotAlphabetic,
+            NotAlphabetic => Alphabetic,
+            Alphanumeric => NotAlphanumeric,
+            NotAlphanumeric => Alphanumeric,
+            Control => NotControl,
+            NotControl => Control,
+            Digit => NotDigit,
+            NotDigit => Digit,
+            Graphic => NotGraphic,
+            NotGraphic => Graphic,
+            Lowercase => NotLowercase,
+            NotLowercase => Lowercase,
+            Punctuation => NotPunctuation,
+            NotPunctuation => Punctuation,
+            Whitespace => NotWhitespace,
+            NotWhitespace => Whitespace,
+            Uppercase => NotUppercase,
+            NotUppercase => Uppercase,
+            Hexadigit => NotHexadigit,
+            NotHexadigit => Hexadigit,
+        }
+    }
+}
+
+impl std::ops::Not for CharacterClass {
+    type Output = CharacterClass;
+
+    fn not(self) -> CharacterClass {
+        self.negated()
+    }
 }
 
 use std::convert::TryFrom;
@@ -51,8 +101,37 @@ impl TryFrom<u8> for CharacterClass {
             b's' => Ok(Whitespace),
             b'u' => Ok(Uppercase),
             b'x' => Ok(Hexadigit),
+            b'A' => Ok(NotAlphabetic),
+            b'W' => Ok(NotAlphanumeric),
+            b'C' => Ok(NotControl),
+            b'D' => Ok(NotDigit),
+            b'G' => Ok(NotGraphic),
+            b'L' => Ok(NotLowercase),
+            b'P' => Ok(NotPunctuation),
+            b'S' => Ok(NotWhitespace),
+            b'U' => Ok(NotUppercase),
+            b'X' => Ok(NotHexadigit),
             _ => Err(TryFromU8),
         }
     }
 }
 
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use CharacterClass::*;
+
+    #[test]
+    fn test_negated() {
+        assert_eq!(Digit.negated(), NotDigit);
+        assert_eq!(NotWhitespace.negated(), Whitespace);
+        assert_eq!(!Alphanumeric, NotAlphanumeric);
+        assert!(NotDigit.is_negated() && !Digit.is_negated());
+        assert!(NotDigit.is_member('a') && NotDigit.is_member('α') && !NotDigit.is_member('5'));
+        assert!(NotAlphanumeric.is_member('-') && !NotAlphanumeric.is_member('ж'));
+        assert!(matches!(CharacterClass::try_from(b'W'), Ok(NotAlphanumeric)));
+        assert!(CharacterClass::try_from(b'Z').is_err());
+    }
+}
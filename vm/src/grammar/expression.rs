@@ -1,25 +1,335 @@
 use super::character_class::CharacterClass;
+use super::unicode::UnicodeClass;
 
+use std::collections::BTreeSet;
+use std::fmt;
+use std::mem;
 use std::ops::{Add, BitXor, Div, Neg, Not, Shr};
+use std::slice;
 
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub enum Expression {
     Char(char),
     Literal(String),
+    /// Case-insensitive variants, comparing chars under simple case folding
+    CharNoCase(char),
+    LiteralNoCase(String),
+    SetNoCase(String),
     Class(CharacterClass),
+    UnicodeClass(UnicodeClass),
+    /// Class declared with `Compiler::define_class`
+    NamedClass(String),
     Set(String),
     InverseSet(String),
     Range(char, char),
     Any,
+    EndOfInput,
     NonTerminal(String),
     Quantifier(Box<Expression>, i32),
     And(Box<Expression>),
     Not(Box<Expression>),
+    /// Lookbehind `<&e`, with `e` of bounded length
+    Behind(Box<Expression>),
+    /// Negative lookbehind `<!e`, with `e` of bounded length
+    NotBehind(Box<Expression>),
     Sequence(Vec<Expression>),
     Choice(Vec<Expression>),
     Capture(Box<Expression>, String),
     //Error(i32, Expression),
 }
 
+impl Expression {
+    /// Direct sub-expressions, in order
+    pub fn children(&self) -> &[Expression] {
+        use Expression::*;
+        match self {
+            Quantifier(e, _) | And(e) | Not(e) | Behind(e) | NotBehind(e) | Capture(e, _) => slice::from_ref(e),
+            Sequence(exprs) | Choice(exprs) => exprs,
+            _ => &[],
+        }
+    }
+
+    pub fn children_mut(&mut self) -> &mut [Expression] {
+        use Expression::*;
+        match self {
+            Quantifier(e, _) | And(e) | Not(e) | Behind(e) | NotBehind(e) | Capture(e, _) => slice::from_mut(e),
+            Sequence(exprs) | Choice(exprs) => exprs,
+            _ => &mut [],
+        }
+    }
+
+    /// The expression and its sub-expressions, in pre-order
+    pub fn sub_expressions(&self) -> SubExpressions<'_> {
+        SubExpressions { stack: vec![self] }
+    }
+
+    /// Visits the expression and its sub-expressions in pre-order, skipping
+    /// the sub-expressions of those `visit` returns false for
+    pub fn walk<F: FnMut(&Expression) -> bool>(&self, visit: &mut F) {
+        if visit(self) {
+            for child in self.children() {
+                child.walk(visit);
+            }
+        }
+    }
+
+    /// Rebuilds the expression bottom-up, replacing each sub-expression by
+    /// `f` of it once its own sub-expressions are replaced
+    pub fn map<F: FnMut(Expression) -> Expression>(mut self, f: &mut F) -> Expression {
+        for child in self.children_mut() {
+            *child = mem::replace(child, Expression::Any).map(f);
+        }
+        f(self)
+    }
+
+    /// Names of the rules the expression calls
+    pub fn referenced_nonterminals(&self) -> BTreeSet<&str> {
+        self.sub_expressions()
+            .filter_map(|e| match e {
+                Expression::NonTerminal(name) => Some(name.as_str()),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+pub struct SubExpressions<'a> {
+    stack: Vec<&'a Expression>,
+}
+
+impl<'a> Iterator for SubExpressions<'a> {
+    type Item = &'a Expression;
+
+    fn next(&mut self) -> Option<&'a Expression> {
+        let expr = self.stack.pop()?;
+        self.stack.extend(expr.children().iter().rev());
+        Some(expr)
+    }
+}
+
+// Precedence levels of the grammar syntax, from loosest to tightest
+const CHOICE: u8 = 0;
+const SEQUENCE: u8 = 1;
+const PREFIX: u8 = 2;
+const SUFFIX: u8 = 3;
+const PRIMARY: u8 = 4;
+
+impl Expression {
+    /// Displays the expression as the body of `rule`, with the captures named
+    /// after it in the `{e}` shorthand
+    pub fn display_in_rule<'a>(&'a self, rule: &'a str) -> impl fmt::Display + 'a {
+        Printer { expr: self, rule: Some(rule) }
+    }
+
+    fn precedence(&self) -> u8 {
+        use Expression::*;
+        match self {
+            Sequence(exprs) | Choice(exprs) if exprs.len() == 1 => exprs[0].precedence(),
+            // the empty forms print as `''` and `!''`
+            Sequence(exprs) if exprs.is_empty() => PRIMARY,
+            Choice(exprs) if exprs.is_empty() => PREFIX,
+            Set(s) | SetNoCase(s) if s.is_empty() => PREFIX,
+            Choice(exprs) if is_set_choice(exprs) => PRIMARY,
+            Sequence(exprs) if negated_set_items(exprs).is_some() => PRIMARY,
+            Choice(_) => CHOICE,
+            Sequence(_) => SEQUENCE,
+            And(_) | Not(_) | Behind(_) | NotBehind(_) | EndOfInput => PREFIX,
+            Quantifier(..) => SUFFIX,
+            _ => PRIMARY,
+        }
+    }
+}
+
+/// Grammar syntax of `compiler/src/syntax.rs`, with the least parentheses.
+/// Parsing it back gives an equivalent expression, though not always the
+/// same one: nested sequences and choices are flattened, one-char literals
+/// become chars, runs of chars in a set join and `!.` is the end of input.
+impl fmt::Display for Expression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        Printer { expr: self, rule: None }.fmt(f)
+    }
+}
+
+struct Printer<'a> {
+    expr: &'a Expression,
+    /// Rule the expression belongs to, for unnamed captures
+    rule: Option<&'a str>,
+}
+
+impl fmt::Display for Printer<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.write(f, self.expr, CHOICE)
+    }
+}
+
+fn write_char(f: &mut fmt::Formatter<'_>, c: char, special: &str) -> fmt::Result {
+    match c {
+        '\n' => write!(f, "\\n"),
+        '\r' => write!(f, "\\r"),
+        '\t' => write!(f, "\\t"),
+        '\0' => write!(f, "\\0"),
+        c if c == '\\' || special.contains(c) => write!(f, "\\{}", c),
+        c if c.is_control() => write!(f, "\\u{{{:x}}}", c as u32),
+        c => write!(f, "{}", c),
+    }
+}
+
+fn write_literal(f: &mut fmt::Formatter<'_>, s: &str, no_case: bool) -> fmt::Result {
+    write!(f, "'")?;
+    for c in s.chars() {
+        write_char(f, c, "'")?;
+    }
+    write!(f, "'{}", if no_case { "i" } else { "" })
+}
+
+// Chars of a set, `first` and `last` when they start or end it
+fn write_set_chars(f: &mut fmt::Formatter<'_>, negated: bool, chars: &str, first: bool, last: bool) -> fmt::Result {
+    let count = chars.chars().count();
+    for (i, c) in chars.chars().enumerate() {
+        let (starts, ends) = (first && i == 0, last && i + 1 == count);
+        // `-` between chars makes a range, `^` first negates the set
+        let special = match c {
+            '-' if !starts && !ends => "-",
+            '^' if starts && !negated => "^",
+            _ => "]",
+        };
+        write_char(f, c, special)?;
+    }
+    Ok(())
+}
+
+fn write_set(f: &mut fmt::Formatter<'_>, negated: bool, chars: &str, no_case: bool) -> fmt::Result {
+    write!(f, "[{}", if negated { "^" } else { "" })?;
+    write_set_chars(f, negated, chars, true, true)?;
+    write!(f, "]{}", if no_case { "i" } else { "" })
+}
+
+// The syntax turns sets with ranges or classes into a choice of their
+// items, in order, and negated ones into `!(items) .`, so those print as sets
+fn is_set_item(expr: &Expression) -> bool {
+    use Expression::*;
+    match expr {
+        Set(s) => !s.is_empty(),
+        Char(_) | Range(..) | Class(_) | UnicodeClass(_) => true,
+        _ => false,
+    }
+}
+
+fn is_set_choice(exprs: &[Expression]) -> bool {
+    use Expression::*;
+    exprs.len() > 1 && exprs.iter().all(is_set_item)
+        && exprs.iter().any(|e| matches!(e, Range(..) | Class(_) | UnicodeClass(_)))
+}
+
+fn negated_set_items(exprs: &[Expression]) -> Option<&[Expression]> {
+    use Expression::*;
+    match exprs {
+        [Not(e), Any] => match e.as_ref() {
+            Choice(items) if is_set_choice(items) => Some(items),
+            item @ (Range(..) | Class(_) | UnicodeClass(_)) => Some(slice::from_ref(item)),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn write_set_items(f: &mut fmt::Formatter<'_>, negated: bool, items: &[Expression]) -> fmt::Result {
+    use Expression::*;
+    write!(f, "[{}", if negated { "^" } else { "" })?;
+    for (i, item) in items.iter().enumerate() {
+        let (first, last) = (i == 0, i + 1 == items.len());
+        match item {
+            Char(c) => write_set_chars(f, negated, &c.to_string(), first, last)?,
+            Set(s) => write_set_chars(f, negated, s, first, last)?,
+            Range(min, max) => {
+                write_char(f, *min, "]-^")?;
+                write!(f, "-")?;
+                write_char(f, *max, "]-^")?;
+            },
+            Class(class) => write!(f, "\\{}", *class as u8 as char)?,
+            UnicodeClass(class) => write!(f, "{}", class)?,
+            _ => (),
+        }
+    }
+    write!(f, "]")
+}
+
+impl Printer<'_> {
+    /// Writes `expr` where the syntax expects `precedence` or tighter
+    fn write(&self, f: &mut fmt::Formatter<'_>, expr: &Expression, precedence: u8) -> fmt::Result {
+        use Expression::*;
+        if expr.precedence() < precedence {
+            write!(f, "(")?;
+            self.write(f, expr, CHOICE)?;
+            return write!(f, ")");
+        }
+        match expr {
+            Char(c) => write_literal(f, &c.to_string(), false),
+            Literal(s) => write_literal(f, s, false),
+            CharNoCase(c) => write_literal(f, &c.to_string(), true),
+            LiteralNoCase(s) => write_literal(f, s, true),
+            Set(s) | SetNoCase(s) if s.is_empty() => write!(f, "!''"),
+            SetNoCase(s) => write_set(f, false, s, true),
+            Set(s) => write_set(f, false, s, false),
+            InverseSet(s) if s.is_empty() => write!(f, "."),
+            InverseSet(s) => write_set(f, true, s, false),
+            Range(..) => write_set_items(f, false, slice::from_ref(expr)),
+            Class(class) => write!(f, "\\{}", *class as u8 as char),
+            UnicodeClass(class) => write!(f, "{}", class),
+            NamedClass(name) => write!(f, "@{}", name),
+            Any => write!(f, "."),
+            EndOfInput => write!(f, "!."),
+            NonTerminal(name) => write!(f, "{}", name),
+            Quantifier(e, count) => {
+                self.write(f, e, SUFFIX)?;
+                match count {
+                    0 => write!(f, "*"),
+                    1 => write!(f, "+"),
+                    -1 => write!(f, "?"),
+                    n => write!(f, "^{}", n),
+                }
+            },
+            And(e) => self.write_prefix(f, "&", e),
+            Not(e) => self.write_prefix(f, "!", e),
+            Behind(e) => self.write_prefix(f, "<&", e),
+            NotBehind(e) => self.write_prefix(f, "<!", e),
+            Sequence(exprs) if exprs.is_empty() => write!(f, "''"),
+            Choice(exprs) if exprs.is_empty() => write!(f, "!''"),
+            Sequence(exprs) => match negated_set_items(exprs) {
+                Some(items) => write_set_items(f, true, items),
+                None => self.write_list(f, exprs, " ", SEQUENCE),
+            },
+            Choice(exprs) if is_set_choice(exprs) => write_set_items(f, false, exprs),
+            Choice(exprs) => self.write_list(f, exprs, " / ", CHOICE),
+            Capture(e, name) if self.rule == Some(name.as_str()) => {
+                write!(f, "{{")?;
+                self.write(f, e, CHOICE)?;
+                write!(f, "}}")
+            },
+            Capture(e, name) => {
+                write!(f, "{{{}: ", name)?;
+                self.write(f, e, CHOICE)?;
+                write!(f, "}}")
+            },
+        }
+    }
+
+    fn write_prefix(&self, f: &mut fmt::Formatter<'_>, operator: &str, e: &Expression) -> fmt::Result {
+        write!(f, "{}", operator)?;
+        self.write(f, e, SUFFIX)
+    }
+
+    fn write_list(&self, f: &mut fmt::Formatter<'_>, exprs: &[Expression], separator: &str, precedence: u8) -> fmt::Result {
+        for (i, e) in exprs.iter().enumerate() {
+            if i > 0 {
+                write!(f, "{}", separator)?;
+            }
+            self.write(f, e, precedence)?;
+        }
+        Ok(())
+    }
+}
+
 impl Add for Expression {
     type Output = Self;
 
@@ -86,3 +396,121 @@ impl Shr<&str> for Expression {
     }
 }
 
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::Expression::*;
+
+    fn nt(name: &str) -> Expression {
+        NonTerminal(name.to_string())
+    }
+
+    #[test]
+    fn test_display() {
+        assert_eq!(((nt("a") + nt("b")) / nt("c")).to_string(), "a b / c");
+        assert_eq!((nt("a") + (nt("b") / nt("c"))).to_string(), "a (b / c)");
+        assert_eq!(((nt("a") + nt("b")) ^ 0).to_string(), "(a b)*");
+        assert_eq!(((nt("a") ^ 1) ^ -1).to_string(), "a+?");
+        assert_eq!((-(nt("a") ^ 3)).to_string(), "&a^3");
+        assert_eq!(((-nt("a")) ^ -2).to_string(), "(&a)^-2");
+        assert_eq!((!!nt("a")).to_string(), "a");
+        assert_eq!(Not(Box::new(Not(Box::new(nt("a"))))).to_string(), "!(!a)");
+        assert_eq!(Behind(Box::new(Choice(vec![Char('a'), Any]))).to_string(), "<&('a' / .)");
+        assert_eq!((nt("a") + EndOfInput).to_string(), "a !.");
+        assert_eq!(Sequence(vec![nt("a"), Sequence(vec![nt("b"), nt("c")])]).to_string(), "a b c");
+        assert_eq!(Sequence(vec![Choice(vec![nt("a")])]).to_string(), "a");
+        assert_eq!((Sequence(vec![]) / Choice(vec![])).to_string(), "'' / !''");
+        assert_eq!(((nt("a") / nt("b")) >> "x").to_string(), "{x: a / b}");
+        assert_eq!(((nt("a") >> "S") + (nt("b") >> "x")).display_in_rule("S").to_string(), "{a} {x: b}");
+    }
+
+    #[test]
+    fn test_derives() {
+        use std::collections::HashSet;
+        let expr = (nt("a") + Class(CharacterClass::Digit)) ^ 1;
+        assert_eq!(expr.clone(), expr);
+        assert_ne!(expr, (nt("a") + Class(CharacterClass::Digit)) ^ 0);
+        assert_eq!(format!("{:?}", Char('a') >> "x"), "Capture(Char('a'), \"x\")");
+        let set: HashSet<Expression> = vec![nt("a"), nt("a"), Any].into_iter().collect();
+        assert_eq!(set.len(), 2);
+    }
+
+    #[test]
+    fn test_traversal() {
+        let expr = (nt("a") + !(nt("b") / Any)) >> "x";
+        let visited: Vec<String> = expr.sub_expressions().map(|e| e.to_string()).collect();
+        assert_eq!(visited, vec!["{x: a !(b / .)}", "a !(b / .)", "a", "!(b / .)", "b / .", "b", "."]);
+        assert_eq!(expr.children(), &[nt("a") + !(nt("b") / Any)]);
+        assert!(Any.children().is_empty());
+
+        // lookaheads are skipped
+        let mut names = Vec::new();
+        expr.walk(&mut |e| match e {
+            NonTerminal(name) => {
+                names.push(name.clone());
+                true
+            },
+            Not(_) => false,
+            _ => true,
+        });
+        assert_eq!(names, vec!["a"]);
+        assert_eq!(expr.referenced_nonterminals().into_iter().collect::<Vec<_>>(), vec!["a", "b"]);
+        assert_eq!((nt("b") + nt("a") + nt("b")).referenced_nonterminals().len(), 2);
+    }
+
+    #[test]
+    fn test_map() {
+        let expr = (nt("a") + Any) / (nt("a") ^ 0);
+        let renamed = expr.clone().map(&mut |e| match e {
+            NonTerminal(name) if name == "a" => nt("c"),
+            e => e,
+        });
+        assert_eq!(renamed, (nt("c") + Any) / (nt("c") ^ 0));
+
+        // sub-expressions are replaced first, so flattening sees flat children
+        let nested = Sequence(vec![nt("a"), Sequence(vec![nt("b"), Sequence(vec![nt("c")])])]);
+        let flattened = nested.map(&mut |e| match e {
+            Sequence(exprs) => Sequence(exprs.into_iter().flat_map(|e| match e {
+                Sequence(inner) => inner,
+                e => vec![e],
+            }).collect()),
+            e => e,
+        });
+        assert_eq!(flattened, Sequence(vec![nt("a"), nt("b"), nt("c")]));
+    }
+
+    #[test]
+    fn test_display_terminals() {
+        assert_eq!(Literal("it's\n\\".to_string()).to_string(), r"'it\'s\n\\'");
+        assert_eq!(Char('\u{7f}').to_string(), r"'\u{7f}'");
+        assert_eq!(LiteralNoCase("ab".to_string()).to_string(), "'ab'i");
+        assert_eq!(CharNoCase('ß').to_string(), "'ß'i");
+        assert_eq!(Set("a]^-\\".to_string()).to_string(), r"[a\]^\-\\]");
+        assert_eq!(Set("^a-".to_string()).to_string(), r"[\^a-]");
+        assert_eq!(InverseSet("^-".to_string()).to_string(), r"[^^-]");
+        assert_eq!(SetNoCase("ab".to_string()).to_string(), "[ab]i");
+        assert_eq!(InverseSet("\t ".to_string()).to_string(), r"[^\t ]");
+        assert_eq!(Range('a', 'z').to_string(), "[a-z]");
+        assert_eq!(Range('-', '-').to_string(), r"[\--\-]");
+        assert_eq!(Class(CharacterClass::NotDigit).to_string(), r"\D");
+        assert_eq!(NamedClass("Ident".to_string()).to_string(), "@Ident");
+        assert_eq!(Set(String::new()).to_string(), "!''");
+        assert_eq!(InverseSet(String::new()).to_string(), ".");
+    }
+
+    #[test]
+    fn test_display_set_choices() {
+        let digit = || Class(CharacterClass::Digit);
+        assert_eq!((Range('a', 'z') / Char('_') / digit()).to_string(), r"[a-z_\d]");
+        assert_eq!(((Set("-x".to_string()) / Range('0', '9') / Set("y-".to_string()))^0).to_string(), r"[-x0-9y-]*");
+        assert_eq!((Char('a') / Set("-^".to_string()) / Range('^', 'z')).to_string(), r"[a\-^\^-z]");
+        assert_eq!((!(Range('0', '9') / Char(']')) + Any).to_string(), r"[^0-9\]]");
+        assert_eq!((!digit() + Any).to_string(), r"[^\d]");
+        // choices of chars alone and other negations keep their own syntax
+        assert_eq!((Char('+') / Char('-')).to_string(), "'+' / '-'");
+        assert_eq!((!Char('a') + Any).to_string(), "!'a' .");
+        assert_eq!((Range('a', 'z') / nt("a")).to_string(), "[a-z] / a");
+    }
+}
@@ -0,0 +1,304 @@
+pub use super::unicode_tables::{Script, UNICODE_VERSION};
+use super::unicode_tables::{CASE_FOLDING_TABLE, GENERAL_CATEGORY_TABLE, SCRIPT_NAMES, SCRIPTS, SCRIPT_TABLE};
+
+use std::fmt;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum GeneralCategory {
+    Lu, Ll, Lt, Lm, Lo,
+    Mn, Mc, Me,
+    Nd, Nl, No,
+    Pc, Pd, Ps, Pe, Pi, Pf, Po,
+    Sm, Sc, Sk, So,
+    Zs, Zl, Zp,
+    Cc, Cf, Cs, Co, Cn,
+}
+
+use GeneralCategory::*;
+
+const CATEGORIES: [(GeneralCategory, &str, &str); 30] = [
+    (Lu, "Lu", "Uppercase_Letter"),
+    (Ll, "Ll", "Lowercase_Letter"),
+    (Lt, "Lt", "Titlecase_Letter"),
+    (Lm, "Lm", "Modifier_Letter"),
+    (Lo, "Lo", "Other_Letter"),
+    (Mn, "Mn", "Nonspacing_Mark"),
+    (Mc, "Mc", "Spacing_Mark"),
+    (Me, "Me", "Enclosing_Mark"),
+    (Nd, "Nd", "Decimal_Number"),
+    (Nl, "Nl", "Letter_Number"),
+    (No, "No", "Other_Number"),
+    (Pc, "Pc", "Connector_Punctuation"),
+    (Pd, "Pd", "Dash_Punctuation"),
+    (Ps, "Ps", "Open_Punctuation"),
+    (Pe, "Pe", "Close_Punctuation"),
+    (Pi, "Pi", "Initial_Punctuation"),
+    (Pf, "Pf", "Final_Punctuation"),
+    (Po, "Po", "Other_Punctuation"),
+    (Sm, "Sm", "Math_Symbol"),
+    (Sc, "Sc", "Currency_Symbol"),
+    (Sk, "Sk", "Modifier_Symbol"),
+    (So, "So", "Other_Symbol"),
+    (Zs, "Zs", "Space_Separator"),
+    (Zl, "Zl", "Line_Separator"),
+    (Zp, "Zp", "Paragraph_Separator"),
+    (Cc, "Cc", "Control"),
+    (Cf, "Cf", "Format"),
+    (Cs, "Cs", "Surrogate"),
+    (Co, "Co", "Private_Use"),
+    (Cn, "Cn", "Unassigned"),
+];
+
+const CATEGORY_GROUPS: [(&str, &str, &[GeneralCategory]); 8] = [
+    ("L", "Letter", &[Lu, Ll, Lt, Lm, Lo]),
+    ("LC", "Cased_Letter", &[Lu, Ll, Lt]),
+    ("M", "Mark", &[Mn, Mc, Me]),
+    ("N", "Number", &[Nd, Nl, No]),
+    ("P", "Punctuation", &[Pc, Pd, Ps, Pe, Pi, Pf, Po]),
+    ("S", "Symbol", &[Sm, Sc, Sk, So]),
+    ("Z", "Separator", &[Zs, Zl, Zp]),
+    ("C", "Other", &[Cc, Cf, Cs, Co, Cn]),
+];
+
+fn lookup<T: Copy>(table: &[(u32, u32, T)], c: char) -> Option<T> {
+    let c = c as u32;
+    table.binary_search_by(|&(start, end, _)| {
+        if end < c {
+            std::cmp::Ordering::Less
+        }
+        else if start > c {
+            std::cmp::Ordering::Greater
+        }
+        else {
+            std::cmp::Ordering::Equal
+        }
+    }).ok().map(|index| table[index].2)
+}
+
+pub fn general_category(c: char) -> GeneralCategory {
+    lookup(GENERAL_CATEGORY_TABLE, c).unwrap_or(Cn)
+}
+
+pub fn script(c: char) -> Script {
+    lookup(SCRIPT_TABLE, c).unwrap_or(Script::Unknown)
+}
+
+/// Simple case folding: every case variant of a char folds to the same char
+pub fn simple_fold(c: char) -> char {
+    CASE_FOLDING_TABLE.binary_search_by_key(&(c as u32), |&(code, _)| code)
+        .ok()
+        .and_then(|index| char::from_u32(CASE_FOLDING_TABLE[index].1))
+        .unwrap_or(c)
+}
+
+/// Chars that fold to the same char as `c`, `c` included, in order
+pub fn case_variants(c: char) -> Vec<char> {
+    let folded = simple_fold(c);
+    let mut variants: Vec<char> = CASE_FOLDING_TABLE.iter()
+        .filter(|&&(_, fold)| fold == folded as u32)
+        .filter_map(|&(code, _)| char::from_u32(code))
+        .chain(std::iter::once(folded))
+        .collect();
+    variants.sort_unstable();
+    variants
+}
+
+impl Script {
+    pub fn name(&self) -> &'static str {
+        SCRIPT_NAMES[*self as usize].0
+    }
+
+    pub fn short_name(&self) -> &'static str {
+        SCRIPT_NAMES[*self as usize].1
+    }
+}
+
+/// Set of general categories, one bit per category
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct CategorySet(u32);
+
+impl CategorySet {
+    pub fn new() -> CategorySet {
+        CategorySet::default()
+    }
+
+    pub fn from_bits(bits: u32) -> Option<CategorySet> {
+        if bits >> CATEGORIES.len() == 0 { Some(CategorySet(bits)) } else { None }
+    }
+
+    pub fn bits(&self) -> u32 {
+        self.0
+    }
+
+    pub fn insert(&mut self, category: GeneralCategory) {
+        self.0 |= 1 << category as u32;
+    }
+
+    pub fn contains(&self, category: GeneralCategory) -> bool {
+        self.0 & (1 << category as u32) != 0
+    }
+}
+
+impl From<&[GeneralCategory]> for CategorySet {
+    fn from(categories: &[GeneralCategory]) -> CategorySet {
+        let mut set = CategorySet::new();
+        categories.iter().for_each(|category| set.insert(*category));
+        set
+    }
+}
+
+/// Class of chars defined by a Unicode property, written `\p{Name}`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum UnicodeClass {
+    Category(CategorySet),
+    Script(Script),
+}
+
+// Loose matching of property names, as in UAX #44: case, spaces,
+// underscores and hyphens are ignored
+fn loose_eq(a: &str, b: &str) -> bool {
+    let normalize = |s: &str| -> Vec<char> {
+        s.chars()
+            .filter(|c| !matches!(c, ' ' | '_' | '-'))
+            .map(|c| c.to_ascii_lowercase())
+            .collect()
+    };
+    normalize(a) == normalize(b)
+}
+
+const KIND_CATEGORY: u8 = 0;
+const KIND_SCRIPT: u8 = 1;
+
+impl UnicodeClass {
+    /// Parses the name inside `\p{...}`: a general category or category
+    /// group like `Lu`, `L` or `Letter`, a script like `Greek` or `Grek`,
+    /// optionally qualified as `gc=Lu` or `Script=Greek`.
+    pub fn from_name(name: &str) -> Option<UnicodeClass> {
+        match name.split_once('=') {
+            Some((property, value)) if loose_eq(property, "gc") || loose_eq(property, "General_Category") => {
+                UnicodeClass::category_from_name(value)
+            },
+            Some((property, value)) if loose_eq(property, "sc") || loose_eq(property, "Script") => {
+                UnicodeClass::script_from_name(value)
+            },
+            Some(_) => None,
+            None => UnicodeClass::category_from_name(name).or_else(|| UnicodeClass::script_from_name(name)),
+        }
+    }
+
+    fn category_from_name(name: &str) -> Option<UnicodeClass> {
+        let categories = CATEGORIES.iter()
+            .find(|(_, short, long)| loose_eq(name, short) || loose_eq(name, long))
+            .map(|(category, _, _)| CategorySet::from(&[*category][..]))
+            .or_else(|| CATEGORY_GROUPS.iter()
+                .find(|(short, long, _)| loose_eq(name, short) || loose_eq(name, long))
+                .map(|(_, _, categories)| CategorySet::from(*categories)))?;
+        Some(UnicodeClass::Category(categories))
+    }
+
+    fn script_from_name(name: &str) -> Option<UnicodeClass> {
+        SCRIPT_NAMES.iter()
+            .position(|(long, short)| loose_eq(name, long) || loose_eq(name, short))
+            .map(|index| UnicodeClass::Script(SCRIPTS[index]))
+    }
+
+    pub fn is_member(&self, c: char) -> bool {
+        match self {
+            UnicodeClass::Category(categories) => categories.contains(general_category(c)),
+            UnicodeClass::Script(s) => script(c) == *s,
+        }
+    }
+
+    pub fn to_bytes(&self) -> [u8; 5] {
+        let (kind, value) = match self {
+            UnicodeClass::Category(categories) => (KIND_CATEGORY, categories.bits()),
+            UnicodeClass::Script(script) => (KIND_SCRIPT, *script as u32),
+        };
+        let value = value.to_le_bytes();
+        [kind, value[0], value[1], value[2], value[3]]
+    }
+
+    pub fn from_bytes(bytes: [u8; 5]) -> Option<UnicodeClass> {
+        let value = u32::from_le_bytes([bytes[1], bytes[2], bytes[3], bytes[4]]);
+        match bytes[0] {
+            KIND_CATEGORY => CategorySet::from_bits(value).map(UnicodeClass::Category),
+            KIND_SCRIPT => SCRIPTS.get(value as usize).map(|script| UnicodeClass::Script(*script)),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for UnicodeClass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UnicodeClass::Script(script) => write!(f, "\\p{{{}}}", script.name()),
+            UnicodeClass::Category(categories) => {
+                let name = CATEGORIES.iter()
+                    .map(|(category, short, _)| (CategorySet::from(&[*category][..]), short))
+                    .chain(CATEGORY_GROUPS.iter().map(|(short, _, categories)| (CategorySet::from(*categories), short)))
+                    .find(|(set, _)| set == categories);
+                match name {
+                    Some((_, name)) => write!(f, "\\p{{{}}}", name),
+                    None => write!(f, "\\p{{gc={:#x}}}", categories.bits()),
+                }
+            },
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lookup() {
+        assert_eq!(general_category('a'), Ll);
+        assert_eq!(general_category('Ω'), Lu);
+        assert_eq!(general_category('٣'), Nd);
+        assert_eq!(general_category(' '), Zs);
+        assert_eq!(general_category('\u{10FFFF}'), Cn);
+        assert_eq!(script('a'), Script::Latin);
+        assert_eq!(script('λ'), Script::Greek);
+        assert_eq!(script('漢'), Script::Han);
+        assert_eq!(script('1'), Script::Common);
+        assert_eq!(script('\u{10FFFF}'), Script::Unknown);
+        assert_eq!(Script::Greek.short_name(), "Grek");
+        assert_eq!(simple_fold('A'), 'a');
+        assert_eq!(simple_fold('a'), 'a');
+        assert_eq!(simple_fold('Σ'), simple_fold('ς'));
+        assert_eq!(simple_fold('ẞ'), 'ß');
+        assert_eq!(simple_fold('1'), '1');
+        assert_eq!(case_variants('k'), ['K', 'k', '\u{212A}']);
+        assert_eq!(case_variants('σ'), ['Σ', 'ς', 'σ']);
+        assert_eq!(case_variants('-'), ['-']);
+    }
+
+    #[test]
+    fn test_unicode_class() {
+        let letter = UnicodeClass::from_name("L").unwrap();
+        assert!(letter.is_member('a') && letter.is_member('ж') && letter.is_member('漢'));
+        assert!(!letter.is_member('1') && !letter.is_member('_'));
+        assert_eq!(UnicodeClass::from_name("letter"), Some(letter));
+        assert_eq!(UnicodeClass::from_name("gc=L"), Some(letter));
+        assert_eq!(UnicodeClass::from_name("Decimal Number"), UnicodeClass::from_name("Nd"));
+
+        let greek = UnicodeClass::from_name("Greek").unwrap();
+        assert!(greek.is_member('λ') && !greek.is_member('l'));
+        assert_eq!(UnicodeClass::from_name("sc=Grek"), Some(greek));
+        assert_eq!(UnicodeClass::from_name("Old_Italic"), UnicodeClass::from_name("olditalic"));
+        assert_eq!(UnicodeClass::from_name("Klingon"), None);
+        assert_eq!(UnicodeClass::from_name("sc=Lu"), None);
+        assert_eq!(UnicodeClass::from_name("foo=Lu"), None);
+
+        assert_eq!(letter.to_string(), "\\p{L}");
+        assert_eq!(UnicodeClass::from_name("Zs").unwrap().to_string(), "\\p{Zs}");
+        assert_eq!(greek.to_string(), "\\p{Greek}");
+        for class in [letter, greek] {
+            assert_eq!(UnicodeClass::from_bytes(class.to_bytes()), Some(class));
+        }
+        assert_eq!(UnicodeClass::from_bytes([0, 0, 0, 0, 0x40]), None);
+        assert_eq!(UnicodeClass::from_bytes([1, 255, 0, 0, 0]), None);
+        assert_eq!(UnicodeClass::from_bytes([2, 0, 0, 0, 0]), None);
+    }
+}
@@ -0,0 +1,266 @@
+use crate::grammar::expression::Expression;
+use crate::grammar::unicode::simple_fold;
+use crate::matcher::{Capture, MatchError};
+
+use std::cmp;
+use std::collections::HashMap;
+
+/// Matches grammars by walking their expressions directly, as a slow but
+/// straightforward reference for compiled bytecode.
+///
+/// Matching starts at the first rule. Undefined rules and classes never
+/// match, and repetitions stop at iterations that consume nothing, where
+/// compiled loops would never end.
+pub struct Interpreter<'g> {
+    rules: HashMap<&'g str, &'g Expression>,
+    start: Option<&'g Expression>,
+    classes: HashMap<String, &'g Expression>,
+    capture_names: Vec<&'g str>,
+}
+
+fn collect_capture_names<'g>(expr: &'g Expression, names: &mut Vec<&'g str>) {
+    match expr {
+        Expression::Capture(e, name) => {
+            if !names.contains(&name.as_str()) {
+                names.push(name);
+            }
+            collect_capture_names(e, names);
+        },
+        Expression::Quantifier(e, _) | Expression::And(e) | Expression::Not(e)
+            | Expression::Behind(e) | Expression::NotBehind(e) => collect_capture_names(e, names),
+        Expression::Sequence(es) | Expression::Choice(es) => es.iter().for_each(|e| collect_capture_names(e, names)),
+        _ => (),
+    }
+}
+
+impl<'g> Interpreter<'g> {
+    pub fn new(grammar: &'g [(&str, Expression)]) -> Interpreter<'g> {
+        let mut capture_names = Vec::new();
+        grammar.iter().for_each(|(_, expr)| collect_capture_names(expr, &mut capture_names));
+        Interpreter {
+            rules: grammar.iter().map(|(name, expr)| (*name, expr)).collect(),
+            start: grammar.first().map(|(_, expr)| expr),
+            classes: HashMap::new(),
+            capture_names,
+        }
+    }
+
+    /// Declares a class for `Expression::NamedClass`, like `Compiler::define_class`
+    pub fn define_class(&mut self, name: &str, expr: &'g Expression) {
+        self.classes.insert(name.to_string(), expr);
+    }
+
+    /// Capture names, indexed by the ids given to actions
+    pub fn capture_names(&self) -> &[&'g str] {
+        &self.capture_names
+    }
+
+    pub fn try_match(&self, text: &str) -> Result<usize, MatchError> {
+        self.try_match_captures_then(text, |_, _: &[()]| ()).map(|p| p.0)
+    }
+
+    pub fn try_match_captures_then<F, T>(&self, text: &str, action: F) -> Result<(usize, Option<T>), MatchError>
+    where
+        F: Fn(&Capture, &[T]) -> T
+    {
+        let mut run = Run { interpreter: self, text, farthest_failure: 0, captures: Vec::new() };
+        match self.start.and_then(|start| run.eval(start, 0)) {
+            Some(end) => Ok((end, run.captures.first().map(|capture| capture.apply(text, &action)))),
+            None => Err(MatchError::NoMatch(run.farthest_failure)),
+        }
+    }
+}
+
+// Captures are kept as a tree, independently from how the matcher stacks them
+struct CaptureNode {
+    start: usize,
+    end: usize,
+    id: u8,
+    children: Vec<CaptureNode>,
+}
+
+impl CaptureNode {
+    fn apply<F, T>(&self, text: &str, action: &F) -> T
+    where
+        F: Fn(&Capture, &[T]) -> T
+    {
+        let args: Vec<T> = self.children.iter().map(|child| child.apply(text, action)).collect();
+        let capture = Capture { text: &text[self.start..self.end], start: self.start, end: self.end, id: self.id };
+        action(&capture, &args)
+    }
+}
+
+struct Run<'i, 'g, 't> {
+    interpreter: &'i Interpreter<'g>,
+    text: &'t str,
+    farthest_failure: usize,
+    /// Captures of the innermost capture being matched, or top level ones
+    captures: Vec<CaptureNode>,
+}
+
+impl Run<'_, '_, '_> {
+    /// Position after `expr` matches at `pos`. Failures leave no captures behind.
+    fn eval(&mut self, expr: &Expression, pos: usize) -> Option<usize> {
+        let capture_count = self.captures.len();
+        let result = self.eval_expr(expr, pos);
+        if result.is_none() {
+            self.captures.truncate(capture_count);
+        }
+        result
+    }
+
+    fn fail(&mut self, pos: usize) -> Option<usize> {
+        self.farthest_failure = cmp::max(self.farthest_failure, pos);
+        None
+    }
+
+    fn test_char<P: Fn(char) -> bool>(&mut self, pos: usize, predicate: P) -> Option<usize> {
+        match self.text[pos..].chars().next() {
+            Some(c) if predicate(c) => Some(pos + c.len_utf8()),
+            _ => self.fail(pos),
+        }
+    }
+
+    // whether `expr` matches some text ending at `pos`
+    fn ends_at(&mut self, expr: &Expression, pos: usize) -> bool {
+        let starts: Vec<usize> = (0..=pos).rev().filter(|start| self.text.is_char_boundary(*start)).collect();
+        starts.into_iter().any(|start| self.eval(expr, start) == Some(pos))
+    }
+
+    fn eval_expr(&mut self, expr: &Expression, pos: usize) -> Option<usize> {
+        match expr {
+            Expression::Char(c) => self.test_char(pos, |next| next == *c),
+            Expression::CharNoCase(c) => self.test_char(pos, |next| simple_fold(next) == simple_fold(*c)),
+            Expression::Literal(s) => {
+                if self.text[pos..].starts_with(s.as_str()) { Some(pos + s.len()) } else { self.fail(pos) }
+            },
+            Expression::LiteralNoCase(s) => {
+                let mut end = pos;
+                for expected in s.chars() {
+                    match self.text[end..].chars().next() {
+                        Some(c) if simple_fold(c) == simple_fold(expected) => end += c.len_utf8(),
+                        _ => return self.fail(pos),
+                    }
+                }
+                Some(end)
+            },
+            Expression::Class(class) => self.test_char(pos, |next| class.is_member(next)),
+            Expression::UnicodeClass(class) => self.test_char(pos, |next| class.is_member(next)),
+            Expression::NamedClass(name) => {
+                let class = *self.interpreter.classes.get(name)?;
+                self.eval(class, pos)
+            },
+            Expression::Set(s) => self.test_char(pos, |next| s.contains(next)),
+            Expression::SetNoCase(s) => self.test_char(pos, |next| s.chars().any(|c| simple_fold(c) == simple_fold(next))),
+            Expression::InverseSet(s) => self.test_char(pos, |next| !s.contains(next)),
+            Expression::Range(min, max) => {
+                // empty ranges fail without looking at the text
+                if min > max { None } else { self.test_char(pos, |next| next >= *min && next <= *max) }
+            },
+            Expression::Any => self.test_char(pos, |_| true),
+            Expression::EndOfInput => if pos == self.text.len() { Some(pos) } else { self.fail(pos) },
+            Expression::NonTerminal(name) => {
+                let rule = *self.interpreter.rules.get(name.as_str())?;
+                self.eval(rule, pos)
+            },
+            Expression::Quantifier(e, n) => {
+                let (min, max) = if *n >= 0 { (*n as usize, usize::MAX) } else { (0, n.unsigned_abs() as usize) };
+                let mut end = pos;
+                let mut count = 0;
+                while count < max {
+                    match self.eval(e, end) {
+                        Some(next) => {
+                            count += 1;
+                            if next == end {
+                                count = cmp::max(count, min);
+                                break;
+                            }
+                            end = next;
+                        },
+                        None => break,
+                    }
+                }
+                if count >= min { Some(end) } else { None }
+            },
+            Expression::And(e) => {
+                let capture_count = self.captures.len();
+                self.eval(e, pos)?;
+                self.captures.truncate(capture_count);
+                Some(pos)
+            },
+            Expression::Not(e) => {
+                let capture_count = self.captures.len();
+                let result = self.eval(e, pos);
+                self.captures.truncate(capture_count);
+                if result.is_some() { None } else { Some(pos) }
+            },
+            Expression::Behind(e) | Expression::NotBehind(e) => {
+                let alternatives = match e.as_ref() {
+                    Expression::Choice(es) => es.iter().collect(),
+                    e => vec![e],
+                };
+                let capture_count = self.captures.len();
+                let found = alternatives.into_iter().any(|alternative| self.ends_at(alternative, pos));
+                self.captures.truncate(capture_count);
+                if found == matches!(expr, Expression::Behind(_)) { Some(pos) } else { None }
+            },
+            Expression::Sequence(es) => {
+                let mut end = pos;
+                for e in es.iter() {
+                    end = self.eval(e, end)?;
+                }
+                Some(end)
+            },
+            Expression::Choice(es) => es.iter().find_map(|e| self.eval(e, pos)),
+            Expression::Capture(e, name) => {
+                let outer = std::mem::take(&mut self.captures);
+                let result = self.eval(e, pos);
+                let children = std::mem::replace(&mut self.captures, outer);
+                let end = result?;
+                let id = self.interpreter.capture_names.iter().position(|n| *n == name.as_str()).unwrap();
+                self.captures.push(CaptureNode { start: pos, end, id: id as u8, children });
+                Some(end)
+            },
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grammar::character_class::CharacterClass;
+    use Expression::*;
+
+    fn nt(name: &str) -> Expression {
+        NonTerminal(name.to_string())
+    }
+
+    #[test]
+    fn test_interpreter() {
+        let digit = || Class(CharacterClass::Digit);
+        let grammar = [
+            ("Sum", ((nt("Number") + ((Char('+') + nt("Number"))^0)) >> "sum") + EndOfInput),
+            ("Number", (digit()^1) >> "number"),
+        ];
+        let interpreter = Interpreter::new(&grammar);
+        assert_eq!(interpreter.capture_names(), ["sum", "number"]);
+        assert_eq!(interpreter.try_match("1+23+4"), Ok(6));
+        assert_eq!(interpreter.try_match("1+23+"), Err(MatchError::NoMatch(5)));
+        let sum = interpreter.try_match_captures_then("1+23+4", |capture, args: &[usize]| {
+            if capture.id == 0 { args.iter().sum() } else { capture.text.parse().unwrap() }
+        });
+        assert_eq!(sum, Ok((6, Some(28))));
+
+        let grammar = [
+            ("Main", (Char('a')^(-2)) + (!Behind(Box::new(Literal("aa".to_string()))) + (Char('b') / Any)) + ((Char('c')^3)^0)),
+        ];
+        let interpreter = Interpreter::new(&grammar);
+        assert_eq!(interpreter.try_match("accccccc"), Ok(8));
+        assert_eq!(interpreter.try_match("abcc"), Ok(2));
+        assert_eq!(interpreter.try_match("aab"), Err(MatchError::NoMatch(2)));
+        assert_eq!(interpreter.try_match("b"), Ok(1));
+        // repetitions of empty matches end at once
+        assert_eq!(Interpreter::new(&[("Main", Sequence(vec![])^2)]).try_match("x"), Ok(0));
+    }
+}
@@ -1,4 +1,11 @@
 pub mod bytecode;
+#[cfg(any(test, feature = "fuzz"))]
+pub mod fuzz;
 pub mod grammar;
+#[cfg(feature = "interpreter")]
+pub mod interpreter;
 pub mod matcher;
+pub mod profiler;
+pub mod source_map;
+pub mod tracer;
 mod utils;
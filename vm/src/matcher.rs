@@ -1,16 +1,51 @@
 use crate::bytecode::Bytecode;
 use crate::bytecode::address::Address;
 use crate::bytecode::instruction::{Instruction, InstructionIterator};
+use crate::grammar::unicode::simple_fold;
+use crate::source_map::{LineIndex, Position};
+use crate::tracer::{NullTracer, Step, TraceControl, Tracer};
+
+use std::cmp;
+use std::fmt;
 
 #[derive(Debug, PartialEq)]
 pub enum MatchError {
-    NoMatch,
+    /// No match, with the farthest offset where matching failed
+    NoMatch(usize),
     UnmatchedPop,
+    Aborted,
+    /// Match that stopped before the end of the text, at this offset
+    TrailingInput(usize),
+}
+
+impl MatchError {
+    pub fn offset(&self) -> Option<usize> {
+        match *self {
+            MatchError::NoMatch(offset) | MatchError::TrailingInput(offset) => Some(offset),
+            MatchError::UnmatchedPop | MatchError::Aborted => None,
+        }
+    }
+
+    pub fn position(&self, index: &LineIndex) -> Option<Position> {
+        self.offset().map(|offset| index.position(offset))
+    }
+}
+
+impl fmt::Display for MatchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            MatchError::NoMatch(_) => write!(f, "no match"),
+            MatchError::UnmatchedPop => write!(f, "unmatched pop in bytecode"),
+            MatchError::Aborted => write!(f, "match aborted"),
+            MatchError::TrailingInput(_) => write!(f, "unexpected input after match"),
+        }
+    }
 }
 
 #[derive(Clone, Copy, Debug)]
 struct MatchState {
     sp: usize,
+    /// Iteration count, only meaningful in the entry pushed by `QuantifierInit`
     qc: i32,
     ac: i32,
     ip: Address,
@@ -21,32 +56,143 @@ struct MatchCapture {
     start: usize,
     end: usize,
     argc: i32,
+    /// This capture plus all the captures nested in it
+    size: usize,
     id: u8,
 }
 
+/// Pushes a capture whose nested captures are the ones from `first` on,
+/// with the direct ones as its arguments.
+fn push_capture(captures: &mut Vec<MatchCapture>, first: usize, start: usize, end: usize, id: u8) {
+    let mut argc = 0;
+    let mut i = captures.len();
+    while i > first {
+        i -= captures[i - 1].size;
+        argc += 1;
+    }
+    let size = captures.len() - first + 1;
+    captures.push(MatchCapture { start, end, argc, size, id });
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Capture<'a> {
+    pub text: &'a str,
+    pub start: usize,
+    pub end: usize,
+    pub id: u8,
+}
+
+impl Capture<'_> {
+    pub fn start_position(&self, index: &LineIndex) -> Position {
+        index.position(self.start)
+    }
+
+    pub fn end_position(&self, index: &LineIndex) -> Position {
+        index.position(self.end)
+    }
+}
+
 pub fn try_match(bytecode: &Bytecode, text: &str) -> Result<usize, MatchError> {
     try_match_then(bytecode, text, |_, _, _| ()).map(|p| p.0)
 }
 
+/// Like `try_match`, but the match must cover the whole text
+pub fn try_match_full(bytecode: &Bytecode, text: &str) -> Result<usize, MatchError> {
+    try_match_full_then(bytecode, text, |_, _: &[()]| ()).map(|p| p.0)
+}
+
+pub fn try_match_full_then<F, T>(bytecode: &Bytecode, text: &str, action: F) -> Result<(usize, Option<T>), MatchError>
+where
+    F: Fn(&Capture, &[T]) -> T
+{
+    try_match_full_traced_then(bytecode, text, &mut NullTracer, action)
+}
+
+pub fn try_match_full_traced_then<F, T, R>(bytecode: &Bytecode, text: &str, tracer: &mut R, action: F) -> Result<(usize, Option<T>), MatchError>
+where
+    F: Fn(&Capture, &[T]) -> T,
+    R: Tracer,
+{
+    match try_match_traced_then(bytecode, text, tracer, action)? {
+        (end, _) if end < text.len() => Err(MatchError::TrailingInput(end)),
+        result => Ok(result),
+    }
+}
+
 pub fn try_match_then<F, T>(bytecode: &Bytecode, text: &str, action: F) -> Result<(usize, Option<T>), MatchError> 
 where
     F: Fn(&str, u8, &[T]) -> T
+{
+    try_match_captures_then(bytecode, text, |capture, args| action(capture.text, capture.id, args))
+}
+
+pub fn try_match_captures_then<F, T>(bytecode: &Bytecode, text: &str, action: F) -> Result<(usize, Option<T>), MatchError>
+where
+    F: Fn(&Capture, &[T]) -> T
+{
+    try_match_traced_then(bytecode, text, &mut NullTracer, action)
+}
+
+pub fn try_match_traced<R: Tracer>(bytecode: &Bytecode, text: &str, tracer: &mut R) -> Result<usize, MatchError> {
+    try_match_traced_then(bytecode, text, tracer, |_, _: &[()]| ()).map(|p| p.0)
+}
+
+pub fn try_match_traced_then<F, T, R>(bytecode: &Bytecode, text: &str, tracer: &mut R, action: F) -> Result<(usize, Option<T>), MatchError>
+where
+    F: Fn(&Capture, &[T]) -> T,
+    R: Tracer,
+{
+    match_at(bytecode, text, 0, tracer, action)
+}
+
+/// Like `try_match`, but the match starts at byte `start`, which must be a char boundary.
+/// Lookbehinds still see the text before it
+pub fn try_match_at(bytecode: &Bytecode, text: &str, start: usize) -> Result<usize, MatchError> {
+    match_at(bytecode, text, start, &mut NullTracer, |_, _: &[()]| ()).map(|p| p.0)
+}
+
+/// Unanchored search: the first match starting at or after byte `start`, as its start and end
+pub fn find_at(bytecode: &Bytecode, text: &str, start: usize) -> Result<Option<(usize, usize)>, MatchError> {
+    for offset in (start..=text.len()).filter(|&i| text.is_char_boundary(i)) {
+        match try_match_at(bytecode, text, offset) {
+            Ok(end) => return Ok(Some((offset, end))),
+            Err(MatchError::NoMatch(_)) => continue,
+            Err(error) => return Err(error),
+        }
+    }
+    Ok(None)
+}
+
+fn match_at<F, T, R>(bytecode: &Bytecode, text: &str, start: usize, tracer: &mut R, action: F) -> Result<(usize, Option<T>), MatchError>
+where
+    F: Fn(&Capture, &[T]) -> T,
+    R: Tracer,
 {
     let mut success_flag = true;
+    let mut farthest_failure = start;
 
-    let mut state = MatchState { sp: 0, qc: 0, ac: 0, ip: Address::new(0) };
+    let mut state = MatchState { sp: start, qc: 0, ac: 0, ip: Address::new(0) };
     let mut state_stack = Vec::new();
 
     let mut capture_stack = Vec::new();
 
-    let mut iter = InstructionIterator::new(&bytecode);
+    let mut iter = InstructionIterator::new(bytecode);
 
+    // bytes that are part of a longer char never match, so `sp` stays on char boundaries
     fn get_next_byte(text_slice: &str) -> Option<u8> {
-        text_slice.as_bytes().get(0).copied()
+        text_slice.as_bytes().first().copied().filter(|_| text_slice.is_char_boundary(1))
     }
     fn get_next_char(text_slice: &str) -> Option<char> {
         text_slice.chars().next()
     }
+    // folded chars may have different lengths, so the match length comes from the text
+    fn get_prefix_len_no_case(text_slice: &str, s: &str) -> Option<usize> {
+        let mut chars = text_slice.chars();
+        for expected in s.chars() {
+            chars.next().filter(|&c| simple_fold(c) == simple_fold(expected))?;
+        }
+        Some(text_slice.len() - chars.as_str().len())
+    }
     macro_rules! push {
         () => {
             {
@@ -55,7 +201,6 @@ where
                     ac: capture_stack.len() as i32,
                     ..state
                 };
-                //println!(">> Push {:?}", state);
                 state_stack.push(state);
                 state
             }
@@ -73,7 +218,7 @@ where
     }
     macro_rules! jump {
         ($addr:expr) => {
-            iter.jump($addr);
+            iter.jump($addr)
         }
     }
     macro_rules! match_some {
@@ -83,13 +228,31 @@ where
                     state.sp += len;
                     true
                 },
-                None => false,
+                None => {
+                    farthest_failure = cmp::max(farthest_failure, state.sp);
+                    false
+                },
             }
         }
     }
 
-    while let Some(instruction) = iter.next() {
-        //println!("  {}", instruction);
+    loop {
+        let ip = iter.current();
+        let instruction = match iter.next() {
+            Some(instruction) => instruction,
+            None => break,
+        };
+        let step = Step {
+            ip,
+            instruction: &instruction,
+            sp: state.sp,
+            success: success_flag,
+            state_depth: state_stack.len(),
+            capture_depth: capture_stack.len(),
+        };
+        if tracer.trace(&step) == TraceControl::Abort {
+            return Err(MatchError::Aborted);
+        }
         let text_slice = &text[state.sp..];
         match instruction {
             Instruction::Any => {
@@ -103,19 +266,27 @@ where
                 success_flag = false;
             },
             Instruction::FailIfLessThan(n) => {
-                success_flag = state.qc >= n as i32;
+                let quantifier: MatchState = peek!()?;
+                success_flag = quantifier.qc >= n as i32;
+                // iterations matched so far don't count
+                if !success_flag {
+                    state.sp = quantifier.sp;
+                    capture_stack.truncate(quantifier.ac as usize);
+                }
             },
             Instruction::ToggleSuccess => {
                 success_flag = !success_flag;
             },
             Instruction::QuantifierInit => {
-                state = push!();
                 state.qc = 0;
+                state = push!();
             },
+            // the body may push and pop states of its own, so the loop lives in the stack
             Instruction::QuantifierNext => {
                 if success_flag {
-                    state.qc += 1;
-                    jump!(state.ip);
+                    let quantifier = state_stack.last_mut().ok_or(MatchError::UnmatchedPop)?;
+                    quantifier.qc += 1;
+                    jump!(quantifier.ip);
                 }
             },
             Instruction::Jump(addr) => {
@@ -169,7 +340,7 @@ where
             Instruction::Literal(s) => {
                 match_some!(Some(text_slice)
                     .filter(|text_slice| text_slice.starts_with(s))
-                    .map(str::len));
+                    .map(|_| s.len()));
             },
             Instruction::Set(s) => {
                 match_some!(get_next_char(text_slice)
@@ -181,6 +352,52 @@ where
                     .filter(|&c| !s.contains(c))
                     .map(char::len_utf8));
             },
+            Instruction::LiteralNoCase(s) => {
+                match_some!(get_prefix_len_no_case(text_slice, s));
+            },
+            Instruction::SetNoCase(s) => {
+                match_some!(get_next_char(text_slice)
+                    .filter(|&c| s.chars().any(|expected| simple_fold(c) == simple_fold(expected)))
+                    .map(char::len_utf8));
+            },
+            Instruction::EndOfInput => {
+                match_some!(Some(0).filter(|_| text_slice.is_empty()));
+            },
+            Instruction::FailIfMoved => {
+                success_flag = peek!()?.sp == state.sp;
+            },
+            Instruction::Behind(n) => {
+                let mut chars = text[..state.sp].chars();
+                success_flag = (0..n).all(|_| chars.next_back().is_some());
+                if success_flag {
+                    state.sp = chars.as_str().len();
+                }
+            },
+            Instruction::ClassSet(class_set) => {
+                match_some!(get_next_char(text_slice)
+                    .filter(|&c| class_set.contains(c))
+                    .map(char::len_utf8));
+            },
+            Instruction::UnicodeClass(class) => {
+                match_some!(get_next_char(text_slice)
+                    .filter(|&c| class.is_member(c))
+                    .map(char::len_utf8));
+            },
+            Instruction::CharRange(min, max) => {
+                match_some!(get_next_char(text_slice)
+                    .filter(|&c| c >= min && c <= max)
+                    .map(char::len_utf8));
+            },
+            Instruction::Charset(charset) => {
+                match_some!(get_next_char(text_slice)
+                    .filter(|&c| charset.contains_char(c))
+                    .map(char::len_utf8));
+            },
+            Instruction::NotCharset(charset) => {
+                match_some!(get_next_char(text_slice)
+                    .filter(|&c| !charset.contains_char(c))
+                    .map(char::len_utf8));
+            },
             Instruction::Range(b_min, b_max) => {
                 match_some!(get_next_byte(text_slice)
                     .filter(|&next_byte| next_byte >= b_min && next_byte <= b_max)
@@ -188,32 +405,33 @@ where
             },
             Instruction::Capture(i) => {
                 let previous_state = peek!()?;
-                let capture = MatchCapture {
-                    start: previous_state.sp,
-                    end: state.sp,
-                    argc: state.ac - previous_state.ac,
-                    id: i,
-                };
-                capture_stack.push(capture);
-                //println!("== Capture {:?} (ac: {})", &text[capture.start..capture.end], capture.argc);
+                // moving back with `Behind` may end a capture before its start
+                let start = cmp::min(previous_state.sp, state.sp);
+                push_capture(&mut capture_stack, previous_state.ac as usize, start, state.sp, i);
             },
             Instruction::Halt(_opt_err) => break,
+            Instruction::TestSet(addr, s) => {
+                if !get_next_char(text_slice).is_some_and(|c| s.contains(c)) {
+                    success_flag = false;
+                    farthest_failure = cmp::max(farthest_failure, state.sp);
+                    jump!(addr);
+                }
+            },
         }
     }
 
     if success_flag {
-        //println!("MATCHED {:?}", capture_stack);
         let action_result = run_action_on(text, &capture_stack, action);
         Ok((state.sp, action_result))
     }
     else {
-        Err(MatchError::NoMatch)
+        Err(MatchError::NoMatch(cmp::max(farthest_failure, state.sp)))
     }
 }
 
 fn run_action_on<F, T>(text: &str, captures: &[MatchCapture], action: F) -> Option<T>
 where
-    F: Fn(&str, u8, &[T]) -> T
+    F: Fn(&Capture, &[T]) -> T
 {
     let num_captures = captures.len();
     if num_captures == 0 {
@@ -228,8 +446,12 @@ where
         data_index -= argc;
         // run action with arguments (which are still stacked in `data_stack` in the right position)
         let value = action(
-            &text[capture.start .. capture.end],
-            capture.id,
+            &Capture {
+                text: &text[capture.start .. capture.end],
+                start: capture.start,
+                end: capture.end,
+                id: capture.id,
+            },
             &data_stack[data_index .. data_index + argc]
         );
         data_stack.truncate(data_index);
@@ -245,7 +467,6 @@ mod tests {
     use super::*;
     use Instruction::*;
     use crate::bytecode::OwnedBytecode;
-    use crate::bytecode::builder::Builder;
     use crate::matcher::MatchError::*;
 
     macro_rules! test_match {
@@ -260,32 +481,264 @@ mod tests {
         let any = any.as_bytecode();
         test_match!(&any, ".", Ok(1));
         test_match!(&any, "\u{0}", Ok(1));
-        test_match!(&any, "", Err(NoMatch));
+        test_match!(&any, "", Err(NoMatch(0)));
+    }
+
+    #[test]
+    fn test_byte_in_char() {
+        // bytes never match inside a multibyte char
+        let byte = OwnedBytecode::from_instructions(&[Byte(0xce)]);
+        test_match!(&byte.as_bytecode(), "α", Err(NoMatch(0)));
+        let range = OwnedBytecode::from_instructions(&[Range(0x80, 0xff)]);
+        test_match!(&range.as_bytecode(), "é", Err(NoMatch(0)));
+    }
+
+    #[test]
+    fn test_find() {
+        use crate::grammar::character_class::CharacterClass;
+        let digits = OwnedBytecode::from_instructions(&[Class(CharacterClass::Digit), Class(CharacterClass::Digit)]);
+        let digits = digits.as_bytecode();
+        assert_eq!(find_at(&digits, "ab12c34", 0), Ok(Some((2, 4))));
+        assert_eq!(find_at(&digits, "ab12c34", 3), Ok(Some((5, 7))));
+        assert_eq!(find_at(&digits, "αβ1γ", 0), Ok(None));
+        assert_eq!(try_match_at(&digits, "ab12", 2), Ok(4));
+        assert_eq!(try_match_at(&digits, "ab1c", 2), Err(NoMatch(3)));
+
+        // lookbehind sees the text before the start
+        let after_a = OwnedBytecode::from_instructions(&[Behind(1), Char('a')]);
+        let after_a = after_a.as_bytecode();
+        assert_eq!(find_at(&after_a, "xbab", 0), Ok(Some((3, 3))));
+
+        let empty = OwnedBytecode::from_instructions(&[Succeed]);
+        assert_eq!(find_at(&empty.as_bytecode(), "α", 1), Ok(Some((2, 2))));
     }
 
     #[test]
     fn test_set() {
         let set = OwnedBytecode::from_instructions(&[Set("1234")]);
         let set = set.as_bytecode();
-        test_match!(&set, "", Err(NoMatch));
-        test_match!(&set, "0", Err(NoMatch));
+        test_match!(&set, "", Err(NoMatch(0)));
+        test_match!(&set, "0", Err(NoMatch(0)));
         test_match!(&set, "1", Ok(1));
         test_match!(&set, "2", Ok(1));
         test_match!(&set, "3", Ok(1));
         test_match!(&set, "4", Ok(1));
-        test_match!(&set, "5", Err(NoMatch));
+        test_match!(&set, "5", Err(NoMatch(0)));
     }
 
     #[test]
     fn test_inverse_set() {
         let set = OwnedBytecode::from_instructions(&[NotSet("1234")]);
         let set = set.as_bytecode();
-        test_match!(&set, "", Err(NoMatch));
+        test_match!(&set, "", Err(NoMatch(0)));
         test_match!(&set, "0", Ok(1));
-        test_match!(&set, "1", Err(NoMatch));
-        test_match!(&set, "2", Err(NoMatch));
-        test_match!(&set, "3", Err(NoMatch));
-        test_match!(&set, "4", Err(NoMatch));
+        test_match!(&set, "1", Err(NoMatch(0)));
+        test_match!(&set, "2", Err(NoMatch(0)));
+        test_match!(&set, "3", Err(NoMatch(0)));
+        test_match!(&set, "4", Err(NoMatch(0)));
         test_match!(&set, "5", Ok(1));
     }
+
+    #[test]
+    fn test_literal() {
+        let literal = OwnedBytecode::from_instructions(&[Literal("ab")]);
+        let literal = literal.as_bytecode();
+        test_match!(&literal, "ab", Ok(2));
+        test_match!(&literal, "abcd", Ok(2));
+        test_match!(&literal, "a", Err(NoMatch(0)));
+        test_match!(&literal, "ba", Err(NoMatch(0)));
+    }
+
+    #[test]
+    fn test_charset() {
+        let mut digits = crate::bytecode::charset::Charset::new();
+        digits.insert_range(b'0', b'9');
+        let charset = OwnedBytecode::from_instructions(&[Charset(digits)]);
+        let charset = charset.as_bytecode();
+        test_match!(&charset, "", Err(NoMatch(0)));
+        test_match!(&charset, "0", Ok(1));
+        test_match!(&charset, "9", Ok(1));
+        test_match!(&charset, "a", Err(NoMatch(0)));
+        let not_charset = OwnedBytecode::from_instructions(&[NotCharset(digits)]);
+        let not_charset = not_charset.as_bytecode();
+        test_match!(&not_charset, "", Err(NoMatch(0)));
+        test_match!(&not_charset, "0", Err(NoMatch(0)));
+        test_match!(&not_charset, "a", Ok(1));
+        test_match!(&not_charset, "α", Ok(2));
+    }
+
+    #[test]
+    fn test_char_range() {
+        let greek = OwnedBytecode::from_instructions(&[CharRange('α', 'ω')]);
+        let greek = greek.as_bytecode();
+        test_match!(&greek, "", Err(NoMatch(0)));
+        test_match!(&greek, "α", Ok(2));
+        test_match!(&greek, "λx", Ok(2));
+        test_match!(&greek, "ω", Ok(2));
+        test_match!(&greek, "a", Err(NoMatch(0)));
+        test_match!(&greek, "Ω", Err(NoMatch(0)));
+    }
+
+    #[test]
+    fn test_no_case() {
+        let keyword = OwnedBytecode::from_instructions(&[LiteralNoCase("select")]);
+        let keyword = keyword.as_bytecode();
+        test_match!(&keyword, "select", Ok(6));
+        test_match!(&keyword, "SeLeCt *", Ok(6));
+        test_match!(&keyword, "selec", Err(NoMatch(0)));
+        test_match!(&keyword, "selekt", Err(NoMatch(0)));
+        let kelvin = OwnedBytecode::from_instructions(&[LiteralNoCase("ok")]);
+        test_match!(&kelvin.as_bytecode(), "O\u{212A}", Ok(4));
+        let greek = OwnedBytecode::from_instructions(&[LiteralNoCase("Σ")]);
+        test_match!(&greek.as_bytecode(), "ς", Ok(2));
+
+        let set = OwnedBytecode::from_instructions(&[SetNoCase("aßx")]);
+        let set = set.as_bytecode();
+        test_match!(&set, "A", Ok(1));
+        test_match!(&set, "ẞ", Ok(3));
+        test_match!(&set, "x", Ok(1));
+        test_match!(&set, "b", Err(NoMatch(0)));
+        test_match!(&set, "", Err(NoMatch(0)));
+    }
+
+    #[test]
+    fn test_end_of_input() {
+        let end = OwnedBytecode::from_instructions(&[Char('a'), EndOfInput]);
+        let end = end.as_bytecode();
+        test_match!(&end, "a", Ok(1));
+        test_match!(&end, "ab", Err(NoMatch(1)));
+
+        let a = OwnedBytecode::from_instructions(&[Char('a')]);
+        let a = a.as_bytecode();
+        assert_eq!(try_match_full(&a, "a"), Ok(1));
+        assert_eq!(try_match_full(&a, "b"), Err(NoMatch(0)));
+        let error = try_match_full(&a, "a\nb").unwrap_err();
+        assert_eq!(error, TrailingInput(1));
+        assert_eq!(error.position(&LineIndex::new("a\nb")).map(|p| (p.line, p.column)), Some((1, 2)));
+
+        let captured = OwnedBytecode::from_instructions(&[Push, Char('a'), Capture(3), Pop]);
+        let captured = captured.as_bytecode();
+        let result = try_match_full_then(&captured, "a", |capture, _: &[()]| assert_eq!((capture.end, capture.id), (1, 3)));
+        assert_eq!(result, Ok((1, Some(()))));
+        assert_eq!(try_match_full_then(&captured, "ab", |_, _: &[()]| ()), Err(TrailingInput(1)));
+    }
+
+    #[test]
+    fn test_behind() {
+        // "ab" followed by 'c', preceded by "αb"
+        let behind = OwnedBytecode::from_instructions(&[Literal("αb"), Push, Behind(2), Literal("αb"), Peek, Pop, Char('c')]);
+        let behind = behind.as_bytecode();
+        test_match!(&behind, "αbc", Ok(4));
+        test_match!(&behind, "αbd", Err(NoMatch(3)));
+        let start = OwnedBytecode::from_instructions(&[Char('a'), Behind(2)]);
+        test_match!(&start.as_bytecode(), "ab", Err(NoMatch(1)));
+        let zero = OwnedBytecode::from_instructions(&[Behind(0)]);
+        test_match!(&zero.as_bytecode(), "", Ok(0));
+    }
+
+    #[test]
+    fn test_unicode_class() {
+        let class = crate::grammar::unicode::UnicodeClass::from_name("Nd").unwrap();
+        let digit = OwnedBytecode::from_instructions(&[UnicodeClass(class)]);
+        let digit = digit.as_bytecode();
+        test_match!(&digit, "", Err(NoMatch(0)));
+        test_match!(&digit, "7", Ok(1));
+        test_match!(&digit, "٣", Ok(2));
+        test_match!(&digit, "x", Err(NoMatch(0)));
+    }
+
+    #[test]
+    fn test_negated_class() {
+        use crate::grammar::character_class::CharacterClass;
+        let not_space = OwnedBytecode::from_instructions(&[Class(CharacterClass::NotWhitespace)]);
+        let not_space = not_space.as_bytecode();
+        test_match!(&not_space, "", Err(NoMatch(0)));
+        test_match!(&not_space, " ", Err(NoMatch(0)));
+        test_match!(&not_space, "\u{3000}", Err(NoMatch(0)));
+        test_match!(&not_space, "x", Ok(1));
+        test_match!(&not_space, "ж", Ok(2));
+        assert_eq!(Class(CharacterClass::NotWhitespace).to_string(), "cls \\S");
+    }
+
+    #[test]
+    fn test_test_set() {
+        let test_set = OwnedBytecode::from_instructions(&[TestSet(Address::new(9), "ab"), Any]);
+        let test_set = test_set.as_bytecode();
+        test_match!(&test_set, "", Err(NoMatch(0)));
+        test_match!(&test_set, "a", Ok(1));
+        test_match!(&test_set, "b", Ok(1));
+        test_match!(&test_set, "c", Err(NoMatch(0)));
+    }
+
+    #[test]
+    fn test_farthest_failure() {
+        let literals = OwnedBytecode::from_instructions(&[
+            Push,
+            Literal("ab"),
+            JumpIfFail(Address::new(19)),
+            Literal("\nc"),
+            JumpIfSuccess(Address::new(20)),
+            Peek,
+            Pop,
+        ]);
+        let literals = literals.as_bytecode();
+        test_match!(&literals, "ab\nc", Ok(4));
+        test_match!(&literals, "ab\nc\n", Ok(4));
+        test_match!(&literals, "x", Err(NoMatch(0)));
+        test_match!(&literals, "ab\nx", Err(NoMatch(2)));
+
+        let text = "ab\nx";
+        let error = try_match(&literals, text).unwrap_err();
+        assert_eq!(error.position(&LineIndex::new(text)).unwrap().to_string(), "1:3");
+    }
+
+    #[test]
+    fn test_quantifier_stack() {
+        // body pushing a state of its own
+        let pushes = OwnedBytecode::from_instructions(&[QuantifierInit, Push, Char('a'), Pop, QuantifierNext, FailIfLessThan(2), Pop]);
+        let pushes = pushes.as_bytecode();
+        test_match!(&pushes, "aaab", Ok(3));
+        test_match!(&pushes, "ab", Err(NoMatch(1)));
+
+        // (('a'^1) ';')^2, where the inner count must not leak into the outer one
+        let nested = OwnedBytecode::from_instructions(&[
+            QuantifierInit, QuantifierInit, Char('a'), QuantifierNext, FailIfLessThan(1), Pop,
+            Char(';'), QuantifierNext, FailIfLessThan(2), Pop,
+        ]);
+        let nested = nested.as_bytecode();
+        test_match!(&nested, "a;aa;", Ok(5));
+        test_match!(&nested, "aaa;", Err(NoMatch(4)));
+    }
+
+    #[test]
+    fn test_nested_captures() {
+        let nested = OwnedBytecode::from_instructions(&[
+            Push, Push, Char('a'), Push, Char('b'), Capture(2), Pop, Capture(1), Pop, Push, Char('c'), Capture(3), Pop, Capture(0), Pop,
+        ]);
+        let result = try_match_captures_then(&nested.as_bytecode(), "abc", |capture, args: &[String]| {
+            format!("{}{:?}", capture.id, args)
+        });
+        assert_eq!(result, Ok((3, Some(r#"0["1[\"2[]\"]", "3[]"]"#.to_string()))));
+    }
+
+    #[test]
+    fn test_capture_spans() {
+        let capture = OwnedBytecode::from_instructions(&[Push, Char('\n'), Literal("αβ"), Capture(7), Pop]);
+        let capture = capture.as_bytecode();
+        let text = "\nαβ";
+        let index = LineIndex::new(text);
+        let result = try_match_captures_then(&capture, text, |capture, _: &[()]| {
+            assert_eq!(capture.text, "\nαβ");
+            assert_eq!((capture.start, capture.end, capture.id), (0, 5, 7));
+            assert_eq!(capture.end_position(&index).to_string(), "2:3");
+        });
+        assert_eq!(result, Ok((5, Some(()))));
+
+        // a capture moved back by `Behind` is empty
+        let behind = OwnedBytecode::from_instructions(&[Push, Any, Any, Behind(2), Capture(0), Pop]);
+        let result = try_match_captures_then(&behind.as_bytecode(), "αα", |capture, _: &[()]| {
+            assert_eq!((capture.start, capture.end, capture.text), (0, 0, ""));
+        });
+        assert_eq!(result, Ok((0, Some(()))));
+    }
 }
@@ -0,0 +1,249 @@
+use crate::bytecode::address::Address;
+use crate::bytecode::instruction::Instruction;
+use crate::bytecode::symbols::SymbolTable;
+use crate::tracer::{Step, TraceControl, Tracer};
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{self, Write};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct RuleProfile {
+    pub invocations: usize,
+    pub successes: usize,
+    pub failures: usize,
+    pub bytes_consumed: usize,
+    /// Instructions executed in the rule body itself
+    pub instructions: usize,
+    /// Instructions executed in the rule body and the rules it called
+    pub total_instructions: usize,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Frame {
+    rule: Address,
+    start_sp: usize,
+    total_instructions: usize,
+    /// Entered through a tail call, so its `Return` also leaves the caller
+    tail: bool,
+}
+
+/// Tracer that counts invocations, results, consumed bytes and executed
+/// instructions for each rule, identified by its `Call` target address.
+/// `Jump`s given to `with_tail_calls` count as tail calls, other jumps stay
+/// in the current rule even if they target a rule entry.
+#[derive(Clone, Debug, Default)]
+pub struct Profiler {
+    symbols: SymbolTable,
+    tail_calls: BTreeSet<Address>,
+    profiles: BTreeMap<Address, RuleProfile>,
+    frames: Vec<Frame>,
+    started: bool,
+}
+
+impl Profiler {
+    pub fn new(symbols: SymbolTable) -> Profiler {
+        Profiler { symbols, ..Profiler::default() }
+    }
+
+    /// Sets the addresses of the `Jump`s that are tail calls, like
+    /// `Metadata::tail_calls`
+    pub fn with_tail_calls(mut self, tail_calls: &[Address]) -> Profiler {
+        self.tail_calls = tail_calls.iter().copied().collect();
+        self
+    }
+
+    pub fn profile(&self, rule: &str) -> Option<&RuleProfile> {
+        let address = self.symbols.address_of(rule)?;
+        self.profiles.get(&address)
+    }
+
+    pub fn profiles(&self) -> impl Iterator<Item = (Address, &RuleProfile)> {
+        self.profiles.iter().map(|(addr, profile)| (*addr, profile))
+    }
+
+    pub fn reset(&mut self) {
+        self.profiles.clear();
+        self.frames.clear();
+        self.started = false;
+    }
+
+    /// Writes a table of all profiled rules, most expensive first.
+    pub fn write_report<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        let mut rows: Vec<_> = self.profiles.iter().collect();
+        rows.sort_by(|(addr_a, a), (addr_b, b)| {
+            b.total_instructions.cmp(&a.total_instructions).then(addr_a.cmp(addr_b))
+        });
+        writeln!(out, "{:<20} {:>10} {:>10} {:>10} {:>10} {:>12} {:>12}",
+            "rule", "calls", "success", "fail", "bytes", "self instr", "total instr")?;
+        for (address, profile) in rows {
+            let name = match self.symbols.name_at(*address) {
+                Some(name) => name.to_string(),
+                None => format!("@{}", address),
+            };
+            writeln!(out, "{:<20} {:>10} {:>10} {:>10} {:>10} {:>12} {:>12}",
+                name,
+                profile.invocations,
+                profile.successes,
+                profile.failures,
+                profile.bytes_consumed,
+                profile.instructions,
+                profile.total_instructions)?;
+        }
+        Ok(())
+    }
+
+    fn enter(&mut self, rule: Address, sp: usize, tail: bool) {
+        self.profiles.entry(rule).or_default().invocations += 1;
+        self.frames.push(Frame { rule, start_sp: sp, total_instructions: 0, tail });
+    }
+
+    fn leave(&mut self, sp: usize, success: bool) {
+        while let Some(frame) = self.frames.pop() {
+            let profile = self.profiles.entry(frame.rule).or_default();
+            profile.total_instructions += frame.total_instructions;
+            if success {
+                profile.successes += 1;
+                profile.bytes_consumed += sp.saturating_sub(frame.start_sp);
+            }
+            else {
+                profile.failures += 1;
+            }
+            if let Some(parent) = self.frames.last_mut() {
+                parent.total_instructions += frame.total_instructions;
+            }
+            if !frame.tail {
+                break;
+            }
+        }
+    }
+}
+
+impl Tracer for Profiler {
+    fn trace(&mut self, step: &Step) -> TraceControl {
+        if !self.started {
+            self.started = true;
+            self.enter(step.ip, step.sp, false);
+        }
+        if let Some(frame) = self.frames.last_mut() {
+            frame.total_instructions += 1;
+            self.profiles.entry(frame.rule).or_default().instructions += 1;
+        }
+        match *step.instruction {
+            Instruction::Call(addr) => self.enter(addr, step.sp, false),
+            Instruction::Return => self.leave(step.sp, step.success),
+            Instruction::Jump(addr) if self.tail_calls.contains(&step.ip) => self.enter(addr, step.sp, true),
+            _ => (),
+        }
+        TraceControl::Continue
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bytecode::OwnedBytecode;
+    use crate::matcher::try_match_traced;
+    use Instruction::*;
+
+    #[test]
+    fn test_profiler() {
+        // Main <- Digit Digit
+        // Digit <- [0-9]
+        let bytecode = OwnedBytecode::from_instructions(&[
+            Call(Address::new(16)),
+            JumpIfFail(Address::new(15)),
+            Call(Address::new(16)),
+            Return,
+            Range(b'0', b'9'),
+            Return,
+        ]);
+        let mut symbols = SymbolTable::new();
+        symbols.insert(Address::new(0), "Main");
+        symbols.insert(Address::new(16), "Digit");
+
+        let mut profiler = Profiler::new(symbols);
+        assert!(try_match_traced(&bytecode.as_bytecode(), "4x", &mut profiler).is_err());
+        assert_eq!(profiler.profile("Digit"), Some(&RuleProfile {
+            invocations: 2,
+            successes: 1,
+            failures: 1,
+            bytes_consumed: 1,
+            instructions: 4,
+            total_instructions: 4,
+        }));
+        assert_eq!(profiler.profile("Main"), Some(&RuleProfile {
+            invocations: 1,
+            successes: 0,
+            failures: 1,
+            bytes_consumed: 0,
+            instructions: 4,
+            total_instructions: 8,
+        }));
+
+        let mut report = Vec::new();
+        profiler.write_report(&mut report).unwrap();
+        let report = String::from_utf8(report).unwrap();
+        let lines: Vec<_> = report.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[1].starts_with("Main "));
+        assert!(lines[2].starts_with("Digit "));
+    }
+
+    #[test]
+    fn test_profiler_tail_call() {
+        // Main <- Digit, with Digit called in tail position
+        let bytecode = OwnedBytecode::from_instructions(&[
+            Jump(Address::new(5)),
+            Range(b'0', b'9'),
+            Return,
+        ]);
+        let mut symbols = SymbolTable::new();
+        symbols.insert(Address::new(0), "Main");
+        symbols.insert(Address::new(5), "Digit");
+
+        let mut profiler = Profiler::new(symbols).with_tail_calls(&[Address::new(0)]);
+        assert!(try_match_traced(&bytecode.as_bytecode(), "4", &mut profiler).is_ok());
+        assert_eq!(profiler.profile("Digit"), Some(&RuleProfile {
+            invocations: 1,
+            successes: 1,
+            failures: 0,
+            bytes_consumed: 1,
+            instructions: 2,
+            total_instructions: 2,
+        }));
+        assert_eq!(profiler.profile("Main"), Some(&RuleProfile {
+            invocations: 1,
+            successes: 1,
+            failures: 0,
+            bytes_consumed: 1,
+            instructions: 1,
+            total_instructions: 3,
+        }));
+    }
+
+    #[test]
+    fn test_profiler_loop() {
+        // Digits <- [0-9]*, looping by jumping back to the rule entry
+        let bytecode = OwnedBytecode::from_instructions(&[
+            Range(b'0', b'9'),
+            JumpIfFail(Address::new(13)),
+            Jump(Address::new(0)),
+            Succeed,
+            Return,
+        ]);
+        let mut symbols = SymbolTable::new();
+        symbols.insert(Address::new(0), "Digits");
+
+        let mut profiler = Profiler::new(symbols);
+        assert_eq!(try_match_traced(&bytecode.as_bytecode(), "12x", &mut profiler), Ok(2));
+        assert_eq!(profiler.profile("Digits"), Some(&RuleProfile {
+            invocations: 1,
+            successes: 1,
+            failures: 0,
+            bytes_consumed: 2,
+            instructions: 10,
+            total_instructions: 10,
+        }));
+    }
+}
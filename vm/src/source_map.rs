@@ -0,0 +1,125 @@
+use std::fmt;
+
+/// Line and column of a byte offset in the matched text.
+///
+/// All values are 1-based. `column` counts bytes from the start of the line,
+/// while `char_column` counts UTF-8 characters, which is what text editors show.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Position {
+    pub offset: usize,
+    pub line: usize,
+    pub column: usize,
+    pub char_column: usize,
+}
+
+impl fmt::Display for Position {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.line, self.char_column)
+    }
+}
+
+/// Maps byte offsets of a text into line/column positions.
+///
+/// Line starts are computed once, so repeated lookups are a binary search.
+#[derive(Clone, Debug)]
+pub struct LineIndex<'a> {
+    text: &'a str,
+    line_starts: Vec<usize>,
+}
+
+impl<'a> LineIndex<'a> {
+    pub fn new(text: &'a str) -> LineIndex<'a> {
+        let mut line_starts = vec![0];
+        line_starts.extend(text.bytes()
+            .enumerate()
+            .filter(|(_, b)| *b == b'\n')
+            .map(|(i, _)| i + 1));
+        LineIndex { text, line_starts }
+    }
+
+    pub fn text(&self) -> &'a str {
+        self.text
+    }
+
+    pub fn line_count(&self) -> usize {
+        self.line_starts.len()
+    }
+
+    /// Position of `offset`, clamped to the end of the text.
+    /// Offsets inside a multibyte character count as that character.
+    pub fn position(&self, offset: usize) -> Position {
+        let offset = offset.min(self.text.len());
+        let line_index = match self.line_starts.binary_search(&offset) {
+            Ok(i) => i,
+            Err(i) => i - 1,
+        };
+        let line_start = self.line_starts[line_index];
+        let bytes = self.text.as_bytes();
+        let line_bytes = &bytes[line_start..offset];
+        let is_continuation = |b: &u8| (*b & 0xC0) == 0x80;
+        let chars_before = line_bytes.iter().filter(|b| !is_continuation(b)).count();
+        let inside_char = bytes.get(offset).is_some_and(is_continuation);
+        Position {
+            offset,
+            line: line_index + 1,
+            column: line_bytes.len() + 1,
+            char_column: if inside_char { chars_before } else { chars_before + 1 },
+        }
+    }
+
+    /// Text of the 1-based line `line`, without its line terminator.
+    pub fn line(&self, line: usize) -> Option<&'a str> {
+        let start = *self.line_starts.get(line.checked_sub(1)?)?;
+        let end = self.line_starts.get(line)
+            .map(|next_start| next_start - 1)
+            .unwrap_or_else(|| self.text.len());
+        let line_text = &self.text[start..end];
+        Some(line_text.strip_suffix('\r').unwrap_or(line_text))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    macro_rules! test_position {
+        ($index:expr, $offset:expr, ($line:expr, $column:expr, $char_column:expr)) => {
+            let position = $index.position($offset);
+            assert_eq!((position.line, position.column, position.char_column), ($line, $column, $char_column))
+        }
+    }
+
+    #[test]
+    fn test_position() {
+        let index = LineIndex::new("ab\ncd\n\nαβγ\n");
+        test_position!(index, 0, (1, 1, 1));
+        test_position!(index, 1, (1, 2, 2));
+        test_position!(index, 2, (1, 3, 3));
+        test_position!(index, 3, (2, 1, 1));
+        test_position!(index, 5, (2, 3, 3));
+        test_position!(index, 6, (3, 1, 1));
+        test_position!(index, 7, (4, 1, 1));
+        test_position!(index, 9, (4, 3, 2));
+        test_position!(index, 10, (4, 4, 2));
+        test_position!(index, 13, (4, 7, 4));
+        test_position!(index, 14, (5, 1, 1));
+        test_position!(index, 100, (5, 1, 1));
+        assert_eq!(index.position(9).to_string(), "4:2");
+
+        let empty = LineIndex::new("");
+        test_position!(empty, 0, (1, 1, 1));
+    }
+
+    #[test]
+    fn test_line() {
+        let index = LineIndex::new("ab\r\ncd\n\nαβγ");
+        assert_eq!(index.line_count(), 4);
+        assert_eq!(index.line(0), None);
+        assert_eq!(index.line(1), Some("ab"));
+        assert_eq!(index.line(2), Some("cd"));
+        assert_eq!(index.line(3), Some(""));
+        assert_eq!(index.line(4), Some("αβγ"));
+        assert_eq!(index.line(5), None);
+    }
+}
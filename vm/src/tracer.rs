@@ -0,0 +1,237 @@
+use crate::bytecode::address::Address;
+use crate::bytecode::instruction::Instruction;
+use crate::bytecode::symbols::SymbolTable;
+
+use std::collections::BTreeSet;
+use std::io::{self, BufRead, Write};
+
+/// Matcher state right before an instruction is executed.
+#[derive(Debug)]
+pub struct Step<'s, 'a> {
+    pub ip: Address,
+    pub instruction: &'s Instruction<'a>,
+    pub sp: usize,
+    pub success: bool,
+    pub state_depth: usize,
+    pub capture_depth: usize,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TraceControl {
+    Continue,
+    Abort,
+}
+
+pub trait Tracer {
+    fn trace(&mut self, step: &Step) -> TraceControl;
+}
+
+pub struct NullTracer;
+
+impl Tracer for NullTracer {
+    fn trace(&mut self, _step: &Step) -> TraceControl {
+        TraceControl::Continue
+    }
+}
+
+fn write_step<W: Write>(out: &mut W, step: &Step, symbols: &SymbolTable) -> io::Result<()> {
+    let indent = step.state_depth * 2;
+    let flag = if step.success { 'S' } else { 'F' };
+    match *step.instruction {
+        Instruction::Call(addr) => match symbols.name_at(addr) {
+            Some(name) => write!(out, "{:5} | {:indent$}call {}", step.ip, "", name, indent = indent)?,
+            None => write!(out, "{:5} | {:indent$}{}", step.ip, "", step.instruction, indent = indent)?,
+        },
+        _ => write!(out, "{:5} | {:indent$}{}", step.ip, "", step.instruction, indent = indent)?,
+    }
+    writeln!(out, "    ; sp={} {} stack={} captures={}", step.sp, flag, step.state_depth, step.capture_depth)
+}
+
+/// Prints every executed instruction, indented by the state stack depth.
+pub struct PrintTracer<W: Write> {
+    out: W,
+    symbols: SymbolTable,
+}
+
+impl<W: Write> PrintTracer<W> {
+    pub fn new(out: W, symbols: SymbolTable) -> PrintTracer<W> {
+        PrintTracer { out, symbols }
+    }
+}
+
+impl<W: Write> Tracer for PrintTracer<W> {
+    fn trace(&mut self, step: &Step) -> TraceControl {
+        if let Some(name) = self.symbols.name_at(step.ip) {
+            let _ = writeln!(self.out, "{:5} | {:indent$}{}:", "", "", name, indent = step.state_depth * 2);
+        }
+        match write_step(&mut self.out, step, &self.symbols) {
+            Ok(_) => TraceControl::Continue,
+            Err(_) => TraceControl::Abort,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum DebuggerMode {
+    Stepping,
+    Running,
+    StepOver(usize),
+}
+
+/// Interactive step debugger reading commands from `input`.
+///
+/// Commands: `s`tep, `n`ext (step over calls), `c`ontinue, `b <rule|address>`,
+/// `d <rule|address>`, `l`ist breakpoints, `q`uit and `h`elp.
+pub struct Debugger<R: BufRead, W: Write> {
+    input: R,
+    out: W,
+    symbols: SymbolTable,
+    breakpoints: BTreeSet<Address>,
+    mode: DebuggerMode,
+}
+
+impl<R: BufRead, W: Write> Debugger<R, W> {
+    pub fn new(input: R, out: W, symbols: SymbolTable) -> Debugger<R, W> {
+        Debugger {
+            input,
+            out,
+            symbols,
+            breakpoints: BTreeSet::new(),
+            mode: DebuggerMode::Stepping,
+        }
+    }
+
+    pub fn add_breakpoint(&mut self, target: &str) -> Option<Address> {
+        let address = self.resolve(target)?;
+        self.breakpoints.insert(address);
+        Some(address)
+    }
+
+    pub fn remove_breakpoint(&mut self, target: &str) -> Option<Address> {
+        let address = self.resolve(target)?;
+        self.breakpoints.remove(&address).then_some(address)
+    }
+
+    pub fn run_until_breakpoint(&mut self) {
+        self.mode = DebuggerMode::Running;
+    }
+
+    fn resolve(&self, target: &str) -> Option<Address> {
+        match target.parse::<u32>() {
+            Ok(address) => Some(Address::new(address)),
+            Err(_) => self.symbols.address_of(target),
+        }
+    }
+
+    fn should_stop(&self, step: &Step) -> bool {
+        self.breakpoints.contains(&step.ip) || match self.mode {
+            DebuggerMode::Stepping => true,
+            DebuggerMode::Running => false,
+            DebuggerMode::StepOver(depth) => step.state_depth <= depth,
+        }
+    }
+
+    fn prompt(&mut self, step: &Step) -> io::Result<TraceControl> {
+        if let Some(name) = self.symbols.rule_containing(step.ip) {
+            write!(self.out, "[{}] ", name)?;
+        }
+        write_step(&mut self.out, step, &self.symbols)?;
+        loop {
+            write!(self.out, "(debug) ")?;
+            self.out.flush()?;
+            let mut line = String::new();
+            if self.input.read_line(&mut line)? == 0 {
+                return Ok(TraceControl::Abort);
+            }
+            let mut words = line.split_whitespace();
+            let command = words.next().unwrap_or("s");
+            let argument = words.next();
+            match (command, argument) {
+                ("s", _) | ("step", _) => {
+                    self.mode = DebuggerMode::Stepping;
+                    return Ok(TraceControl::Continue);
+                },
+                ("n", _) | ("next", _) => {
+                    self.mode = DebuggerMode::StepOver(step.state_depth);
+                    return Ok(TraceControl::Continue);
+                },
+                ("c", _) | ("continue", _) => {
+                    self.mode = DebuggerMode::Running;
+                    return Ok(TraceControl::Continue);
+                },
+                ("q", _) | ("quit", _) => return Ok(TraceControl::Abort),
+                ("b", Some(target)) | ("break", Some(target)) => match self.add_breakpoint(target) {
+                    Some(address) => writeln!(self.out, "breakpoint at {}", address)?,
+                    None => writeln!(self.out, "unknown rule {:?}", target)?,
+                },
+                ("d", Some(target)) | ("delete", Some(target)) => match self.remove_breakpoint(target) {
+                    Some(address) => writeln!(self.out, "removed breakpoint at {}", address)?,
+                    None => writeln!(self.out, "no breakpoint at {:?}", target)?,
+                },
+                ("l", _) | ("list", _) => {
+                    for &address in self.breakpoints.iter() {
+                        match self.symbols.name_at(address) {
+                            Some(name) => writeln!(self.out, "{} ({})", address, name)?,
+                            None => writeln!(self.out, "{}", address)?,
+                        }
+                    }
+                },
+                _ => writeln!(self.out, "commands: s(tep), n(ext), c(ontinue), b(reak) <rule|address>, d(elete) <rule|address>, l(ist), q(uit)")?,
+            }
+        }
+    }
+}
+
+impl<R: BufRead, W: Write> Tracer for Debugger<R, W> {
+    fn trace(&mut self, step: &Step) -> TraceControl {
+        if !self.should_stop(step) {
+            return TraceControl::Continue;
+        }
+        self.prompt(step).unwrap_or(TraceControl::Abort)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bytecode::OwnedBytecode;
+    use crate::matcher::{try_match_traced, MatchError};
+    use Instruction::*;
+
+    fn symbols() -> SymbolTable {
+        let mut symbols = SymbolTable::new();
+        symbols.insert(Address::new(0), "Main");
+        symbols.insert(Address::new(6), "Digit");
+        symbols
+    }
+
+    #[test]
+    fn test_print_tracer() {
+        let bytecode = OwnedBytecode::from_instructions(&[Call(Address::new(6)), Return, Range(b'0', b'9'), Return]);
+        let mut out = Vec::new();
+        let result = try_match_traced(&bytecode.as_bytecode(), "7", &mut PrintTracer::new(&mut out, symbols()));
+        assert_eq!(result, Ok(1));
+        let log = String::from_utf8(out).unwrap();
+        assert!(log.contains("call Digit"));
+        assert!(log.contains("  Digit:"));
+        assert!(log.contains("    6 |   rng [0-9]    ; sp=0 S stack=1 captures=0"));
+    }
+
+    #[test]
+    fn test_debugger() {
+        let bytecode = OwnedBytecode::from_instructions(&[Call(Address::new(6)), Return, Range(b'0', b'9'), Return]);
+        let input = "b Digit\nc\nc\n".as_bytes();
+        let mut out = Vec::new();
+        let result = try_match_traced(&bytecode.as_bytecode(), "7", &mut Debugger::new(input, &mut out, symbols()));
+        assert_eq!(result, Ok(1));
+        let log = String::from_utf8(out).unwrap();
+        assert!(log.contains("breakpoint at 6"));
+        assert!(log.contains("[Digit]     6 |   rng [0-9]"));
+
+        let input = "q\n".as_bytes();
+        let mut out = Vec::new();
+        let result = try_match_traced(&bytecode.as_bytecode(), "7", &mut Debugger::new(input, &mut out, symbols()));
+        assert_eq!(result, Err(MatchError::Aborted));
+    }
+}
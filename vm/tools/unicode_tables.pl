@@ -0,0 +1,89 @@
+#!/usr/bin/env perl
+# Generates vm/src/grammar/unicode_tables.rs from the Unicode Character
+# Database bundled with Perl, so no data files need to be downloaded.
+#
+#   perl vm/tools/unicode_tables.pl > vm/src/grammar/unicode_tables.rs
+use strict;
+use warnings;
+use Unicode::UCD qw(all_casefolds prop_invmap prop_value_aliases);
+
+# Ranges with a value other than the default, as [start, end, value]
+sub ranges {
+    my ($property) = @_;
+    my ($list, $map, $format, $default) = prop_invmap($property);
+    die "unexpected format $format for $property" unless $format eq 's';
+    my @ranges;
+    for my $i (0 .. $#$list) {
+        next if $map->[$i] eq $default;
+        my $end = $i < $#$list ? $list->[$i + 1] - 1 : 0x10FFFF;
+        push @ranges, [$list->[$i], $end, $map->[$i]];
+    }
+    return @ranges;
+}
+
+# Simple case folding, as [code, folded] sorted by code
+sub case_folding {
+    my $casefolds = all_casefolds();
+    my @folds;
+    for my $fold (values %$casefolds) {
+        my $simple = $fold->{simple};
+        push @folds, [hex($fold->{code}), hex($simple)] if $simple ne '';
+    }
+    return sort { $a->[0] <=> $b->[0] } @folds;
+}
+
+sub write_entries {
+    my ($name, $type, @entries) = @_;
+    print "pub const $name: &[$type] = &[\n";
+    my $line = '   ';
+    for my $entry (@entries) {
+        if (length($line) + length($entry) + 2 > 100) {
+            print "$line\n";
+            $line = '   ';
+        }
+        $line .= " $entry,";
+    }
+    print "$line\n" if $line ne '   ';
+    print "];\n";
+}
+
+sub write_table {
+    my ($name, $type, $prefix, @ranges) = @_;
+    my @entries = map { sprintf("(0x%X, 0x%X, %s%s)", $_->[0], $_->[1], $prefix, $_->[2] =~ s/_//gr) } @ranges;
+    write_entries($name, "(u32, u32, $type)", @entries);
+}
+
+my @categories = ranges('General_Category');
+my @scripts = ranges('Script');
+my %script_names = map { $_->[2] => 1 } @scripts;
+my @script_names = ('Unknown', sort grep { $_ ne 'Unknown' } keys %script_names);
+die "too many scripts to encode in a byte" if @script_names > 256;
+
+print "// Generated by vm/tools/unicode_tables.pl from Unicode ", Unicode::UCD::UnicodeVersion(), ", do not edit.\n";
+print "\n";
+print "use super::unicode::GeneralCategory::{self, *};\n";
+print "\n";
+print "pub const UNICODE_VERSION: &str = \"", Unicode::UCD::UnicodeVersion(), "\";\n";
+print "\n";
+print "#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]\n";
+print "pub enum Script {\n";
+print "    ", $_ =~ s/_//gr, ",\n" for @script_names;
+print "}\n";
+print "\n";
+print "pub const SCRIPTS: [Script; ", scalar(@script_names), "] = [\n";
+print "    Script::", $_ =~ s/_//gr, ",\n" for @script_names;
+print "];\n";
+print "\n";
+print "/// Long and short name of each script, in the same order as `SCRIPTS`\n";
+print "pub const SCRIPT_NAMES: [(&str, &str); ", scalar(@script_names), "] = [\n";
+for my $name (@script_names) {
+    my ($short) = prop_value_aliases('Script', $name);
+    print "    (\"$name\", \"$short\"),\n";
+}
+print "];\n";
+print "\n";
+write_table('GENERAL_CATEGORY_TABLE', 'GeneralCategory', '', @categories);
+print "\n";
+write_table('SCRIPT_TABLE', 'Script', 'Script::', @scripts);
+print "\n";
+write_entries('CASE_FOLDING_TABLE', '(u32, u32)', map { sprintf("(0x%X, 0x%X)", @$_) } case_folding());